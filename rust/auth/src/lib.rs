@@ -1,4 +1,8 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use metrics_utils::prometheus::{
+    register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec,
+};
+use once_cell::sync::Lazy;
 use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
@@ -11,6 +15,8 @@ use serde::{Deserialize, Serialize};
 use auth_models::*;
 use shared_models::*;
 
+pub use auth_models::{decode_token, encode_token};
+
 /// Errors that can occur when using Auth
 #[derive(Error, Debug)]
 pub enum AuthError {
@@ -51,25 +57,37 @@ impl TypedErr for AuthError {
     }
 }
 
-/// Encode the claims with a secret into a JWT, using the JWT default settings
-pub fn encode_token(claims: &Claims, secret: &str) -> String {
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
+/// Login attempts, by outcome
+static LOGINS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("auth_logins_total", "Total login attempts", &["outcome"])
+        .expect("Failed to register auth_logins_total")
+});
+
+/// Refresh attempts, by outcome
+static REFRESHES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auth_refreshes_total",
+        "Total token refresh attempts",
+        &["outcome"]
     )
-    .expect("failed to encode token")
-}
-
-/// Decode a JWT into a Claims struct by passing a secret, using the default JWT validation settings
-pub fn decode_token(token: &str, secret: &str) -> Result<Claims, AuthError> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
+    .expect("Failed to register auth_refreshes_total")
+});
+
+/// Rotated refresh tokens that were used again, invalidating their token family
+static REFRESH_TOKEN_REUSE_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "auth_refresh_token_reuse_total",
+        "Total reuse detections of rotated refresh tokens"
     )
-    .map_err(|_| AuthError::InvalidToken)?;
-    Ok(token_data.claims)
+    .expect("Failed to register auth_refresh_token_reuse_total")
+});
+
+/// The outcome label of an operation, which is either `success` or the type of the error
+fn outcome<T>(result: &Result<T, AuthError>) -> String {
+    match result {
+        Ok(_) => "success".to_string(),
+        Err(err) => err.error_type(),
+    }
 }
 
 /// Configuration for Auth
@@ -123,6 +141,19 @@ impl Auth {
         })
     }
 
+    /// Checks that the database can be reached by running a trivial query
+    pub async fn ping(&self) -> Result<(), AuthError> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        Ok(())
+    }
+
+    /// Closes the connections to the database, waiting for the ones in use to be released
+    ///
+    /// Every clone shares the same pool, so none can query the database afterwards
+    pub async fn close(&self) {
+        self.db.close().await;
+    }
+
     /// Retreives user information from a token
     ///
     /// If the token is invalid, a [AuthError::InvalidToken] is returned. Please see
@@ -131,7 +162,7 @@ impl Auth {
     /// # Arguments
     /// * `token` - The token to retrieve user information from
     pub async fn get_user(&self, token: &str) -> Result<User, AuthError> {
-        let token_data = decode_token(token, &self.secret)?;
+        let token_data = decode_token(token, &self.secret).map_err(|_| AuthError::InvalidToken)?;
 
         if token_data.token_type != TokenType::Access {
             return Err(AuthError::InvalidToken);
@@ -171,6 +202,12 @@ impl Auth {
     /// * `password` - The password of the user
     ///
     pub async fn login(&self, email: &str, password: &str) -> Result<TokenPair, AuthError> {
+        let result = self.try_login(email, password).await;
+        LOGINS_TOTAL.with_label_values(&[&outcome(&result)]).inc();
+        result
+    }
+
+    async fn try_login(&self, email: &str, password: &str) -> Result<TokenPair, AuthError> {
         let query_result = sqlx::query(
             "SELECT 
                 email,
@@ -225,6 +262,14 @@ impl Auth {
     /// # Arguments
     /// * `refresh_token` - The refresh token to use
     pub async fn refresh(&self, refresh_token: &str) -> Result<TokenPair, AuthError> {
+        let result = self.try_refresh(refresh_token).await;
+        REFRESHES_TOTAL
+            .with_label_values(&[&outcome(&result)])
+            .inc();
+        result
+    }
+
+    async fn try_refresh(&self, refresh_token: &str) -> Result<TokenPair, AuthError> {
         let val = Validation::default();
 
         let token_data = decode::<Claims>(
@@ -259,6 +304,7 @@ impl Auth {
             .map_err(|_| AuthError::InvalidToken)?;
 
         if most_recent_token_string != refresh_token {
+            REFRESH_TOKEN_REUSE_TOTAL.inc();
             sqlx::query("DELETE FROM refresh_token WHERE root_token = $1")
                 .bind(&root_token)
                 .execute(&self.db)
@@ -1,33 +1,40 @@
 use auth_models::*;
 use auth_service_models::*;
-use reqwest::Client;
 use reqwest_utils::*;
 use shared_models::*;
+use std::time::Duration;
 
-pub async fn get_user(endpoint: &str, access_token: &str) -> Result<User, ClientHttpResponseError> {
-    let client = Client::new();
+fn get_user_request(endpoint: &str, access_token: &str) -> ServiceRequest {
+    ServiceRequest::get(format!("{}/user", endpoint)).bearer_auth(access_token)
+}
+
+fn refresh_token_request(endpoint: &str, refresh_token: &str) -> ServiceRequest {
+    ServiceRequest::get(format!("{}/token", endpoint)).bearer_auth(refresh_token)
+}
+
+fn login_request(
+    endpoint: &str,
+    request: &LoginRequest,
+) -> Result<ServiceRequest, ClientHttpResponseError> {
+    ServiceRequest::post(format!("{}/token", endpoint)).json(request)
+}
 
-    let res = client
-        .get(&format!("{}/user", endpoint))
-        .bearer_auth(access_token)
-        .send()
-        .await;
+/// Checks that the Auth Service is reachable and live
+pub async fn ping(endpoint: &str, timeout: Duration) -> Result<(), ClientHttpResponseError> {
+    reqwest_utils::ping(ServiceRequest::get(format!("{}/livez", endpoint)).timeout(timeout)).await
+}
 
-    handle_res::<GetUserResponse>(res).await.map(|res| res.user)
+pub async fn get_user(endpoint: &str, access_token: &str) -> Result<User, ClientHttpResponseError> {
+    send::<GetUserResponse>(get_user_request(endpoint, access_token))
+        .await
+        .map(|res| res.user)
 }
 
 pub async fn refresh_token(
     endpoint: &str,
     refresh_token: &str,
 ) -> Result<TokenPair, ClientHttpResponseError> {
-    let client = Client::new();
-    let res = client
-        .get(&format!("{}/token", endpoint))
-        .bearer_auth(refresh_token)
-        .send()
-        .await;
-
-    handle_res::<TokenPairResponse>(res)
+    send::<TokenPairResponse>(refresh_token_request(endpoint, refresh_token))
         .await
         .map(|res| res.token_pair)
 }
@@ -36,14 +43,33 @@ pub async fn login(
     endpoint: &str,
     request: &LoginRequest,
 ) -> Result<TokenPair, ClientHttpResponseError> {
-    let client = Client::new();
-    let res = client
-        .post(&format!("{}/token", endpoint))
-        .json(request)
-        .send()
-        .await;
-
-    handle_res::<TokenPairResponse>(res)
+    send::<TokenPairResponse>(login_request(endpoint, request)?)
         .await
         .map(|res| res.token_pair)
 }
+
+/// Synchronous variants of the Auth Service client, enabled with the `blocking` feature
+#[cfg(feature = "blocking")]
+pub mod blocking {
+    use super::*;
+    use reqwest_utils::blocking::send;
+
+    pub fn get_user(endpoint: &str, access_token: &str) -> Result<User, ClientHttpResponseError> {
+        send::<GetUserResponse>(get_user_request(endpoint, access_token)).map(|res| res.user)
+    }
+
+    pub fn refresh_token(
+        endpoint: &str,
+        refresh_token: &str,
+    ) -> Result<TokenPair, ClientHttpResponseError> {
+        send::<TokenPairResponse>(refresh_token_request(endpoint, refresh_token))
+            .map(|res| res.token_pair)
+    }
+
+    pub fn login(
+        endpoint: &str,
+        request: &LoginRequest,
+    ) -> Result<TokenPair, ClientHttpResponseError> {
+        send::<TokenPairResponse>(login_request(endpoint, request)?).map(|res| res.token_pair)
+    }
+}
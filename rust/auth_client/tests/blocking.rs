@@ -0,0 +1,60 @@
+#![cfg(feature = "blocking")]
+
+use auth_client::blocking;
+use auth_models::*;
+use auth_service_mock::*;
+use auth_service_models::*;
+use shared_models::*;
+
+const EMAIL: &str = "admin@pastureen.com";
+const PASSWORD: &str = "password";
+
+async fn start_server() -> MockAuthServer {
+    let server = MockAuthServer::start().await;
+    server.add_user(
+        User {
+            fname: "fname".to_string(),
+            lname: "lname".to_string(),
+            email: EMAIL.to_string(),
+        },
+        PASSWORD,
+    );
+    server
+}
+
+// The blocking client runs its own runtime, so it is called off the runtime of the mock server
+#[tokio::test(flavor = "multi_thread")]
+async fn test_blocking_round_trip() {
+    let server = start_server().await;
+    let url = server.url().to_string();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let request = LoginRequest {
+            email: EMAIL.to_string(),
+            password: PASSWORD.to_string(),
+        };
+        let token_pair = blocking::login(&url, &request)?;
+        let user = blocking::get_user(&url, &token_pair.access_token)?;
+        let refreshed = blocking::refresh_token(&url, &token_pair.refresh_token)?;
+        let invalid = blocking::get_user(&url, "invalid");
+        let malformed = blocking::get_user(&url, "in\nvalid");
+        Ok::<_, ClientHttpResponseError>((user, refreshed, invalid, malformed))
+    })
+    .await
+    .unwrap();
+
+    let (user, refreshed, invalid, malformed) = result.unwrap();
+    assert_eq!(user.email, EMAIL);
+    assert!(!refreshed.access_token.is_empty());
+    match invalid {
+        Err(ClientHttpResponseError::TypedServiceErr(body)) => {
+            assert_eq!(body.error_type, "InvalidToken")
+        }
+        other => panic!("Expected InvalidToken error, got {:?}", other),
+    }
+    assert!(
+        matches!(malformed, Err(ClientHttpResponseError::RawErr(ref err)) if err == "Invalid bearer token"),
+        "{:?}",
+        malformed
+    );
+}
@@ -1,64 +1,160 @@
-use std::env;
+use std::time::Duration;
 
 use auth_client::*;
 use auth_models::*;
+use auth_service_mock::*;
 use auth_service_models::*;
 use shared_models::*;
 
-struct TestConfig {
-    pub email: String,
-    pub password: String,
-    pub url: String,
-}
+const EMAIL: &str = "admin@pastureen.com";
+const PASSWORD: &str = "password";
 
-impl TestConfig {
-    pub fn from_env() -> Self {
-        let email = env::var("ADMIN_EMAIL").unwrap();
-        let password = env::var("ADMIN_PASSWORD").unwrap();
-        let url = env::var("AUTH_SERVICE_URL").unwrap();
+async fn start_server() -> MockAuthServer {
+    let server = MockAuthServer::start().await;
+    server.add_user(
+        User {
+            fname: "fname".to_string(),
+            lname: "lname".to_string(),
+            email: EMAIL.to_string(),
+        },
+        PASSWORD,
+    );
+    server
+}
 
-        Self {
-            email,
-            password,
-            url,
-        }
+fn login_request(password: &str) -> LoginRequest {
+    LoginRequest {
+        email: EMAIL.to_string(),
+        password: password.to_string(),
     }
 }
 
-async fn login_user() -> Result<(TestConfig, TokenPair), ClientHttpResponseError> {
-    let config = TestConfig::from_env();
-
-    let login_request = LoginRequest {
-        email: config.email.clone(),
-        password: config.password.clone(),
-    };
-
-    let login_response = login(&config.url, &login_request).await?;
-
-    Ok((config, login_response))
+fn assert_error_type(
+    result: Result<impl std::fmt::Debug, ClientHttpResponseError>,
+    expected: &str,
+) {
+    match result {
+        Err(ClientHttpResponseError::TypedServiceErr(body)) => {
+            assert_eq!(body.error_type, expected)
+        }
+        other => panic!("Expected {} error, got {:?}", expected, other),
+    }
 }
 
 #[tokio::test]
 async fn test_login() {
-    let (_, token_pair) = login_user().await.unwrap();
+    let server = start_server().await;
+    let token_pair = login(server.url(), &login_request(PASSWORD)).await.unwrap();
     assert!(token_pair.access_token != token_pair.refresh_token);
 }
 
 #[tokio::test]
 async fn test_get_user() {
-    let (config, token_pair) = login_user().await.unwrap();
-    let user = get_user(&config.url, &token_pair.access_token).await.unwrap();
-    assert_eq!(user.email, config.email);
+    let server = start_server().await;
+    let token_pair = login(server.url(), &login_request(PASSWORD)).await.unwrap();
+
+    let user = get_user(server.url(), &token_pair.access_token)
+        .await
+        .unwrap();
+    assert_eq!(user.email, EMAIL);
+
+    // Refresh tokens can't be used as access tokens
+    assert_error_type(
+        get_user(server.url(), &token_pair.refresh_token).await,
+        "InvalidToken",
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_bearer_token() {
+    let server = start_server().await;
+    // Sent without the token the request would fail as unauthenticated instead
+    let result = get_user(server.url(), "in\nvalid").await;
+    assert!(
+        matches!(result, Err(ClientHttpResponseError::RawErr(ref err)) if err == "Invalid bearer token"),
+        "{:?}",
+        result
+    );
 }
 
 #[tokio::test]
 async fn test_refresh_token() {
-    let (config, token_pair) = login_user().await.unwrap();
-    let new_token_pair = refresh_token(&config.url, &token_pair.refresh_token)
+    let server = start_server().await;
+    let token_pair = login(server.url(), &login_request(PASSWORD)).await.unwrap();
+
+    let new_token_pair = refresh_token(server.url(), &token_pair.refresh_token)
         .await
         .unwrap();
     assert!(new_token_pair.access_token != new_token_pair.refresh_token);
     assert!(new_token_pair.access_token != token_pair.access_token);
 }
 
+#[tokio::test]
+async fn test_ping() {
+    let server = start_server().await;
+    ping(server.url(), Duration::from_secs(1)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_invalid_credentials() {
+    let server = start_server().await;
+    assert_error_type(
+        login(server.url(), &login_request("wrong")).await,
+        "InvalidCredentials",
+    );
+}
+
+#[tokio::test]
+async fn test_refresh_token_reuse() {
+    let server = start_server().await;
+    let token_pair = login(server.url(), &login_request(PASSWORD)).await.unwrap();
+
+    let new_token_pair = refresh_token(server.url(), &token_pair.refresh_token)
+        .await
+        .unwrap();
+    assert!(new_token_pair.access_token != token_pair.access_token);
+
+    // Reusing the rotated token invalidates the whole family
+    assert_error_type(
+        refresh_token(server.url(), &token_pair.refresh_token).await,
+        "InvalidToken",
+    );
+    assert_error_type(
+        refresh_token(server.url(), &new_token_pair.refresh_token).await,
+        "InvalidToken",
+    );
+}
+
+#[tokio::test]
+async fn test_expired_token() {
+    let server = start_server().await;
+    server.set_access_token_ttl(Duration::ZERO);
 
+    let token_pair = login(server.url(), &login_request(PASSWORD)).await.unwrap();
+    assert_error_type(
+        get_user(server.url(), &token_pair.access_token).await,
+        "InvalidToken",
+    );
+}
+
+#[tokio::test]
+async fn test_injected_errors() {
+    let server = start_server().await;
+    let token_pair = server.issue_token_pair(EMAIL);
+
+    server.inject_error(MockEndpoint::GetUser, MockAuthError::InvalidToken);
+    assert_error_type(
+        get_user(server.url(), &token_pair.access_token).await,
+        "InvalidToken",
+    );
+    // Injected errors are only returned once
+    assert!(get_user(server.url(), &token_pair.access_token)
+        .await
+        .is_ok());
+
+    server.inject_error(MockEndpoint::Login, MockAuthError::InvalidCredentials);
+    assert_error_type(
+        login(server.url(), &login_request(PASSWORD)).await,
+        "InvalidCredentials",
+    );
+}
@@ -1,3 +1,4 @@
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 
 
@@ -48,3 +49,27 @@ pub struct TokenPair {
 }
 
 
+// TOKENS
+
+/// Encode the claims with a secret into a JWT, using the JWT default settings
+pub fn encode_token(claims: &Claims, secret: &str) -> String {
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .expect("failed to encode token")
+}
+
+/// Decode a JWT into a Claims struct by passing a secret, using the default JWT validation settings
+///
+/// Kept here rather than in the auth crate so services verifying tokens don't depend on its
+/// database
+pub fn decode_token(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let token_data = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )?;
+    Ok(token_data.claims)
+}
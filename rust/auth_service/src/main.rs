@@ -3,14 +3,18 @@ use actix_web::{
     get,
     http::StatusCode,
     post,
+    rt::time::timeout,
     web::{scope, Data, Json},
     App, HttpRequest, HttpResponse, HttpServer, Responder,
 };
 use shared_models::*;
+use shutdown_utils::Shutdown;
+use std::time::{Duration, Instant};
 
 use auth::*;
 use auth_service_models::*;
 use thiserror::Error;
+use tracing_utils::tracing::{error, info, warn};
 
 #[derive(Error, Debug)]
 pub enum AuthWebServiceError {
@@ -40,7 +44,13 @@ impl TypedErr for &AuthWebServiceError {
 
 impl ResponseError for AuthWebServiceError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(HttpErrResponseBody::from(self))
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!(error = %self, "Request failed");
+        } else {
+            warn!(error = %self, "Request rejected");
+        }
+        HttpResponse::build(status).json(HttpErrResponseBody::from(self))
     }
 
     fn status_code(&self) -> actix_web::http::StatusCode {
@@ -77,11 +87,47 @@ impl AuthWebServiceConfiguration {
     }
 }
 
+/// How long the database has to respond before the service is reported as not ready
+const READINESS_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[get("/")]
 async fn health_check() -> impl Responder {
     HttpResponse::Ok().body("Health check ok")
 }
 
+#[get("/metrics")]
+async fn metrics() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type(metrics_utils::METRICS_CONTENT_TYPE)
+        .body(metrics_utils::encode_metrics())
+}
+
+#[get("/livez")]
+async fn livez() -> Json<LivenessResponse> {
+    Json(LivenessResponse::new(build_info!()))
+}
+
+#[get("/readyz")]
+async fn readyz(api: Data<Auth>, shutdown: Data<Shutdown>) -> HttpResponse {
+    let started = Instant::now();
+    let result = match timeout(READINESS_CHECK_TIMEOUT, api.ping()).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(_) => Err("Timed out".to_string()),
+    };
+
+    let mut checks = vec![DependencyCheck::from_result("database", started, result)];
+    if shutdown.is_draining() {
+        checks.push(DependencyCheck::draining());
+    }
+    let response = ReadinessResponse::new(build_info!(), checks);
+
+    if response.is_ready() {
+        HttpResponse::Ok().json(response)
+    } else {
+        HttpResponse::ServiceUnavailable().json(response)
+    }
+}
+
 fn get_token_from_header(req: &HttpRequest) -> Result<String, AuthWebServiceError> {
     let auth_header = req
         .headers()
@@ -91,11 +137,10 @@ fn get_token_from_header(req: &HttpRequest) -> Result<String, AuthWebServiceErro
         .map_err(|_| AuthWebServiceError::MissingToken)?
         .to_string();
 
-    if auth_header.starts_with("Bearer ") {
-        Ok(auth_header[7..].to_string())
-    } else {
-        Err(AuthWebServiceError::MissingToken)
-    }
+    auth_header
+        .strip_prefix("Bearer ")
+        .map(|token| token.to_string())
+        .ok_or(AuthWebServiceError::MissingToken)
 }
 
 #[get("")]
@@ -131,20 +176,47 @@ type StdError = Box<dyn std::error::Error + Send + Sync>;
 
 #[actix_web::main]
 async fn main() -> Result<(), StdError> {
-    let api = Auth::from_env().await?;
-
-    HttpServer::new(move || {
+    tracing_utils::init();
+
+    let api = Auth::from_env().await.inspect_err(|err| {
+        error!(error = %err, "Failed to create Auth from environment variables");
+    })?;
+    let config = AuthWebServiceConfiguration::from_env()?;
+    let shutdown = Shutdown::on_signals()?;
+    info!(listen_address = %config.listen_address, "Listening");
+
+    let app_api = api.clone();
+    let app_shutdown = shutdown.clone();
+    let server = HttpServer::new(move || {
         let user_resource = scope("/user").service(get_user);
         let token_resource = scope("/token").service(refresh_token).service(login);
         App::new()
+            .wrap_fn(metrics_utils::actix::track_metrics)
+            .wrap_fn(tracing_utils::actix::request_context)
             .service(health_check)
+            .service(livez)
+            .service(readyz)
+            .service(metrics)
             .service(user_resource)
             .service(token_resource)
-            .app_data(Data::new(api.clone()))
+            .app_data(Data::new(app_api.clone()))
+            .app_data(Data::new(app_shutdown.clone()))
     })
-    .bind(AuthWebServiceConfiguration::from_env()?.listen_address)?
-    .run()
-    .await?;
-
+    // Signals are handled by `Shutdown`, so readiness fails while requests drain
+    .disable_signals()
+    .shutdown_timeout(shutdown.timeout().as_secs())
+    .bind(config.listen_address)?
+    .run();
+
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown.started().await;
+        // Stops accepting connections and waits for requests in flight, up to the timeout
+        handle.stop(true).await;
+    });
+    server.await?;
+
+    api.close().await;
+    info!("Stopped");
     Ok(())
 }
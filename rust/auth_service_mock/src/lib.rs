@@ -0,0 +1,354 @@
+use axum::{
+    extract::{Json, State, TypedHeader},
+    headers::authorization::{Authorization, Bearer},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router, Server,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use auth_models::*;
+use auth_service_models::*;
+use shared_models::*;
+
+// ERRORS
+
+/// Errors the mock can be scripted to return, mirroring the errors of the Auth Service
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MockAuthError {
+    /// Returned with a 401, as the Auth Service does for bad, expired or reused tokens
+    InvalidToken,
+    /// Returned with a 400, as the Auth Service does for a failed login
+    InvalidCredentials,
+    /// Returned with a 401 when the authorization header is missing
+    MissingToken,
+}
+
+impl TypedErr for MockAuthError {
+    fn error_type(&self) -> String {
+        match self {
+            Self::InvalidToken => "InvalidToken".to_string(),
+            Self::InvalidCredentials => "InvalidCredentials".to_string(),
+            Self::MissingToken => "MissingToken".to_string(),
+        }
+    }
+}
+
+impl MockAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidToken | Self::MissingToken => StatusCode::UNAUTHORIZED,
+            Self::InvalidCredentials => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl IntoResponse for MockAuthError {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(HttpErrResponseBody::from(self))).into_response()
+    }
+}
+
+/// The endpoints of the Auth Service contract, used to target injected errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockEndpoint {
+    /// `GET /user`
+    GetUser,
+    /// `POST /token`
+    Login,
+    /// `GET /token`
+    RefreshToken,
+}
+
+// STATE
+
+struct MockUser {
+    user: User,
+    password: String,
+}
+
+struct MockState {
+    secret: String,
+    access_token_ttl: Duration,
+    refresh_token_ttl: Duration,
+    users: HashMap<String, MockUser>,
+    /// Refresh token to the root token of its rotation family
+    refresh_tokens: HashMap<String, String>,
+    /// Root token to the most recently issued refresh token of the family
+    latest_refresh_tokens: HashMap<String, String>,
+    injected_errors: HashMap<MockEndpoint, VecDeque<MockAuthError>>,
+}
+
+impl MockState {
+    fn take_injected_error(&mut self, endpoint: MockEndpoint) -> Result<(), MockAuthError> {
+        match self
+            .injected_errors
+            .get_mut(&endpoint)
+            .and_then(|errors| errors.pop_front())
+        {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn create_token(&self, email: &str, token_type: TokenType) -> String {
+        let now = get_epoch();
+        let ttl = match token_type {
+            TokenType::Access => self.access_token_ttl,
+            TokenType::Refresh => self.refresh_token_ttl,
+        };
+        let claims = Claims {
+            sub: email.to_string(),
+            exp: now + ttl.as_secs(),
+            iat: now,
+            token_type,
+            id: Uuid::new_v4().to_string(),
+        };
+        encode_token(&claims, &self.secret)
+    }
+
+    /// Decodes a token, treating it as expired once its `exp` has been reached
+    ///
+    /// The JWT validation used by the Auth Service allows some leeway, which is checked here
+    /// without leeway so that a zero TTL produces tokens that are immediately expired
+    fn decode(&self, token: &str, token_type: TokenType) -> Result<Claims, MockAuthError> {
+        let claims = decode_token(token, &self.secret).map_err(|_| MockAuthError::InvalidToken)?;
+        if claims.token_type != token_type || claims.exp <= get_epoch() {
+            return Err(MockAuthError::InvalidToken);
+        }
+        Ok(claims)
+    }
+
+    fn issue_token_pair(&mut self, email: &str, root_token: Option<String>) -> TokenPair {
+        let access_token = self.create_token(email, TokenType::Access);
+        let refresh_token = self.create_token(email, TokenType::Refresh);
+        let root_token = root_token.unwrap_or_else(|| refresh_token.clone());
+
+        self.refresh_tokens
+            .insert(refresh_token.clone(), root_token.clone());
+        self.latest_refresh_tokens
+            .insert(root_token, refresh_token.clone());
+
+        TokenPair {
+            access_token,
+            refresh_token,
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+// SERVER
+
+/// An in-process fake of the Auth Service
+///
+/// It speaks the same `/user` and `/token` contract as `auth_service`, so clients can be tested
+/// without a deployed service or database. Users, token lifetimes and errors are scripted through
+/// the methods on this struct. The server is shut down when it is dropped.
+pub struct MockAuthServer {
+    url: String,
+    state: SharedState,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockAuthServer {
+    /// Starts the mock on a random local port
+    ///
+    /// Must be called from within a tokio runtime
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock auth server");
+        listener
+            .set_nonblocking(true)
+            .expect("Failed to set mock auth server listener to non blocking");
+        let addr: SocketAddr = listener
+            .local_addr()
+            .expect("Failed to get mock auth server address");
+
+        let state = Arc::new(Mutex::new(MockState {
+            secret: Uuid::new_v4().to_string(),
+            access_token_ttl: Duration::from_secs(60 * 10),
+            refresh_token_ttl: Duration::from_secs(60 * 60 * 24 * 30),
+            users: HashMap::new(),
+            refresh_tokens: HashMap::new(),
+            latest_refresh_tokens: HashMap::new(),
+            injected_errors: HashMap::new(),
+        }));
+
+        let app = Router::new()
+            .route("/", get(health_check))
+            .route("/livez", get(livez))
+            .route("/user", get(get_user))
+            .route("/token", get(refresh_token).post(login))
+            .with_state(state.clone());
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .expect("Failed to start mock auth server")
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async {
+                shutdown_rx.await.ok();
+            });
+        tokio::spawn(server);
+
+        Self {
+            url: format!("http://{}", addr),
+            state,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// The base url of the mock, to be used in place of `AUTH_SERVICE_URL`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The secret the mock signs its tokens with
+    pub fn secret(&self) -> String {
+        self.lock().secret.clone()
+    }
+
+    /// Adds a user which can log in with the provided password
+    pub fn add_user(&self, user: User, password: &str) {
+        self.lock().users.insert(
+            user.email.clone(),
+            MockUser {
+                user,
+                password: password.to_string(),
+            },
+        );
+    }
+
+    /// Sets how long newly issued access tokens are valid for
+    pub fn set_access_token_ttl(&self, ttl: Duration) {
+        self.lock().access_token_ttl = ttl;
+    }
+
+    /// Sets how long newly issued refresh tokens are valid for
+    pub fn set_refresh_token_ttl(&self, ttl: Duration) {
+        self.lock().refresh_token_ttl = ttl;
+    }
+
+    /// Makes the next request to an endpoint fail with the provided error
+    ///
+    /// Errors injected for the same endpoint are returned in the order they were injected
+    pub fn inject_error(&self, endpoint: MockEndpoint, error: MockAuthError) {
+        self.lock()
+            .injected_errors
+            .entry(endpoint)
+            .or_default()
+            .push_back(error);
+    }
+
+    /// Issues a token pair for a user without going through the login endpoint
+    pub fn issue_token_pair(&self, email: &str) -> TokenPair {
+        self.lock().issue_token_pair(email, None)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().expect("Mock auth server state poisoned")
+    }
+}
+
+impl Drop for MockAuthServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+// HANDLERS
+
+async fn health_check() -> &'static str {
+    "Health check ok"
+}
+
+async fn livez() -> Json<LivenessResponse> {
+    Json(LivenessResponse::new(build_info!()))
+}
+
+async fn get_user(
+    State(state): State<SharedState>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<GetUserResponse>, MockAuthError> {
+    let mut state = state.lock().expect("Mock auth server state poisoned");
+    state.take_injected_error(MockEndpoint::GetUser)?;
+
+    let TypedHeader(auth) = auth.ok_or(MockAuthError::MissingToken)?;
+    let claims = state.decode(auth.token(), TokenType::Access)?;
+
+    let user = state
+        .users
+        .get(&claims.sub)
+        .ok_or(MockAuthError::InvalidToken)?
+        .user
+        .clone();
+    Ok(Json(GetUserResponse { user }))
+}
+
+async fn login(
+    State(state): State<SharedState>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Json<TokenPairResponse>, MockAuthError> {
+    let mut state = state.lock().expect("Mock auth server state poisoned");
+    state.take_injected_error(MockEndpoint::Login)?;
+
+    let user = state
+        .users
+        .get(&req.email)
+        .ok_or(MockAuthError::InvalidCredentials)?;
+    if user.password != req.password {
+        return Err(MockAuthError::InvalidCredentials);
+    }
+
+    let token_pair = state.issue_token_pair(&req.email, None);
+    Ok(Json(TokenPairResponse { token_pair }))
+}
+
+async fn refresh_token(
+    State(state): State<SharedState>,
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Json<TokenPairResponse>, MockAuthError> {
+    let mut state = state.lock().expect("Mock auth server state poisoned");
+    state.take_injected_error(MockEndpoint::RefreshToken)?;
+
+    let TypedHeader(auth) = auth.ok_or(MockAuthError::MissingToken)?;
+    let token = auth.token();
+    let claims = state.decode(token, TokenType::Refresh)?;
+
+    let root_token = state
+        .refresh_tokens
+        .get(token)
+        .cloned()
+        .ok_or(MockAuthError::InvalidToken)?;
+
+    // Same as the Auth Service, reusing a rotated refresh token invalidates the whole family
+    if state
+        .latest_refresh_tokens
+        .get(&root_token)
+        .map(String::as_str)
+        != Some(token)
+    {
+        state.refresh_tokens.retain(|_, root| *root != root_token);
+        state.latest_refresh_tokens.remove(&root_token);
+        return Err(MockAuthError::InvalidToken);
+    }
+
+    let token_pair = state.issue_token_pair(&claims.sub, Some(root_token));
+    Ok(Json(TokenPairResponse { token_pair }))
+}
+
+fn get_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
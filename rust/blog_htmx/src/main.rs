@@ -1,6 +1,7 @@
 use axum::{
     extract::{Form, Json, Query, State},
-    http::{HeaderValue, StatusCode, Uri},
+    http::{header, HeaderValue, StatusCode, Uri},
+    middleware,
     response::{Html, IntoResponse},
     routing::{get, post},
     Router, Server,
@@ -8,10 +9,19 @@ use axum::{
 
 use blog_htmx::*;
 use shared_models::*;
-use std::{net::SocketAddr, sync::Arc};
+use shutdown_utils::Shutdown;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing_utils::tracing::{error, info};
 
 use tower_http::cors::{Any, CorsLayer, AllowOrigin};
 
+/// How long the librarian has to respond before the service is reported as not ready
+const READINESS_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct JsonErrResponse(pub StatusCode, pub Json<HttpErrResponseBody>);
 impl IntoResponse for JsonErrResponse {
     fn into_response(self) -> axum::response::Response {
@@ -27,12 +37,14 @@ impl From<BlogHtmxError> for JsonErrResponse {
             }
         };
 
+        error!(error = %err, "Request failed");
         JsonErrResponse(status, Json(HttpErrResponseBody::from(err)))
     }
 }
 
 struct BlogHtmxState {
     config: BlogHtmxConfig,
+    shutdown: Shutdown,
 }
 
 fn cors_predicate(headers: &HeaderValue, blog_base_url: &str) -> bool {
@@ -57,11 +69,10 @@ fn cors_predicate(headers: &HeaderValue, blog_base_url: &str) -> bool {
 
 #[tokio::main]
 async fn main() {
+    tracing_utils::init();
+
     let config = BlogHtmxConfig::from_env().unwrap_or_else(|err| {
-        eprintln!(
-            "Failed to get configuration from environment variables: {}",
-            err
-        );
+        error!(error = %err, "Failed to get configuration from environment variables");
         std::process::exit(1);
     });
 
@@ -73,33 +84,48 @@ async fn main() {
         .allow_methods(Any)
         .allow_headers(Any);
 
+    let shutdown = Shutdown::on_signals().unwrap_or_else(|err| {
+        error!(error = %err, "Failed to listen for shutdown signals");
+        std::process::exit(1);
+    });
     let state = Arc::new(BlogHtmxState {
         config: config.clone(),
+        shutdown: shutdown.clone(),
     });
 
     let app = Router::new()
         .route("/healthcheck", get(healthcheck))
+        .route("/livez", get(livez))
+        .route("/readyz", get(readyz))
         .route("/search", post(search_links))
         .route("/links", get(get_next_page_links))
+        .route("/metrics", get(metrics))
+        .layer(middleware::from_fn(metrics_utils::axum::track_metrics))
+        .layer(middleware::from_fn(tracing_utils::axum::request_context))
         .with_state(state)
         .layer(cors);
 
     let socket_addr: SocketAddr = config.listen_address.parse().unwrap_or_else(|err| {
-        eprintln!(
-            "Failed to parse listen address `{}`: {}",
-            config.listen_address, err
+        error!(
+            error = %err,
+            listen_address = %config.listen_address,
+            "Failed to parse listen address"
         );
         std::process::exit(1);
     });
-    println!("Listening on {}", socket_addr);
+    info!(%socket_addr, "Listening");
 
-    Server::bind(&socket_addr)
+    // Stops accepting connections on SIGTERM or SIGINT and lets requests in flight finish
+    let server = Server::bind(&socket_addr)
         .serve(app.into_make_service())
-        .await
-        .unwrap_or_else(|err| {
-            eprintln!("Failed to start server: {}", err);
+        .with_graceful_shutdown(shutdown.started());
+    match shutdown.drain(server).await {
+        Some(Err(err)) => {
+            error!(error = %err, "Failed to start server");
             std::process::exit(1);
-        });
+        }
+        _ => info!("Stopped"),
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -142,3 +168,35 @@ async fn get_next_page_links(
 async fn healthcheck() -> &'static str {
     "OK"
 }
+
+/// Liveness endpoint
+async fn livez() -> Json<LivenessResponse> {
+    Json(LivenessResponse::new(build_info!()))
+}
+
+/// Readiness endpoint, checks that the librarian is reachable
+async fn readyz(State(state): State<Arc<BlogHtmxState>>) -> (StatusCode, Json<ReadinessResponse>) {
+    let started = Instant::now();
+    let result = librarian_client::ping(&state.config.librarian_url, READINESS_CHECK_TIMEOUT).await;
+
+    let mut checks = vec![DependencyCheck::from_result("librarian", started, result)];
+    if state.shutdown.is_draining() {
+        checks.push(DependencyCheck::draining());
+    }
+    let response = ReadinessResponse::new(build_info!(), checks);
+
+    let status = if response.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(response))
+}
+
+/// Prometheus metrics endpoint
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, metrics_utils::METRICS_CONTENT_TYPE)],
+        metrics_utils::encode_metrics(),
+    )
+}
@@ -1,7 +1,7 @@
-use reqwest::Client;
 use reqwest_utils::*;
 use serde::{Deserialize, Serialize};
 use shared_models::*;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -45,15 +45,35 @@ pub struct GetTagsResponse {
     pub tags: Vec<String>,
 }
 
+fn get_tags_request(endpoint: &str) -> ServiceRequest {
+    ServiceRequest::get(format!("{}/tags", endpoint))
+}
+
+fn query_links_request(
+    endpoint: &str,
+    query: &QueryLinksRequest,
+) -> Result<ServiceRequest, ClientHttpResponseError> {
+    ServiceRequest::post(format!("{}/search", endpoint)).json(query)
+}
+
+/// Checks that the Librarian Api is reachable
+///
+/// # Arguments
+/// * `endpoint` - The endpoint of the Librarian Api
+/// * `timeout` - How long to wait for the Librarian Api to respond
+pub async fn ping(endpoint: &str, timeout: Duration) -> Result<(), ClientHttpResponseError> {
+    reqwest_utils::ping(ServiceRequest::get(format!("{}/healthcheck", endpoint)).timeout(timeout))
+        .await
+}
+
 /// Queries the Librarian Api and retireves the list of tags
 ///
 /// # Arguments
 /// * `endpoint` - The endpoint of the Librarian Api
 pub async fn get_tags(endpoint: &str) -> Result<Vec<String>, ClientHttpResponseError> {
-    let client = Client::new();
-
-    let res = client.get(&format!("{}/tags", endpoint)).send().await;
-    handle_res::<GetTagsResponse>(res).await.map(|r| r.tags)
+    send::<GetTagsResponse>(get_tags_request(endpoint))
+        .await
+        .map(|r| r.tags)
 }
 
 /// Searches links in the Librarian Api
@@ -66,13 +86,35 @@ pub async fn query_links(
     endpoint: &str,
     query: QueryLinksRequest,
 ) -> Result<Vec<Link>, ClientHttpResponseError> {
-    let client = Client::new();
+    send::<QueryLinksResponse>(query_links_request(endpoint, &query)?)
+        .await
+        .map(|r| r.links)
+}
+
+/// Synchronous variants of the Librarian Api client, enabled with the `blocking` feature
+#[cfg(feature = "blocking")]
+pub mod blocking {
+    use super::*;
+    use reqwest_utils::blocking::send;
 
-    let res = client
-        .post(&format!("{}/search", endpoint))
-        .json(&query)
-        .send()
-        .await;
+    /// Queries the Librarian Api and retireves the list of tags
+    ///
+    /// # Arguments
+    /// * `endpoint` - The endpoint of the Librarian Api
+    pub fn get_tags(endpoint: &str) -> Result<Vec<String>, ClientHttpResponseError> {
+        send::<GetTagsResponse>(get_tags_request(endpoint)).map(|r| r.tags)
+    }
 
-    handle_res::<QueryLinksResponse>(res).await.map(|r| r.links)
+    /// Searches links in the Librarian Api
+    ///
+    /// # Arguements
+    /// * `endpoint` - The endpoint of the Librarian Api
+    /// * `query` - The query to search for
+    ///
+    pub fn query_links(
+        endpoint: &str,
+        query: QueryLinksRequest,
+    ) -> Result<Vec<Link>, ClientHttpResponseError> {
+        send::<QueryLinksResponse>(query_links_request(endpoint, &query)?).map(|r| r.links)
+    }
 }
@@ -0,0 +1,29 @@
+use crate::observe_request;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    Error,
+};
+use std::{future::Future, time::Instant};
+
+/// Records the count and latency of every request, labelled by the matched route
+///
+/// Added with `App::wrap_fn(metrics_utils::actix::track_metrics)`
+pub fn track_metrics<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let res = srv.call(req);
+    async move {
+        let res = res.await?;
+        observe_request(&method, &route, res.status().as_u16(), started.elapsed());
+        Ok(res)
+    }
+}
@@ -0,0 +1,27 @@
+use crate::observe_request;
+use ::axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use std::time::Instant;
+
+/// Records the count and latency of every request, labelled by the matched route
+///
+/// Added with `axum::middleware::from_fn(metrics_utils::axum::track_metrics)`
+pub async fn track_metrics<B>(
+    matched_path: Option<MatchedPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = matched_path
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+    observe_request(
+        &method,
+        &route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
@@ -0,0 +1,62 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
+};
+use std::time::Duration;
+
+pub use prometheus;
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+
+/// Content type of the Prometheus text exposition format
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Total HTTP requests handled by a service, by method, route and status
+pub static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Total number of HTTP requests handled",
+        &["method", "route", "status"]
+    )
+    .expect("Failed to register http_requests_total")
+});
+
+/// Latency of HTTP requests handled by a service, by method, route and status
+pub static HTTP_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Latency of HTTP requests handled, in seconds",
+        &["method", "route", "status"]
+    )
+    .expect("Failed to register http_request_duration_seconds")
+});
+
+/// Records a handled HTTP request
+///
+/// # Arguments
+/// * `method` - The method of the request
+/// * `route` - The matched route of the request, this should be the route pattern rather than
+///   the raw path to keep the number of label values bounded
+/// * `status` - The status code of the response
+/// * `duration` - How long the request took to handle
+pub fn observe_request(method: &str, route: &str, status: u16, duration: Duration) {
+    let status = status.to_string();
+    let labels = [method, route, status.as_str()];
+    HTTP_REQUESTS_TOTAL.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&labels)
+        .observe(duration.as_secs_f64());
+}
+
+/// Encodes all metrics registered in the default registry in the Prometheus text format
+pub fn encode_metrics() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Failed to encode metrics");
+    String::from_utf8(buffer).expect("Metrics are not valid utf8")
+}
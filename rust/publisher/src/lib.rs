@@ -1,8 +1,10 @@
-use refresh_blog::*;
+use auth_client::get_user;
+use auth_models::User;
 use markdown::{mdast::Node, to_html_with_options, to_mdast, Constructs, Options, ParseOptions};
+use refresh_blog::*;
 use serde::{Deserialize, Serialize};
-use thiserror::Error;
 use shared_models::*;
+use thiserror::Error;
 
 // CONTRACTS
 
@@ -14,6 +16,9 @@ pub struct GeneratePostRequest {
     pub markdown_str: String,
 }
 
+/// Largest [GeneratePostRequest] accepted, larger ones are rejected with a 413
+pub const GENERATE_POST_MAX_BYTES: usize = 1024 * 1024;
+
 /// HTTP response body to generate a post from markdown
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -146,6 +151,34 @@ impl From<PublisherConfig> for BlogConfig {
     }
 }
 
+// AUTH
+
+/// Checks that an access token belongs to the admin, the only user allowed to publish posts
+///
+/// # Arguments
+/// * `auth_url` - URL of the auth service the token is checked with
+/// * `admin_email` - Email of the admin user
+/// * `token` - The access token sent by the client
+pub async fn authorize_admin(
+    auth_url: &str,
+    admin_email: &str,
+    token: &str,
+) -> Result<User, PublisherError> {
+    let user = get_user(auth_url, token).await.map_err(|err| match err {
+        ClientHttpResponseError::RawErr(msg) => PublisherError::AuthCheckRequestFailed(msg),
+        ClientHttpResponseError::TypedServiceErr(body) if body.error_type == "InvalidToken" => {
+            PublisherError::Forbidden
+        }
+        ClientHttpResponseError::TypedServiceErr(body) => {
+            PublisherError::AuthServiceError(format!("{:?}", body))
+        }
+    })?;
+    if user.email != admin_email {
+        return Err(PublisherError::Forbidden);
+    }
+    Ok(user)
+}
+
 // GENERATOR
 
 /// Generates a post from markdown
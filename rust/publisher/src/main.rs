@@ -1,7 +1,7 @@
 use axum::{
-    extract::{Json, State, TypedHeader},
+    extract::{DefaultBodyLimit, Json, State, TypedHeader},
     headers::authorization::{Authorization, Bearer},
-    http::{Request, StatusCode},
+    http::{header, Request, StatusCode},
     middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{post, get},
@@ -9,11 +9,19 @@ use axum::{
 };
 
 use shared_models::*;
+use shutdown_utils::Shutdown;
 
 use auth_client::*;
-use auth_models::*;
 use publisher::*;
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing_utils::tracing::{error, info, warn};
+
+/// How long the auth service has to respond before the publisher is reported as not ready
+const READINESS_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
 
 type JsonHandlerResponse<T> = Result<Json<T>, JsonErrResponse>;
 pub struct JsonErrResponse(pub StatusCode, pub Json<HttpErrResponseBody>);
@@ -36,62 +44,76 @@ impl From<PublisherError> for JsonErrResponse {
             PublisherError::Forbidden => StatusCode::FORBIDDEN,
         };
 
+        if status_code.is_server_error() {
+            error!(error = %err, "Request failed");
+        } else {
+            warn!(error = %err, "Request rejected");
+        }
         JsonErrResponse(status_code, Json(HttpErrResponseBody::from(err)))
     }
 }
 
 struct PublisherState {
     config: PublisherConfig,
+    shutdown: Shutdown,
 }
 
 #[tokio::main]
 async fn main() {
+    tracing_utils::init();
+
     let config = PublisherConfig::from_env().unwrap_or_else(|err| {
-        eprintln!(
-            "Failed to get configuration from environment variables: {}",
-            err
-        );
+        error!(error = %err, "Failed to get configuration from environment variables");
         std::process::exit(1);
     });
 
+    let shutdown = Shutdown::on_signals().unwrap_or_else(|err| {
+        error!(error = %err, "Failed to listen for shutdown signals");
+        std::process::exit(1);
+    });
     let state = Arc::new(PublisherState {
         config: config.clone(),
+        shutdown: shutdown.clone(),
     });
 
     let app = Router::new()
-        .route("/", post(handle))
+        .route(
+            "/",
+            post(handle).layer(DefaultBodyLimit::max(GENERATE_POST_MAX_BYTES)),
+        )
         .route("/healthcheck", get(healthcheck))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
         ))
+        .route("/livez", get(livez))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+        .layer(middleware::from_fn(metrics_utils::axum::track_metrics))
+        .layer(middleware::from_fn(tracing_utils::axum::request_context))
         .with_state(state);
 
     let socket_addr: SocketAddr = config.listen_address.parse().unwrap_or_else(|err| {
-        eprintln!(
-            "Failed to parse listen address `{}`: {}",
-            config.listen_address, err
+        error!(
+            error = %err,
+            listen_address = %config.listen_address,
+            "Failed to parse listen address"
         );
         std::process::exit(1);
     });
-    println!("Listening on {}", socket_addr);
+    info!(%socket_addr, "Listening");
 
-    Server::bind(&socket_addr)
+    // Stops accepting connections on SIGTERM or SIGINT and lets requests in flight finish
+    let server = Server::bind(&socket_addr)
         .serve(app.into_make_service())
-        .await
-        .unwrap_or_else(|err| {
-            eprintln!("Failed to start server: {}", err);
+        .with_graceful_shutdown(shutdown.started());
+    match shutdown.drain(server).await {
+        Some(Err(err)) => {
+            error!(error = %err, "Failed to start server");
             std::process::exit(1);
-        });
-}
-
-async fn get_user_wrapper(endpoint: &str, token: &str) -> Result<User, PublisherError> {
-    get_user(endpoint, token).await.map_err(|err| match err {
-        ClientHttpResponseError::RawErr(msg) => PublisherError::AuthCheckRequestFailed(msg),
-        ClientHttpResponseError::TypedServiceErr(body) => {
-            PublisherError::AuthServiceError(format!("{:?}", body))
         }
-    })
+        _ => info!("Stopped"),
+    }
 }
 
 async fn auth_middleware<B>(
@@ -100,12 +122,7 @@ async fn auth_middleware<B>(
     request: Request<B>,
     next: Next<B>,
 ) -> Result<Response, JsonErrResponse> {
-    let token = auth.token();
-    let endpoint = &state.config.auth_url;
-    let user = get_user_wrapper(endpoint, token).await?;
-    if user.email != state.config.admin_email {
-        return Err(PublisherError::Forbidden.into())
-    }
+    authorize_admin(&state.config.auth_url, &state.config.admin_email, auth.token()).await?;
     Ok(next.run(request).await)
 }
 
@@ -113,6 +130,28 @@ async fn healthcheck() -> &'static str {
     "OK"
 }
 
+async fn livez() -> Json<LivenessResponse> {
+    Json(LivenessResponse::new(build_info!()))
+}
+
+async fn readyz(State(state): State<Arc<PublisherState>>) -> (StatusCode, Json<ReadinessResponse>) {
+    let started = Instant::now();
+    let result = ping(&state.config.auth_url, READINESS_CHECK_TIMEOUT).await;
+
+    let mut checks = vec![DependencyCheck::from_result("authService", started, result)];
+    if state.shutdown.is_draining() {
+        checks.push(DependencyCheck::draining());
+    }
+    let response = ReadinessResponse::new(build_info!(), checks);
+
+    let status = if response.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(response))
+}
+
 async fn handle(
     State(state): State<Arc<PublisherState>>,
     Json(payload): Json<GeneratePostRequest>,
@@ -124,3 +163,11 @@ async fn handle(
         generated_post: generate_result,
     }))
 }
+
+/// Prometheus metrics endpoint
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, metrics_utils::METRICS_CONTENT_TYPE)],
+        metrics_utils::encode_metrics(),
+    )
+}
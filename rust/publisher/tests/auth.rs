@@ -0,0 +1,74 @@
+use auth_models::*;
+use auth_service_mock::*;
+use auth_service_models::LoginRequest;
+use publisher::*;
+
+const ADMIN_EMAIL: &str = "admin@pastureen.com";
+const READER_EMAIL: &str = "reader@pastureen.com";
+const PASSWORD: &str = "password";
+
+fn user(email: &str) -> User {
+    User {
+        fname: "fname".to_string(),
+        lname: "lname".to_string(),
+        email: email.to_string(),
+    }
+}
+
+async fn start_server() -> MockAuthServer {
+    let server = MockAuthServer::start().await;
+    server.add_user(user(ADMIN_EMAIL), PASSWORD);
+    server.add_user(user(READER_EMAIL), PASSWORD);
+    server
+}
+
+async fn access_token(server: &MockAuthServer, email: &str) -> String {
+    let request = LoginRequest {
+        email: email.to_string(),
+        password: PASSWORD.to_string(),
+    };
+    auth_client::login(server.url(), &request)
+        .await
+        .unwrap()
+        .access_token
+}
+
+#[tokio::test]
+async fn authorizes_the_admin() {
+    let server = start_server().await;
+    let token = access_token(&server, ADMIN_EMAIL).await;
+
+    let user = authorize_admin(server.url(), ADMIN_EMAIL, &token)
+        .await
+        .unwrap();
+    assert_eq!(user.email, ADMIN_EMAIL);
+}
+
+#[tokio::test]
+async fn forbids_other_users_and_invalid_tokens() {
+    let server = start_server().await;
+    let token = access_token(&server, READER_EMAIL).await;
+
+    let result = authorize_admin(server.url(), ADMIN_EMAIL, &token).await;
+    assert!(
+        matches!(result, Err(PublisherError::Forbidden)),
+        "{:?}",
+        result
+    );
+    let result = authorize_admin(server.url(), ADMIN_EMAIL, "invalid").await;
+    assert!(
+        matches!(result, Err(PublisherError::Forbidden)),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn reports_an_unreachable_auth_service() {
+    let result = authorize_admin("http://127.0.0.1:9", ADMIN_EMAIL, "token").await;
+    assert!(
+        matches!(result, Err(PublisherError::AuthCheckRequestFailed(_))),
+        "{:?}",
+        result
+    );
+}
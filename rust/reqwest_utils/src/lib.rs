@@ -1,8 +1,142 @@
 use shared_models::*;
 
-use reqwest::{Error, Response};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Error, Method, Response, StatusCode,
+};
 
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::time::Duration;
+
+/// A description of a request to a service, independent of the client used to send it
+///
+/// Both the async and the [blocking] clients build their requests from this, so a service client
+/// only has to describe each endpoint once. The id of the request currently being handled is
+/// propagated in the `X-Request-Id` header
+#[derive(Debug, Clone)]
+pub struct ServiceRequest {
+    pub method: Method,
+    pub url: String,
+    pub bearer_token: Option<String>,
+    pub json_body: Option<Vec<u8>>,
+    pub timeout: Option<Duration>,
+}
+
+impl ServiceRequest {
+    pub fn new(method: Method, url: String) -> Self {
+        Self {
+            method,
+            url,
+            bearer_token: None,
+            json_body: None,
+            timeout: None,
+        }
+    }
+
+    pub fn get(url: String) -> Self {
+        Self::new(Method::GET, url)
+    }
+
+    pub fn post(url: String) -> Self {
+        Self::new(Method::POST, url)
+    }
+
+    /// Adds a bearer token to the authorization header of the request
+    pub fn bearer_auth(mut self, token: &str) -> Self {
+        self.bearer_token = Some(token.to_string());
+        self
+    }
+
+    /// Fails the request if no response is received within the duration
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Serializes the body of the request as json
+    pub fn json<T>(mut self, body: &T) -> Result<Self, ClientHttpResponseError>
+    where
+        T: Serialize,
+    {
+        let body = serde_json::to_vec(body).map_err(|err| {
+            ClientHttpResponseError::RawErr(format!("Failed to serialize body {:?}", err))
+        })?;
+        self.json_body = Some(body);
+        Ok(self)
+    }
+
+    /// Headers of the request, shared between the async and blocking clients
+    ///
+    /// A bearer token which isn't a valid header value, such as a token with a newline, is an
+    /// error rather than a request sent without it
+    fn headers(&self) -> Result<HeaderMap, ClientHttpResponseError> {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = &self.bearer_token {
+            let mut bearer = HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|_| ClientHttpResponseError::RawErr("Invalid bearer token".to_string()))?;
+            // Kept out of debug output, as `bearer_auth` does
+            bearer.set_sensitive(true);
+            headers.insert(AUTHORIZATION, bearer);
+        }
+        if self.json_body.is_some() {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+        let request_id = tracing_utils::current_request_id()
+            .and_then(|request_id| HeaderValue::from_str(&request_id).ok());
+        if let Some(request_id) = request_id {
+            headers.insert(tracing_utils::REQUEST_ID_HEADER, request_id);
+        }
+        Ok(headers)
+    }
+
+    fn build(
+        self,
+        client: &reqwest::Client,
+    ) -> Result<reqwest::RequestBuilder, ClientHttpResponseError> {
+        let mut builder = client
+            .request(self.method.clone(), &self.url)
+            .headers(self.headers()?);
+        if let Some(body) = self.json_body {
+            builder = builder.body(body);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder)
+    }
+}
+
+/// Sends the request and decodes the response with [handle_res]
+pub async fn send<T>(request: ServiceRequest) -> Result<T, ClientHttpResponseError>
+where
+    T: DeserializeOwned,
+{
+    let client = reqwest::Client::new();
+    let res = request.build(&client)?.send().await;
+    handle_res::<T>(res).await
+}
+
+/// Sends the request, only checking that the response was successful
+///
+/// Used to check that a service is reachable, the body of a successful response is ignored
+pub async fn ping(request: ServiceRequest) -> Result<(), ClientHttpResponseError> {
+    let client = reqwest::Client::new();
+    let res = request
+        .build(&client)?
+        .send()
+        .await
+        .map_err(|err| ClientHttpResponseError::RawErr(format!("{:?}", err)))?;
+
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body_contents = res.bytes().await.map_err(|err| {
+        ClientHttpResponseError::RawErr(format!("Failed to get bytes from body: {:?}", err))
+    })?;
+    decode_body::<serde::de::IgnoredAny>(status, &body_contents).map(|_| ())
+}
 
 pub async fn handle_res<T>(res: Result<Response, Error>) -> Result<T, ClientHttpResponseError>
 where
@@ -19,23 +153,80 @@ where
     T: DeserializeOwned,
 {
     let status = res.status();
+    let body_contents = res.bytes().await.map_err(|err| {
+        ClientHttpResponseError::RawErr(format!("Failed to get bytes from body: {:?}", err))
+    })?;
+    decode_body::<T>(status, &body_contents)
+}
+
+/// Decodes the body of a response, shared between the async and blocking clients
+///
+/// Successful responses are deserialized into `T`, unsuccessful responses are deserialized into
+/// a [HttpErrResponseBody] if possible and otherwise reported raw
+fn decode_body<T>(status: StatusCode, body_contents: &[u8]) -> Result<T, ClientHttpResponseError>
+where
+    T: DeserializeOwned,
+{
     if status.is_success() {
-        return res.json::<T>().await.map_err(|err| {
+        serde_json::from_slice::<T>(body_contents).map_err(|err| {
             ClientHttpResponseError::RawErr(format!("Failed to deserialize {:?}", err))
-        });
+        })
     } else {
-        let body_contents = res.bytes().await.map_err(|err| {
-            ClientHttpResponseError::RawErr(format!("Failed to get bytes from body: {:?}", err))
-        })?;
-
-        serde_json::from_slice::<HttpErrResponseBody>(&body_contents)
+        serde_json::from_slice::<HttpErrResponseBody>(body_contents)
             .map_err(|_| {
                 ClientHttpResponseError::RawErr(format!(
                     "Status: {:?}\nBody: {}",
                     status,
-                    String::from_utf8_lossy(&body_contents)
+                    String::from_utf8_lossy(body_contents)
                 ))
             })
             .and_then(|body| Err(ClientHttpResponseError::TypedServiceErr(body)))
     }
 }
+
+/// Synchronous variants of [send] and [handle_res], built on `reqwest::blocking`
+#[cfg(feature = "blocking")]
+pub mod blocking {
+    use super::*;
+    use reqwest::blocking::{Client, RequestBuilder, Response};
+
+    impl ServiceRequest {
+        fn build_blocking(
+            self,
+            client: &Client,
+        ) -> Result<RequestBuilder, ClientHttpResponseError> {
+            let mut builder = client
+                .request(self.method.clone(), &self.url)
+                .headers(self.headers()?);
+            if let Some(body) = self.json_body {
+                builder = builder.body(body);
+            }
+            if let Some(timeout) = self.timeout {
+                builder = builder.timeout(timeout);
+            }
+            Ok(builder)
+        }
+    }
+
+    /// Sends the request and decodes the response with [handle_res]
+    pub fn send<T>(request: ServiceRequest) -> Result<T, ClientHttpResponseError>
+    where
+        T: DeserializeOwned,
+    {
+        let client = Client::new();
+        let res = request.build_blocking(&client)?.send();
+        handle_res::<T>(res)
+    }
+
+    pub fn handle_res<T>(res: Result<Response, Error>) -> Result<T, ClientHttpResponseError>
+    where
+        T: DeserializeOwned,
+    {
+        let res = res.map_err(|err| ClientHttpResponseError::RawErr(format!("{:?}", err)))?;
+        let status = res.status();
+        let body_contents = res.bytes().map_err(|err| {
+            ClientHttpResponseError::RawErr(format!("Failed to get bytes from body: {:?}", err))
+        })?;
+        decode_body::<T>(status, &body_contents)
+    }
+}
@@ -0,0 +1,516 @@
+use futures_util::TryStreamExt;
+use hyper::{
+    body::HttpBody,
+    header::{HeaderValue, CONTENT_LENGTH},
+    Body, Request, Response,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tracing_utils::tracing::error;
+
+use crate::error::ReverseProxyError;
+
+/* ACCESS_LOG_CONFIG */
+
+/// Line format of the access log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    /// Apache Combined Log Format, followed by the fields it doesn't have
+    #[default]
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+impl AccessLogFormat {
+    pub fn parse(value: &str) -> Result<Self, ReverseProxyError> {
+        match value {
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ => Err(ReverseProxyError::InvalidConfiguration(format!(
+                "Unknown access log format `{}`, expected `combined` or `json`",
+                value
+            ))),
+        }
+    }
+}
+
+/// Where and how requests are logged, read once at startup
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    /// Log a line per request, defaults to true
+    pub enabled: bool,
+    pub format: AccessLogFormat,
+    /// File the log is appended to, stdout when not set
+    pub path: Option<PathBuf>,
+    /// Size the file is rotated at, defaults to 100 MiB
+    pub max_bytes: u64,
+    /// Number of rotated files kept as `<path>.1` to `<path>.<max_files>`, defaults to 5
+    pub max_files: usize,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            format: AccessLogFormat::Combined,
+            path: None,
+            max_bytes: 100 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+impl AccessLogConfig {
+    /// The access log of the environment configuration, `ACCESS_LOG_FORMAT` is `combined`,
+    /// `json` or `off` and `ACCESS_LOG_PATH` is a file to write to instead of stdout
+    pub fn from_env() -> Result<Self, ReverseProxyError> {
+        let mut config = Self::default();
+        match std::env::var("ACCESS_LOG_FORMAT")
+            .unwrap_or_default()
+            .as_str()
+        {
+            "" => {}
+            "off" => config.enabled = false,
+            format => config.format = AccessLogFormat::parse(format)?,
+        }
+        config.path = std::env::var("ACCESS_LOG_PATH")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ReverseProxyError> {
+        if self.max_bytes == 0 {
+            return Err(ReverseProxyError::InvalidConfiguration(
+                "Access log max_bytes must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/* ACCESS_LOG_ENTRY */
+
+/// The upstream which answered a request, carried in the extensions of its response
+#[derive(Debug, Clone)]
+pub struct UpstreamTiming {
+    pub upstream: String,
+    /// Time until the upstream sent the headers of its response
+    pub latency: Duration,
+}
+
+/// A request as written to the access log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogEntry {
+    pub timestamp: SystemTime,
+    pub client_ip: IpAddr,
+    pub method: String,
+    /// The request target as sent by the client, before it is normalised
+    pub target: String,
+    pub version: String,
+    pub status: u16,
+    /// Bytes of the request body read from the client
+    pub bytes_in: u64,
+    /// Bytes of the response body sent to the client, before transfer encoding
+    pub bytes_out: u64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: String,
+    pub route: String,
+    pub upstream: Option<String>,
+    pub upstream_latency: Option<Duration>,
+    /// Time until the last byte of the response was sent
+    pub latency: Duration,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    timestamp: String,
+    client_ip: String,
+    method: &'a str,
+    path: &'a str,
+    protocol: &'a str,
+    status: u16,
+    bytes_in: u64,
+    bytes_out: u64,
+    referer: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    request_id: &'a str,
+    route: &'a str,
+    upstream: Option<&'a str>,
+    upstream_latency_ms: Option<f64>,
+    latency_ms: f64,
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The day, month, year and time of a timestamp in UTC, eg. `("06", "Nov", "1994", "08:49:37")`
+fn date_parts(timestamp: SystemTime) -> [String; 4] {
+    // Http dates are always `Sun, 06 Nov 1994 08:49:37 GMT`
+    let date = httpdate::fmt_http_date(timestamp);
+    let mut parts = date.split(' ').skip(1).map(str::to_string);
+    std::array::from_fn(|_| parts.next().unwrap_or_default())
+}
+
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
+
+/// Escapes a field of the Combined format the way Apache does, so it can't break out of its quotes
+fn escape_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            byte => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+impl AccessLogEntry {
+    /// `client - - [time] "request line" status bytes_out "referer" "user agent"`, followed by
+    /// `bytes_in request_id route upstream upstream_ms total_ms`, with `-` for missing fields
+    pub fn combined(&self) -> String {
+        let [day, month, year, time] = date_parts(self.timestamp);
+        let quoted =
+            |value: &Option<String>| value.as_deref().map_or("-".to_string(), escape_quoted);
+        format!(
+            "{} - - [{}/{}/{}:{} +0000] \"{} {} {}\" {} {} \"{}\" \"{}\" {} {} {} {} {} {}\n",
+            self.client_ip,
+            day,
+            month,
+            year,
+            time,
+            escape_quoted(&self.method),
+            escape_quoted(&self.target),
+            self.version,
+            self.status,
+            self.bytes_out,
+            quoted(&self.referer),
+            quoted(&self.user_agent),
+            self.bytes_in,
+            escape_quoted(&self.request_id),
+            escape_quoted(&self.route),
+            self.upstream
+                .as_deref()
+                .map_or("-".to_string(), escape_quoted),
+            self.upstream_latency
+                .map_or("-".to_string(), |latency| millis(latency).to_string()),
+            millis(self.latency),
+        )
+    }
+
+    /// A JSON object with an RFC 3339 timestamp and latencies in milliseconds
+    pub fn json(&self) -> String {
+        let [day, month, year, time] = date_parts(self.timestamp);
+        let month = MONTHS
+            .iter()
+            .position(|name| *name == month)
+            .unwrap_or_default()
+            + 1;
+        let subsec_millis = self
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.subsec_millis())
+            .unwrap_or(0);
+        let entry = JsonEntry {
+            timestamp: format!(
+                "{}-{:02}-{}T{}.{:03}Z",
+                year, month, day, time, subsec_millis
+            ),
+            client_ip: self.client_ip.to_string(),
+            method: &self.method,
+            path: &self.target,
+            protocol: &self.version,
+            status: self.status,
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            referer: self.referer.as_deref(),
+            user_agent: self.user_agent.as_deref(),
+            request_id: &self.request_id,
+            route: &self.route,
+            upstream: self.upstream.as_deref(),
+            upstream_latency_ms: self.upstream_latency.map(millis),
+            latency_ms: millis(self.latency),
+        };
+        let mut line = serde_json::to_string(&entry).expect("Failed to serialize access log entry");
+        line.push('\n');
+        line
+    }
+
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Combined => self.combined(),
+            AccessLogFormat::Json => self.json(),
+        }
+    }
+}
+
+/* ACCESS_LOG_SINK */
+
+/// A file rotated to `<path>.1` once it reaches its maximum size, older files shifting up by one
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: Option<std::fs::File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn open(&mut self) -> std::io::Result<()> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        if self.max_files == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        for index in (1..self.max_files).rev() {
+            match std::fs::rename(self.rotated_path(index), self.rotated_path(index + 1)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.file.is_none() {
+            self.open()?;
+        }
+        // A line longer than the limit still gets a file of its own
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            self.open()?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            std::io::Write::write_all(file, line.as_bytes())?;
+            self.written += line.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+enum AccessLogSink {
+    Stdout,
+    File(RotatingFile),
+}
+
+impl AccessLogSink {
+    /// Writes the lines which queued up, stdout is locked and flushed once for all of them
+    fn write_lines(&mut self, lines: &[String]) {
+        match self {
+            Self::Stdout => {
+                let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+                let written = lines
+                    .iter()
+                    .try_for_each(|line| std::io::Write::write_all(&mut stdout, line.as_bytes()))
+                    .and_then(|_| std::io::Write::flush(&mut stdout));
+                if let Err(err) = written {
+                    error!(error = %err, "Failed to write access log");
+                }
+            }
+            Self::File(file) => {
+                for line in lines {
+                    if let Err(err) = file.write_line(line) {
+                        error!(
+                            error = %err,
+                            path = %file.path.display(),
+                            "Failed to write access log"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum AccessLogMessage {
+    Line(String),
+    /// Answered once the lines sent before it are written
+    Flush(std::sync::mpsc::Sender<()>),
+}
+
+/// Writes the lines it receives until every sender is dropped
+fn write_access_log(
+    mut sink: AccessLogSink,
+    receiver: std::sync::mpsc::Receiver<AccessLogMessage>,
+) {
+    while let Ok(message) = receiver.recv() {
+        let mut lines = Vec::new();
+        let mut flushes = Vec::new();
+        for message in std::iter::once(message).chain(receiver.try_iter()) {
+            match message {
+                AccessLogMessage::Line(line) => lines.push(line),
+                AccessLogMessage::Flush(done) => flushes.push(done),
+            }
+        }
+        sink.write_lines(&lines);
+        for done in flushes {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Writes a line per request to stdout or to a rotated file
+///
+/// Lines are written by a thread of its own, so requests never wait on the file or on stdout
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sender: Option<std::sync::mpsc::Sender<AccessLogMessage>>,
+}
+
+impl AccessLog {
+    /// The file is opened when the first line is written
+    pub fn new(config: &AccessLogConfig) -> Self {
+        let sink = match (&config.path, config.enabled) {
+            (_, false) => None,
+            (None, true) => Some(AccessLogSink::Stdout),
+            (Some(path), true) => Some(AccessLogSink::File(RotatingFile {
+                path: path.clone(),
+                max_bytes: config.max_bytes,
+                max_files: config.max_files,
+                file: None,
+                written: 0,
+            })),
+        };
+        let sender = sink.map(|sink| {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::Builder::new()
+                .name("access-log".to_string())
+                .spawn(move || write_access_log(sink, receiver))
+                .expect("Failed to start the access log writer");
+            sender
+        });
+        Self {
+            format: config.format,
+            sender,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn write(&self, entry: &AccessLogEntry) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(AccessLogMessage::Line(entry.format(self.format)));
+        }
+    }
+
+    /// Blocks until the lines already logged are written, eg. before the process exits
+    pub fn flush(&self) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let (done, flushed) = std::sync::mpsc::channel();
+        if sender.send(AccessLogMessage::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+}
+
+/* LOGGED_BODIES */
+
+/// Counts the bytes of a request body read by the proxy
+///
+/// Bodies which are already complete are left as they are, so requests without a body keep being
+/// retried and sent without one
+pub fn count_request_body(req: &mut Request<Body>, bytes_in: Arc<AtomicU64>) {
+    if req.body().is_end_stream() {
+        return;
+    }
+    let body = std::mem::take(req.body_mut());
+    *req.body_mut() = Body::wrap_stream(body.inspect_ok(move |chunk| {
+        bytes_in.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+    }));
+}
+
+/// A request waiting for the end of its response body to be logged
+struct PendingEntry {
+    log: Arc<AccessLog>,
+    entry: AccessLogEntry,
+    bytes_in: Arc<AtomicU64>,
+    started: Instant,
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        self.entry.bytes_in = self.bytes_in.load(Ordering::Relaxed);
+        self.entry.latency = self.started.elapsed();
+        self.log.write(&self.entry);
+    }
+}
+
+/// Logs a request once its response body has been sent, or dropped when the client goes away
+///
+/// # Arguments
+/// * `log` - The access log
+/// * `response` - The response to the request, its `Content-Length` is kept
+/// * `entry` - The request, its byte counts and latency are filled in when it is written
+/// * `bytes_in` - The counter of the request body, see [count_request_body]
+/// * `started` - When the request arrived
+pub fn log_on_completion(
+    log: Arc<AccessLog>,
+    response: Response<Body>,
+    entry: AccessLogEntry,
+    bytes_in: Arc<AtomicU64>,
+    started: Instant,
+) -> Response<Body> {
+    let mut pending = PendingEntry {
+        log,
+        entry,
+        bytes_in,
+        started,
+    };
+    // Dropping the pending entry logs responses without a body straight away
+    if response.body().is_end_stream() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // A wrapped body has no known size, which would otherwise turn into a chunked response
+    if let Some(len) = body.size_hint().exact() {
+        parts
+            .headers
+            .entry(CONTENT_LENGTH)
+            .or_insert_with(|| HeaderValue::from(len));
+    }
+    let body = Body::wrap_stream(body.inspect_ok(move |chunk| {
+        // Captures the whole entry, which is logged when the body is dropped
+        let pending = &mut pending;
+        pending.entry.bytes_out += chunk.len() as u64;
+    }));
+    Response::from_parts(parts, body)
+}
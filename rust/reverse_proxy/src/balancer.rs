@@ -0,0 +1,445 @@
+use hyper::{Body, Request};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing_utils::{
+    tracing::{info, warn},
+    REQUEST_ID_HEADER,
+};
+
+use crate::{
+    breaker::{CircuitBreakers, CircuitStatus},
+    client::UpstreamClients,
+    config::SharedConfig,
+    metrics::UPSTREAM_INSTANCE_AVAILABLE,
+    route::RouteConfig,
+};
+
+/* LOAD_BALANCING_OPTIONS */
+
+/// How the instance of a route's upstream a request is sent to is picked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    /// Each instance in turn
+    #[default]
+    RoundRobin,
+    /// The instance with the fewest requests in flight
+    LeastConnections,
+}
+
+/// Periodic requests made to each instance of a route's upstream, instances failing them get no
+/// traffic until they pass them again
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckOptions {
+    /// Path requested from each instance, a 2xx or 3xx response is healthy
+    pub path: String,
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+    /// Consecutive failed checks after which an instance is unhealthy
+    pub unhealthy_threshold: u32,
+    /// Consecutive passed checks after which an unhealthy instance is healthy again
+    pub healthy_threshold: u32,
+}
+
+impl Default for HealthCheckOptions {
+    fn default() -> Self {
+        Self {
+            path: "/healthcheck".to_string(),
+            interval_ms: 10_000,
+            timeout_ms: 2_000,
+            unhealthy_threshold: 2,
+            healthy_threshold: 2,
+        }
+    }
+}
+
+impl HealthCheckOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.path.starts_with('/') {
+            return Err("health check path must start with `/`".to_string());
+        }
+        if self.interval_ms == 0 || self.timeout_ms == 0 {
+            return Err("health check interval and timeout must be greater than 0".to_string());
+        }
+        if self.unhealthy_threshold == 0 || self.healthy_threshold == 0 {
+            return Err("health check thresholds must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Passive ejection of the instances which keep failing the requests sent to them
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OutlierDetectionOptions {
+    /// Consecutive 5xx responses or connection failures after which an instance is ejected
+    pub consecutive_failures: u32,
+    /// How long an ejected instance gets no traffic
+    pub ejection_ms: u64,
+}
+
+impl Default for OutlierDetectionOptions {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            ejection_ms: 30_000,
+        }
+    }
+}
+
+impl OutlierDetectionOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.consecutive_failures == 0 || self.ejection_ms == 0 {
+            return Err(
+                "outlier detection failures and ejection must be greater than 0".to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/* INSTANCES */
+
+#[derive(Debug)]
+struct InstanceHealth {
+    /// Result of the active health checks, instances are healthy until a check fails
+    healthy: bool,
+    passed_checks: u32,
+    failed_checks: u32,
+    last_checked: Option<Instant>,
+    /// Failed requests in a row, reset by any successful one
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+/// An instance of an upstream, shared by every route listing it
+#[derive(Debug)]
+struct Instance {
+    url: String,
+    active: AtomicUsize,
+    health: Mutex<InstanceHealth>,
+}
+
+impl Instance {
+    fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            active: AtomicUsize::new(0),
+            health: Mutex::new(InstanceHealth {
+                healthy: true,
+                passed_checks: 0,
+                failed_checks: 0,
+                last_checked: None,
+                consecutive_failures: 0,
+                ejected_until: None,
+            }),
+        }
+    }
+
+    fn health(&self) -> std::sync::MutexGuard<'_, InstanceHealth> {
+        self.health
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        let health = self.health();
+        health.healthy && health.ejected_until.is_none_or(|until| until <= now)
+    }
+
+    fn set_available_metric(&self, now: Instant) {
+        UPSTREAM_INSTANCE_AVAILABLE
+            .with_label_values(&[&self.url])
+            .set(self.is_available(now) as i64);
+    }
+}
+
+/// The instance a request is sent to, counted as in flight until dropped
+pub struct SelectedUpstream {
+    instance: Arc<Instance>,
+}
+
+impl SelectedUpstream {
+    /// Base url of the instance
+    pub fn url(&self) -> &str {
+        &self.instance.url
+    }
+}
+
+impl Drop for SelectedUpstream {
+    fn drop(&mut self) {
+        self.instance.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// State of an instance, as shown by the health endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceStatus {
+    pub url: String,
+    pub healthy: bool,
+    pub ejected: bool,
+    pub active_requests: usize,
+    pub circuit: CircuitStatus,
+}
+
+/* UPSTREAMS */
+
+/// Ticks of the health check loop, each instance is checked at the interval of its route
+const HEALTH_CHECK_TICK: Duration = Duration::from_millis(500);
+
+/// The instances of every upstream, with their health and the requests in flight to them
+///
+/// Kept across configuration reloads, instances are identified by their url
+#[derive(Default)]
+pub struct Upstreams {
+    instances: Mutex<HashMap<String, Arc<Instance>>>,
+    /// Round robin position of each route, by prefix
+    cursors: Mutex<HashMap<String, usize>>,
+}
+
+impl Upstreams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn instance(&self, url: &str) -> Arc<Instance> {
+        self.instances
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(url.to_string())
+            .or_insert_with(|| Arc::new(Instance::new(url)))
+            .clone()
+    }
+
+    /// Round robin position of a route, advanced for each new request
+    fn cursor(&self, route: &RouteConfig, advance: bool) -> usize {
+        let mut cursors = self
+            .cursors
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let cursor = cursors.entry(route.prefix.clone()).or_insert(0);
+        let current = *cursor;
+        if advance {
+            *cursor = cursor.wrapping_add(1);
+        }
+        current
+    }
+
+    /// Picks the instance of a route's upstream to send a request to
+    ///
+    /// Unhealthy and ejected instances are skipped unless no other is left, in which case every
+    /// instance is tried rather than failing the request outright. Retries don't advance the round
+    /// robin position, so a failing instance doesn't take the turns of the others
+    ///
+    /// # Arguments
+    /// * `route` - The route of the request
+    /// * `tried` - Urls of the instances the request already failed on
+    pub fn select(&self, route: &RouteConfig, tried: &[String]) -> Option<SelectedUpstream> {
+        let now = Instant::now();
+        let count = route.upstreams.len();
+        let start = self.cursor(route, tried.is_empty()) % count.max(1);
+        // Instances in turn from the round robin position
+        let candidates: Vec<Arc<Instance>> = route.upstreams[start..]
+            .iter()
+            .chain(&route.upstreams[..start])
+            .filter(|url| !tried.contains(url))
+            .map(|url| self.instance(url))
+            .collect();
+        let available: Vec<Arc<Instance>> = candidates
+            .iter()
+            .filter(|instance| instance.is_available(now))
+            .cloned()
+            .collect();
+        let candidates = if available.is_empty() {
+            candidates
+        } else {
+            available
+        };
+
+        let instance = match route.options.load_balancing {
+            LoadBalancing::RoundRobin => candidates.first().cloned()?,
+            // Ties go to the next instance in turn
+            LoadBalancing::LeastConnections => candidates
+                .iter()
+                .min_by_key(|instance| instance.active.load(Ordering::Relaxed))
+                .cloned()?,
+        };
+        instance.active.fetch_add(1, Ordering::Relaxed);
+        Some(SelectedUpstream { instance })
+    }
+
+    /// Records the outcome of a request sent to an instance, ejecting it when it keeps failing
+    ///
+    /// # Arguments
+    /// * `selected` - The instance the request was sent to
+    /// * `failed` - Whether the request failed with a 5xx response or without a response
+    /// * `options` - The outlier detection options of the route
+    pub fn report(
+        &self,
+        selected: &SelectedUpstream,
+        failed: bool,
+        options: &OutlierDetectionOptions,
+    ) {
+        let instance = &selected.instance;
+        let now = Instant::now();
+        {
+            let mut health = instance.health();
+            if !failed {
+                health.consecutive_failures = 0;
+                return;
+            }
+
+            health.consecutive_failures += 1;
+            if health.consecutive_failures < options.consecutive_failures {
+                return;
+            }
+            health.consecutive_failures = 0;
+            health.ejected_until = Some(now + Duration::from_millis(options.ejection_ms));
+        }
+        warn!(
+            instance = %instance.url,
+            ejection_ms = options.ejection_ms,
+            "Ejected failing upstream instance"
+        );
+        instance.set_available_metric(now);
+    }
+
+    /// The state of the instances of a route, with their circuits
+    pub fn status(&self, route: &RouteConfig, breakers: &CircuitBreakers) -> Vec<InstanceStatus> {
+        let now = Instant::now();
+        route
+            .upstreams
+            .iter()
+            .map(|url| {
+                let instance = self.instance(url);
+                let health = instance.health();
+                InstanceStatus {
+                    url: url.clone(),
+                    healthy: health.healthy,
+                    ejected: health.ejected_until.is_some_and(|until| until > now),
+                    active_requests: instance.active.load(Ordering::Relaxed),
+                    circuit: breakers.status(url),
+                }
+            })
+            .collect()
+    }
+
+    /// Records the result of an active health check
+    fn record_check(&self, url: &str, passed: bool, options: &HealthCheckOptions) {
+        let instance = self.instance(url);
+        let changed = {
+            let mut health = instance.health();
+            if passed {
+                health.failed_checks = 0;
+                health.passed_checks += 1;
+                let recovered =
+                    !health.healthy && health.passed_checks >= options.healthy_threshold;
+                if recovered {
+                    health.healthy = true;
+                }
+                recovered
+            } else {
+                health.passed_checks = 0;
+                health.failed_checks += 1;
+                let failed = health.healthy && health.failed_checks >= options.unhealthy_threshold;
+                if failed {
+                    health.healthy = false;
+                }
+                failed
+            }
+        };
+
+        if changed {
+            if passed {
+                info!(instance = %url, "Upstream instance is healthy");
+            } else {
+                warn!(instance = %url, "Upstream instance is unhealthy");
+            }
+            instance.set_available_metric(Instant::now());
+        }
+    }
+
+    /// Whether an instance is due for a health check, marking it as checked when it is
+    fn check_due(&self, url: &str, interval: Duration, now: Instant) -> bool {
+        let instance = self.instance(url);
+        let mut health = instance.health();
+        let due = health
+            .last_checked
+            .is_none_or(|last_checked| now.duration_since(last_checked) >= interval);
+        if due {
+            health.last_checked = Some(now);
+        }
+        due
+    }
+
+    /// Runs the active health checks of the routes of the current configuration in the
+    /// background
+    pub fn watch(self: &Arc<Self>, config: SharedConfig, clients: Arc<UpstreamClients>) {
+        let upstreams = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(HEALTH_CHECK_TICK);
+            loop {
+                ticks.tick().await;
+                let config = config.get();
+                let now = Instant::now();
+                for route in config.routes.routes() {
+                    for url in route.upstreams.iter() {
+                        upstreams.instance(url).set_available_metric(now);
+                    }
+                    let Some(options) = &route.options.health_check else {
+                        continue;
+                    };
+                    let interval = Duration::from_millis(options.interval_ms);
+                    for url in route.upstreams.iter() {
+                        if upstreams.check_due(url, interval, now) {
+                            tokio::spawn(health_check(
+                                upstreams.clone(),
+                                clients.clone(),
+                                route.clone(),
+                                url.clone(),
+                            ));
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Requests the health check path of an instance and records the result
+async fn health_check(
+    upstreams: Arc<Upstreams>,
+    clients: Arc<UpstreamClients>,
+    route: Arc<RouteConfig>,
+    url: String,
+) {
+    let Some(options) = &route.options.health_check else {
+        return;
+    };
+    let request = Request::get(format!("{}{}", url, options.path))
+        .header(REQUEST_ID_HEADER, "healthcheck")
+        .body(Body::empty());
+    let passed = match request {
+        Ok(request) => {
+            let timeout = Duration::from_millis(options.timeout_ms);
+            match tokio::time::timeout(timeout, clients.send(request, &route.options)).await {
+                Ok(Ok(response)) => {
+                    let status = response.status();
+                    status.is_success() || status.is_redirection()
+                }
+                _ => false,
+            }
+        }
+        Err(_) => false,
+    };
+    upstreams.record_check(&url, passed, options);
+}
@@ -0,0 +1,293 @@
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE, RETRY_AFTER},
+    Body, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing_utils::tracing::{info, warn};
+
+use crate::{error::ReverseProxyError, metrics::UPSTREAM_CIRCUIT_STATE};
+
+/* CIRCUIT_BREAKER_OPTIONS */
+
+/// Failing fast while the instances of an upstream are down instead of waiting on every request
+/// for them to fail
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerOptions {
+    /// Failed requests in a row which open the circuit
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before trial requests are let through
+    pub open_ms: u64,
+    /// Successful trial requests in a row which close the circuit again
+    pub success_threshold: u32,
+    /// HTML page sent with the 503 while the circuit is open, an error is sent when not set
+    pub fallback_page: Option<PathBuf>,
+}
+
+impl Default for CircuitBreakerOptions {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_ms: 30_000,
+            success_threshold: 1,
+            fallback_page: None,
+        }
+    }
+}
+
+impl CircuitBreakerOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.failure_threshold == 0 || self.success_threshold == 0 {
+            return Err("circuit breaker thresholds must be greater than 0".to_string());
+        }
+        if self.open_ms == 0 {
+            return Err("circuit breaker open_ms must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    fn open_duration(&self) -> Duration {
+        Duration::from_millis(self.open_ms)
+    }
+}
+
+/* CIRCUITS */
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CircuitState {
+    /// Requests are sent to the instance
+    Closed,
+    /// Requests skip the instance, failing fast when every instance is open
+    Open,
+    /// A trial request is sent to the instance to find out whether it is back
+    HalfOpen,
+}
+
+impl CircuitState {
+    fn metric_value(self) -> i64 {
+        match self {
+            Self::Closed => 0,
+            Self::Open => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: u32,
+    consecutive_successes: u32,
+    opened_at: Option<Instant>,
+    /// When the trial request in flight was let through, only one is sent at a time
+    trial_started: Option<Instant>,
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            consecutive_successes: 0,
+            opened_at: None,
+            trial_started: None,
+        }
+    }
+}
+
+impl Circuit {
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.opened_at = Some(now);
+        self.consecutive_failures = 0;
+        self.consecutive_successes = 0;
+        self.trial_started = None;
+    }
+
+    fn retry_after_secs(&self, options: &CircuitBreakerOptions, now: Instant) -> u64 {
+        let reopens = self
+            .opened_at
+            .map(|opened_at| opened_at + options.open_duration());
+        reopens
+            .map(|reopens| reopens.saturating_duration_since(now).as_secs_f64().ceil() as u64)
+            .unwrap_or(0)
+            .max(1)
+    }
+}
+
+/// State of the circuit of an upstream instance, as shown by the upstream health endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+/// The circuit of each upstream instance, by url
+///
+/// Like the outlier ejection of [Upstreams](crate::balancer::Upstreams), one failing instance
+/// doesn't take down the others. Kept across configuration reloads, instances shared by several
+/// routes share their circuit.
+#[derive(Default)]
+pub struct CircuitBreakers {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn circuits(&self) -> std::sync::MutexGuard<'_, HashMap<String, Circuit>> {
+        self.circuits
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether a request can be sent to an instance, or the seconds until it can be retried
+    ///
+    /// An open circuit lets a single trial request through once `open_ms` has passed. A trial
+    /// which never reports back, eg. because the client went away, is replaced after `open_ms`
+    ///
+    /// # Arguments
+    /// * `instance` - The url of the upstream instance
+    /// * `options` - The circuit breaker options of the route
+    /// * `now` - The time of the request
+    pub fn allow_at(
+        &self,
+        instance: &str,
+        options: &CircuitBreakerOptions,
+        now: Instant,
+    ) -> Result<(), u64> {
+        let mut circuits = self.circuits();
+        let circuit = circuits.entry(instance.to_string()).or_default();
+        let open_duration = options.open_duration();
+        let allowed = match circuit.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => circuit
+                .opened_at
+                .is_none_or(|opened_at| now.saturating_duration_since(opened_at) >= open_duration),
+            CircuitState::HalfOpen => circuit
+                .trial_started
+                .is_none_or(|started| now.saturating_duration_since(started) >= open_duration),
+        };
+        if !allowed {
+            return Err(circuit.retry_after_secs(options, now));
+        }
+
+        if circuit.state != CircuitState::Closed {
+            if circuit.state == CircuitState::Open {
+                info!(instance, "Circuit half open, sending a trial request");
+            }
+            circuit.state = CircuitState::HalfOpen;
+            circuit.trial_started = Some(now);
+            set_circuit_metric(instance, circuit.state);
+        }
+        Ok(())
+    }
+
+    /// Whether a request can be sent to an instance now, see [CircuitBreakers::allow_at]
+    pub fn allow(&self, instance: &str, options: &CircuitBreakerOptions) -> Result<(), u64> {
+        self.allow_at(instance, options, Instant::now())
+    }
+
+    /// Records the outcome of a request sent to an instance, opening or closing its circuit
+    ///
+    /// # Arguments
+    /// * `instance` - The url of the upstream instance
+    /// * `failed` - Whether the upstream was unreachable, timed out or answered 502, 503 or 504
+    /// * `options` - The circuit breaker options of the route
+    pub fn record(&self, instance: &str, failed: bool, options: &CircuitBreakerOptions) {
+        let now = Instant::now();
+        let mut circuits = self.circuits();
+        let circuit = circuits.entry(instance.to_string()).or_default();
+        let previous = circuit.state;
+        match (circuit.state, failed) {
+            (CircuitState::Closed, false) => circuit.consecutive_failures = 0,
+            (CircuitState::Closed, true) => {
+                circuit.consecutive_failures += 1;
+                if circuit.consecutive_failures >= options.failure_threshold {
+                    circuit.open(now);
+                }
+            }
+            (CircuitState::HalfOpen, false) => {
+                circuit.trial_started = None;
+                circuit.consecutive_successes += 1;
+                if circuit.consecutive_successes >= options.success_threshold {
+                    *circuit = Circuit::default();
+                }
+            }
+            (CircuitState::HalfOpen, true) => circuit.open(now),
+            // Requests sent before the circuit opened
+            (CircuitState::Open, _) => {}
+        }
+
+        if circuit.state != previous {
+            match circuit.state {
+                CircuitState::Open => warn!(instance, "Circuit opened"),
+                _ => info!(instance, "Circuit closed"),
+            }
+            set_circuit_metric(instance, circuit.state);
+        }
+    }
+
+    /// The state of the circuit of an instance
+    pub fn status(&self, instance: &str) -> CircuitStatus {
+        let circuits = self.circuits();
+        match circuits.get(instance) {
+            Some(circuit) => CircuitStatus {
+                state: circuit.state,
+                consecutive_failures: circuit.consecutive_failures,
+            },
+            None => CircuitStatus {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+            },
+        }
+    }
+}
+
+fn set_circuit_metric(instance: &str, state: CircuitState) {
+    UPSTREAM_CIRCUIT_STATE
+        .with_label_values(&[instance])
+        .set(state.metric_value());
+}
+
+/// The response sent while the circuits of every instance of an upstream are open, the fallback
+/// page when it is set and readable
+///
+/// # Arguments
+/// * `upstream` - The name of the route of the upstream
+/// * `retry_after_secs` - Seconds until a trial request is let through to one of the instances
+/// * `options` - The circuit breaker options of the route
+pub async fn circuit_open_response(
+    upstream: &str,
+    retry_after_secs: u64,
+    options: &CircuitBreakerOptions,
+) -> Result<Response<Body>, ReverseProxyError> {
+    let open = ReverseProxyError::CircuitOpen(upstream.to_string(), retry_after_secs);
+    let Some(path) = &options.fallback_page else {
+        return Err(open);
+    };
+
+    match tokio::fs::read(path).await {
+        Ok(page) => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(RETRY_AFTER, retry_after_secs)
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(page))
+            .map_err(|_| open),
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "Failed to read circuit fallback page");
+            Err(open)
+        }
+    }
+}
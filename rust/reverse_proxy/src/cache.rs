@@ -0,0 +1,835 @@
+use hyper::{
+    body::Bytes,
+    header::{
+        HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, DATE, ETAG,
+        EXPIRES, HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, PRAGMA, SET_COOKIE,
+        UPGRADE, VARY,
+    },
+    Body, HeaderMap, Method, Request, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use tracing_utils::tracing::{error, warn};
+
+use crate::error::{ReverseProxyError, StdError};
+
+/* CACHE_CONFIG */
+
+/// Limits of the response cache, read once at startup
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Maximum number of responses kept in memory
+    pub max_entries: usize,
+    /// Maximum total size of the bodies kept in memory
+    pub max_memory_bytes: usize,
+    /// Responses with larger bodies, or without a `Content-Length`, are never cached
+    pub max_body_bytes: usize,
+    /// Directory responses evicted from memory are spilled to, disabled when not set
+    pub disk_dir: Option<PathBuf>,
+    /// Maximum total size of the bodies spilled to disk
+    pub max_disk_bytes: usize,
+    /// Bearer token of the purge endpoint, falls back to `CACHE_PURGE_TOKEN`
+    ///
+    /// Purging is disabled without a token
+    pub purge_token: Option<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1024,
+            max_memory_bytes: 64 * 1024 * 1024,
+            max_body_bytes: 1024 * 1024,
+            disk_dir: None,
+            max_disk_bytes: 1024 * 1024 * 1024,
+            purge_token: None,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Fills in the purge token from `CACHE_PURGE_TOKEN` when the configuration has none
+    pub fn with_env_purge_token(mut self) -> Self {
+        if self.purge_token.is_none() {
+            self.purge_token = std::env::var("CACHE_PURGE_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty());
+        }
+        self
+    }
+}
+
+/// Caching of the responses of a route, responses are only cached on routes which set it
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RouteCacheOptions {
+    /// How long responses are fresh for when the upstream doesn't say
+    pub ttl_secs: u64,
+}
+
+/* CONTRACTS */
+
+/// HTTP request body of the purge endpoint, `POST /cache/purge`
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeCacheRequest {
+    /// Only purge responses whose path starts with the prefix, everything is purged without one
+    pub prefix: Option<String>,
+}
+
+/// HTTP response body of the purge endpoint
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeCacheResponse {
+    /// Number of responses removed from the cache
+    pub purged: usize,
+}
+
+/* CACHE_CONTROL */
+
+/// The directives of the `Cache-Control` headers the cache acts on
+#[derive(Debug, Default, PartialEq)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub no_transform: bool,
+}
+
+impl CacheControl {
+    pub fn parse(headers: &HeaderMap) -> Self {
+        let mut cache_control = Self::default();
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = value.and_then(|value| value.parse().ok());
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => cache_control.no_store = true,
+                "no-cache" => cache_control.no_cache = true,
+                "private" => cache_control.private = true,
+                "public" => cache_control.public = true,
+                "max-age" => cache_control.max_age = seconds,
+                "s-maxage" => cache_control.s_maxage = seconds,
+                "no-transform" => cache_control.no_transform = true,
+                _ => {}
+            }
+        }
+        cache_control
+    }
+}
+
+/// Whether the client asks for the cached response to be revalidated with the upstream
+fn requires_revalidation(headers: &HeaderMap) -> bool {
+    let cache_control = CacheControl::parse(headers);
+    let pragma_no_cache = headers
+        .get(PRAGMA)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("no-cache"));
+    cache_control.no_cache || cache_control.max_age == Some(0) || pragma_no_cache
+}
+
+fn http_date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    let value = headers.get(name)?.to_str().ok()?;
+    httpdate::parse_http_date(value).ok()
+}
+
+/// Status codes cacheable by default, RFC 9110 section 15.1
+const CACHEABLE_STATUSES: [u16; 7] = [200, 203, 204, 301, 308, 404, 410];
+
+/// Headers of a 304 response which replace the ones of the cached response
+const REVALIDATED_HEADERS: [HeaderName; 6] =
+    [CACHE_CONTROL, DATE, ETAG, EXPIRES, LAST_MODIFIED, VARY];
+
+/* CACHE_KEY */
+
+/// The cache key of a request, when its response can be cached
+///
+/// Only `GET` and `HEAD` requests are cached, keyed by method, host and path with the query
+pub fn cache_key(req: &Request<Body>) -> Option<String> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return None;
+    }
+    if req.headers().contains_key(UPGRADE) || CacheControl::parse(req.headers()).no_store {
+        return None;
+    }
+
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+    Some(format!("{} {}{}", req.method(), host, path_and_query))
+}
+
+/* LRU */
+
+struct LruSlot<V> {
+    value: V,
+    tick: u64,
+    size: usize,
+}
+
+/// A map evicting its least recently used values when it holds too many, or too large, values
+struct Lru<V> {
+    slots: HashMap<String, LruSlot<V>>,
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+impl<V> Lru<V> {
+    fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            slots: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, key: &str) -> Option<&V> {
+        let tick = self.next_tick();
+        let slot = self.slots.get_mut(key)?;
+        self.order.remove(&slot.tick);
+        self.order.insert(tick, key.to_string());
+        slot.tick = tick;
+        Some(&slot.value)
+    }
+
+    /// Inserts a value, returning the values evicted to make room for it
+    fn insert(&mut self, key: String, value: V, size: usize) -> Vec<(String, V)> {
+        self.remove(&key);
+
+        let mut evicted = Vec::new();
+        if size > self.max_bytes || self.max_entries == 0 {
+            evicted.push((key, value));
+            return evicted;
+        }
+
+        while self.slots.len() >= self.max_entries || self.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(slot) = self.slots.remove(&oldest) {
+                self.bytes -= slot.size;
+                evicted.push((oldest, slot.value));
+            }
+        }
+
+        let tick = self.next_tick();
+        self.order.insert(tick, key.clone());
+        self.bytes += size;
+        self.slots.insert(key, LruSlot { value, tick, size });
+        evicted
+    }
+
+    fn remove(&mut self, key: &str) -> Option<V> {
+        let slot = self.slots.remove(key)?;
+        self.order.remove(&slot.tick);
+        self.bytes -= slot.size;
+        Some(slot.value)
+    }
+
+    /// Removes the values matching the predicate
+    fn remove_where<F>(&mut self, predicate: F) -> Vec<(String, V)>
+    where
+        F: Fn(&V) -> bool,
+    {
+        let keys: Vec<String> = self
+            .slots
+            .iter()
+            .filter(|(_, slot)| predicate(&slot.value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| self.remove(&key).map(|value| (key, value)))
+            .collect()
+    }
+}
+
+/* CACHE_ENTRY */
+
+/// Validators used to revalidate a stale response with the upstream
+#[derive(Debug, Clone)]
+pub struct Validators {
+    pub etag: Option<HeaderValue>,
+    pub last_modified: Option<HeaderValue>,
+}
+
+impl Validators {
+    /// Makes the request conditional, replacing any conditions of the client
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.remove(IF_NONE_MATCH);
+        headers.remove(IF_MODIFIED_SINCE);
+        if let Some(etag) = &self.etag {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    /// Path and query of the request, used to purge by prefix
+    path: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: SystemTime,
+    fresh_for: Duration,
+    /// Request headers named by `Vary`, with the values the response was stored for
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl CacheEntry {
+    fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.stored_at)
+            .unwrap_or_default()
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.age() < self.fresh_for
+    }
+
+    fn validators(&self) -> Option<Validators> {
+        let validators = Validators {
+            etag: self.headers.get(ETAG).cloned(),
+            last_modified: self.headers.get(LAST_MODIFIED).cloned(),
+        };
+        if validators.etag.is_none() && validators.last_modified.is_none() {
+            return None;
+        }
+        Some(validators)
+    }
+
+    fn matches_vary(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+
+    /// The response to a client, a 304 when the conditions of its request hold
+    fn respond(&self, req_headers: &HeaderMap, result: &'static str) -> Response<Body> {
+        let not_modified = self.status == StatusCode::OK && self.not_modified_for(req_headers);
+
+        let mut response = if not_modified {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            for name in REVALIDATED_HEADERS {
+                for value in self.headers.get_all(&name) {
+                    response.headers_mut().append(name.clone(), value.clone());
+                }
+            }
+            response
+        } else {
+            let mut response = Response::new(Body::from(self.body.clone()));
+            *response.status_mut() = self.status;
+            *response.headers_mut() = self.headers.clone();
+            response
+        };
+
+        let headers = response.headers_mut();
+        headers.insert(AGE, HeaderValue::from(self.age().as_secs()));
+        headers.insert(X_CACHE, HeaderValue::from_static(result));
+        response
+    }
+
+    fn not_modified_for(&self, req_headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = req_headers.get(IF_NONE_MATCH) {
+            return match (if_none_match.to_str(), self.headers.get(ETAG)) {
+                (Ok(if_none_match), Some(etag)) => etag_matches(if_none_match, etag),
+                _ => false,
+            };
+        }
+
+        match (
+            http_date(req_headers, IF_MODIFIED_SINCE),
+            http_date(&self.headers, LAST_MODIFIED),
+        ) {
+            (Some(since), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        }
+    }
+}
+
+/// Weak comparison of an `If-None-Match` list with an entity tag, RFC 9110 section 13.1.2
+pub fn etag_matches(if_none_match: &str, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || weak(tag) == weak(etag))
+}
+
+/// How long a response is fresh for, `None` when it must not be stored
+fn freshness(
+    req_headers: &HeaderMap,
+    response: &Response<Body>,
+    options: &RouteCacheOptions,
+) -> Option<Duration> {
+    let headers = response.headers();
+    let cache_control = CacheControl::parse(headers);
+
+    if !CACHEABLE_STATUSES.contains(&response.status().as_u16())
+        || cache_control.no_store
+        || cache_control.private
+        || headers.contains_key(SET_COOKIE)
+        || (req_headers.contains_key(AUTHORIZATION) && !cache_control.public)
+    {
+        return None;
+    }
+    let vary_all = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.split(',').any(|name| name.trim() == "*"));
+    if vary_all {
+        return None;
+    }
+
+    if cache_control.no_cache {
+        return Some(Duration::ZERO);
+    }
+    if let Some(seconds) = cache_control.s_maxage.or(cache_control.max_age) {
+        return Some(Duration::from_secs(seconds));
+    }
+    if let Some(expires) = http_date(headers, EXPIRES) {
+        let date = http_date(headers, DATE).unwrap_or_else(SystemTime::now);
+        return Some(expires.duration_since(date).unwrap_or_default());
+    }
+    Some(Duration::from_secs(options.ttl_secs))
+}
+
+fn vary_values(
+    req_headers: &HeaderMap,
+    headers: &HeaderMap,
+) -> Vec<(HeaderName, Option<HeaderValue>)> {
+    headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .map(|name| {
+            let value = req_headers.get(&name).cloned();
+            (name, value)
+        })
+        .collect()
+}
+
+/* DISK */
+
+/// A response spilled to disk, its body is stored next to it
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    key: String,
+    path: String,
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    stored_at_ms: u64,
+    fresh_for_ms: u64,
+    vary: Vec<(String, Option<Vec<u8>>)>,
+}
+
+impl DiskEntry {
+    fn new(key: &str, entry: &CacheEntry) -> Self {
+        let millis = |duration: Duration| duration.as_millis() as u64;
+        Self {
+            key: key.to_string(),
+            path: entry.path.clone(),
+            status: entry.status.as_u16(),
+            headers: entry
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            stored_at_ms: millis(
+                entry
+                    .stored_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default(),
+            ),
+            fresh_for_ms: millis(entry.fresh_for),
+            vary: entry
+                .vary
+                .iter()
+                .map(|(name, value)| {
+                    let value = value.as_ref().map(|value| value.as_bytes().to_vec());
+                    (name.to_string(), value)
+                })
+                .collect(),
+        }
+    }
+
+    fn into_entry(self, body: Bytes) -> Option<CacheEntry> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers {
+            headers.append(
+                HeaderName::try_from(name).ok()?,
+                HeaderValue::from_bytes(&value).ok()?,
+            );
+        }
+        let vary = self
+            .vary
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Some(value) => Some(HeaderValue::from_bytes(&value).ok()?),
+                    None => None,
+                };
+                Some((HeaderName::try_from(name).ok()?, value))
+            })
+            .collect::<Option<_>>()?;
+
+        Some(CacheEntry {
+            path: self.path,
+            status: StatusCode::from_u16(self.status).ok()?,
+            headers,
+            body,
+            stored_at: SystemTime::UNIX_EPOCH + Duration::from_millis(self.stored_at_ms),
+            fresh_for: Duration::from_millis(self.fresh_for_ms),
+            vary,
+        })
+    }
+}
+
+/// Where a spilled response is on disk
+#[derive(Debug, Clone)]
+struct DiskRecord {
+    file_stem: PathBuf,
+    path: String,
+}
+
+const DISK_ENTRY_EXTENSION: &str = "cache-entry";
+const DISK_BODY_EXTENSION: &str = "cache-body";
+
+struct DiskStore {
+    index: Mutex<Lru<DiskRecord>>,
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    /// Prepares the directory, removing the responses spilled by a previous run
+    fn open(dir: &Path, max_bytes: usize) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        for file in std::fs::read_dir(dir)? {
+            let path = file?.path();
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if matches!(
+                extension,
+                Some(DISK_ENTRY_EXTENSION) | Some(DISK_BODY_EXTENSION)
+            ) {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(Self {
+            index: Mutex::new(Lru::new(usize::MAX, max_bytes)),
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn file_stem(&self, key: &str) -> PathBuf {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.dir.join(format!("{:016x}", hasher.finish()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru<DiskRecord>> {
+        self.index
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn write(&self, key: String, entry: CacheEntry) {
+        let file_stem = self.file_stem(&key);
+        let disk_entry = DiskEntry::new(&key, &entry);
+        let result = async {
+            let json = serde_json::to_vec(&disk_entry)?;
+            tokio::fs::write(file_stem.with_extension(DISK_BODY_EXTENSION), &entry.body).await?;
+            tokio::fs::write(file_stem.with_extension(DISK_ENTRY_EXTENSION), json).await?;
+            Ok::<_, StdError>(())
+        }
+        .await;
+        if let Err(err) = result {
+            warn!(error = %err, "Failed to spill cached response to disk");
+            return;
+        }
+
+        let record = DiskRecord {
+            file_stem,
+            path: entry.path,
+        };
+        let evicted = self.lock().insert(key, record, entry.body.len());
+        for (_, record) in evicted {
+            remove_files(&record.file_stem).await;
+        }
+    }
+
+    /// Reads a spilled response, removing it from disk
+    async fn take(&self, key: &str) -> Option<CacheEntry> {
+        let record = self.lock().remove(key)?;
+        let entry = async {
+            let json = tokio::fs::read(record.file_stem.with_extension(DISK_ENTRY_EXTENSION))
+                .await
+                .ok()?;
+            let disk_entry: DiskEntry = serde_json::from_slice(&json).ok()?;
+            if disk_entry.key != key {
+                return None;
+            }
+            let body = tokio::fs::read(record.file_stem.with_extension(DISK_BODY_EXTENSION))
+                .await
+                .ok()?;
+            disk_entry.into_entry(Bytes::from(body))
+        }
+        .await;
+        remove_files(&record.file_stem).await;
+        entry
+    }
+
+    async fn remove(&self, key: &str) {
+        let record = self.lock().remove(key);
+        if let Some(record) = record {
+            remove_files(&record.file_stem).await;
+        }
+    }
+
+    async fn purge(&self, prefix: Option<&str>) -> usize {
+        let purged = self
+            .lock()
+            .remove_where(|record| prefix.is_none_or(|prefix| record.path.starts_with(prefix)));
+        for (_, record) in purged.iter() {
+            remove_files(&record.file_stem).await;
+        }
+        purged.len()
+    }
+}
+
+async fn remove_files(file_stem: &Path) {
+    for extension in [DISK_ENTRY_EXTENSION, DISK_BODY_EXTENSION] {
+        let _ = tokio::fs::remove_file(file_stem.with_extension(extension)).await;
+    }
+}
+
+/* RESPONSE_CACHE */
+
+/// Value of the `X-Cache` header of responses on cached routes
+pub const X_CACHE: &str = "x-cache";
+
+/// Outcome of looking up a request in the cache
+pub enum CacheLookup {
+    /// A fresh response, served without contacting the upstream
+    Fresh(Response<Body>),
+    /// A stale response which can be revalidated with the upstream
+    Stale(Validators),
+    Miss,
+}
+
+/// Responses of cached routes, kept in memory and spilled to disk when evicted
+pub struct ResponseCache {
+    memory: Mutex<Lru<CacheEntry>>,
+    disk: Option<DiskStore>,
+    max_body_bytes: usize,
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        let disk = config.disk_dir.as_ref().and_then(|dir| {
+            match DiskStore::open(dir, config.max_disk_bytes) {
+                Ok(disk) => Some(disk),
+                Err(err) => {
+                    error!(dir = %dir.display(), error = %err, "Failed to open cache directory");
+                    None
+                }
+            }
+        });
+
+        Self {
+            memory: Mutex::new(Lru::new(config.max_entries, config.max_memory_bytes)),
+            disk,
+            max_body_bytes: config.max_body_bytes,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru<CacheEntry>> {
+        self.memory
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn get(&self, key: &str) -> Option<CacheEntry> {
+        let entry = self.lock().get(key).cloned();
+        if entry.is_some() {
+            return entry;
+        }
+
+        let entry = self.disk.as_ref()?.take(key).await?;
+        self.insert(key.to_string(), entry.clone()).await;
+        Some(entry)
+    }
+
+    async fn insert(&self, key: String, entry: CacheEntry) {
+        let size = entry.body.len();
+        let evicted = self.lock().insert(key, entry, size);
+        if let Some(disk) = &self.disk {
+            for (key, entry) in evicted {
+                disk.write(key, entry).await;
+            }
+        }
+    }
+
+    /// Looks up the response to a request
+    pub async fn lookup(&self, key: &str, req_headers: &HeaderMap) -> CacheLookup {
+        let Some(entry) = self.get(key).await else {
+            return CacheLookup::Miss;
+        };
+        if !entry.matches_vary(req_headers) {
+            return CacheLookup::Miss;
+        }
+        if entry.is_fresh() && !requires_revalidation(req_headers) {
+            return CacheLookup::Fresh(entry.respond(req_headers, "HIT"));
+        }
+        match entry.validators() {
+            Some(validators) => CacheLookup::Stale(validators),
+            None => CacheLookup::Miss,
+        }
+    }
+
+    /// Stores a response from the upstream when it is cacheable, returning the response to send
+    /// to the client
+    ///
+    /// # Arguments
+    /// * `key` - The cache key of the request, see [cache_key]
+    /// * `req` - The request, its body is ignored
+    /// * `response` - The response of the upstream
+    /// * `options` - The cache options of the route
+    pub async fn store(
+        &self,
+        key: String,
+        req: &Request<()>,
+        mut response: Response<Body>,
+        options: &RouteCacheOptions,
+    ) -> Result<Response<Body>, ReverseProxyError> {
+        response
+            .headers_mut()
+            .insert(X_CACHE, HeaderValue::from_static("MISS"));
+
+        let content_length = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        let fits = content_length.is_some_and(|length| length <= self.max_body_bytes);
+        let Some(fresh_for) = freshness(req.headers(), &response, options).filter(|_| fits) else {
+            self.remove(&key).await;
+            return Ok(response);
+        };
+
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let entry = CacheEntry {
+            path: req
+                .uri()
+                .path_and_query()
+                .map(|path_and_query| path_and_query.to_string())
+                .unwrap_or_default(),
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            stored_at: SystemTime::now(),
+            fresh_for,
+            vary: vary_values(req.headers(), &parts.headers),
+        };
+        if entry.fresh_for > Duration::ZERO || entry.validators().is_some() {
+            self.insert(key, entry).await;
+        }
+
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    /// Refreshes a cached response the upstream answered a revalidation for with a 304,
+    /// returning the response to send to the client
+    pub async fn revalidated(
+        &self,
+        key: String,
+        req: &Request<()>,
+        not_modified: &Response<Body>,
+        options: &RouteCacheOptions,
+    ) -> Option<Response<Body>> {
+        let mut entry = self.get(&key).await?;
+        for name in REVALIDATED_HEADERS {
+            if not_modified.headers().contains_key(&name) {
+                entry.headers.remove(&name);
+                for value in not_modified.headers().get_all(&name) {
+                    entry.headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+
+        let mut refreshed = Response::new(Body::empty());
+        *refreshed.status_mut() = entry.status;
+        *refreshed.headers_mut() = entry.headers.clone();
+        entry.fresh_for = freshness(req.headers(), &refreshed, options)?;
+        entry.stored_at = SystemTime::now();
+
+        let response = entry.respond(req.headers(), "REVALIDATED");
+        self.insert(key, entry).await;
+        Some(response)
+    }
+
+    async fn remove(&self, key: &str) {
+        self.lock().remove(key);
+        if let Some(disk) = &self.disk {
+            disk.remove(key).await;
+        }
+    }
+
+    /// Removes the responses whose path starts with the prefix, or all of them without one
+    pub async fn purge(&self, prefix: Option<&str>) -> usize {
+        let purged = self
+            .lock()
+            .remove_where(|entry| prefix.is_none_or(|prefix| entry.path.starts_with(prefix)))
+            .len();
+        match &self.disk {
+            Some(disk) => purged + disk.purge(prefix).await,
+            None => purged,
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use hyper::{client::HttpConnector, Body, Client, Request, Response};
+use hyper_tls::HttpsConnector;
+use std::{collections::HashMap, hash::Hash, sync::Mutex, time::Duration};
+
+use crate::{error::ReverseProxyError, route::RouteOptions};
+
+pub type HttpsClient = Client<HttpsConnector<HttpConnector>, Body>;
+
+/// Settings of a connection pool, routes with the same settings share a pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PoolSettings {
+    connect_timeout: Duration,
+    idle_timeout: Duration,
+}
+
+impl From<&RouteOptions> for PoolSettings {
+    fn from(options: &RouteOptions) -> Self {
+        Self {
+            connect_timeout: options.connect_timeout(),
+            idle_timeout: options.idle_timeout(),
+        }
+    }
+}
+
+/// Long lived clients used to send requests upstream, connections and TLS sessions are reused
+/// across requests and configuration reloads
+#[derive(Default)]
+pub struct UpstreamClients {
+    clients: Mutex<HashMap<PoolSettings, HttpsClient>>,
+}
+
+impl UpstreamClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The client pooling connections with the timeouts of the route
+    fn client(&self, options: &RouteOptions) -> HttpsClient {
+        let settings = PoolSettings::from(options);
+        self.clients
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(settings)
+            .or_insert_with(|| build_client(settings))
+            .clone()
+    }
+
+    /// Sends a request upstream, giving up after the request timeout of the route
+    ///
+    /// # Arguments
+    /// * `request` - The request to send, with its uri pointing to the upstream
+    /// * `options` - The options of the route the request was matched to
+    pub async fn send(
+        &self,
+        request: Request<Body>,
+        options: &RouteOptions,
+    ) -> Result<Response<Body>, ReverseProxyError> {
+        let timeout = options.request_timeout();
+        match tokio::time::timeout(timeout, self.client(options).request(request)).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(err)) if is_connect_timeout(&err) => {
+                Err(ReverseProxyError::UpstreamTimeout(format!(
+                    "no connection within {}ms",
+                    options.connect_timeout().as_millis()
+                )))
+            }
+            Ok(Err(err)) => Err(err.into()),
+            Err(_) => Err(ReverseProxyError::UpstreamTimeout(format!(
+                "no response within {}ms",
+                timeout.as_millis()
+            ))),
+        }
+    }
+}
+
+fn build_client(settings: PoolSettings) -> HttpsClient {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(settings.connect_timeout));
+
+    Client::builder()
+        .pool_idle_timeout(settings.idle_timeout)
+        .build(HttpsConnector::new_with_connector(http))
+}
+
+fn is_connect_timeout(err: &hyper::Error) -> bool {
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            if io_err.kind() == std::io::ErrorKind::TimedOut {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
@@ -0,0 +1,228 @@
+use async_compression::{
+    tokio::bufread::{BrotliEncoder, GzipEncoder},
+    Level,
+};
+use futures_util::TryStreamExt;
+use hyper::{
+    header::{
+        HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH,
+        CONTENT_TYPE, ETAG, VARY,
+    },
+    Body, HeaderMap, Method, Response, StatusCode,
+};
+use serde::Deserialize;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::cache::CacheControl;
+
+/* COMPRESSION_CONFIG */
+
+/// Compression of the responses of every route, see [RouteOptions::compress] to opt a route out
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Responses with a smaller `Content-Length` are sent as is, responses without one are
+    /// always compressed
+    pub min_bytes: u64,
+    /// Media types which are compressed, `text/*` matches every text type
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_bytes: 1024,
+            content_types: [
+                "text/*",
+                "application/javascript",
+                "application/json",
+                "application/xml",
+                "application/rss+xml",
+                "application/atom+xml",
+                "image/svg+xml",
+            ]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    fn allows(&self, content_type: &str) -> bool {
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(top_level) => media_type
+                    .strip_prefix(top_level)
+                    .is_some_and(|rest| rest.starts_with('/')),
+                None => allowed.eq_ignore_ascii_case(&media_type),
+            })
+    }
+}
+
+/* NEGOTIATION */
+
+/// A content coding the proxy can compress responses with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Picks the encoding with the highest quality in `Accept-Encoding`, preferring brotli on
+    /// ties
+    ///
+    /// # Arguments
+    /// * `headers` - The headers of the client request
+    pub fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+        Self::accepted(headers).first().copied()
+    }
+
+    /// The encodings the client accepts, most preferred first
+    ///
+    /// # Arguments
+    /// * `headers` - The headers of the client request
+    pub fn accepted(headers: &HeaderMap) -> Vec<Encoding> {
+        let mut brotli = None;
+        let mut gzip = None;
+        let mut wildcard = None;
+
+        for value in headers.get_all(ACCEPT_ENCODING) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for coding in value.split(',') {
+                let mut params = coding.split(';');
+                let name = params.next().unwrap_or("").trim().to_ascii_lowercase();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|quality| quality.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                match name.as_str() {
+                    "br" => brotli = Some(quality),
+                    "gzip" | "x-gzip" => gzip = Some(quality),
+                    "*" => wildcard = Some(quality),
+                    _ => {}
+                }
+            }
+        }
+
+        let brotli = brotli.or(wildcard).unwrap_or(0.0);
+        let gzip = gzip.or(wildcard).unwrap_or(0.0);
+        let mut accepted = vec![(Encoding::Brotli, brotli), (Encoding::Gzip, gzip)];
+        accepted.retain(|(_, quality)| *quality > 0.0);
+        // Stable, so brotli stays first on ties
+        accepted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        accepted.into_iter().map(|(encoding, _)| encoding).collect()
+    }
+}
+
+/* COMPRESSION */
+
+/// Brotli's default level is tuned for static files, too slow to compress on every request
+const BROTLI_LEVEL: i32 = 4;
+
+/// Adds `Accept-Encoding` to the `Vary` header unless it is already there
+pub fn vary_on_accept_encoding(headers: &mut HeaderMap) {
+    let varies = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|name| name.trim() == "*" || name.trim().eq_ignore_ascii_case("accept-encoding"));
+    if !varies {
+        headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+/// Compresses a response the upstream sent uncompressed, when the client accepts it
+///
+/// Responses whose content type is compressible always vary on `Accept-Encoding`, whether they
+/// are compressed for this client or not
+///
+/// # Arguments
+/// * `encoding` - The encoding negotiated with the client, see [Encoding::negotiate]
+/// * `method` - The method of the client request
+/// * `response` - The response of the upstream
+/// * `config` - The compression configuration
+pub fn compress_response(
+    encoding: Option<Encoding>,
+    method: &Method,
+    mut response: Response<Body>,
+    config: &CompressionConfig,
+) -> Response<Body> {
+    let headers = response.headers();
+    let status = response.status();
+    let compressible = config.enabled
+        && status != StatusCode::SWITCHING_PROTOCOLS
+        && status != StatusCode::NO_CONTENT
+        && status != StatusCode::PARTIAL_CONTENT
+        && !headers.contains_key(CONTENT_ENCODING)
+        && !CacheControl::parse(headers).no_transform
+        && headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| config.allows(content_type));
+    if !compressible {
+        return response;
+    }
+    vary_on_accept_encoding(response.headers_mut());
+
+    let too_small = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .is_some_and(|length| length < config.min_bytes);
+    let Some(encoding) = encoding else {
+        return response;
+    };
+    if too_small || method == Method::HEAD || status == StatusCode::NOT_MODIFIED {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(ACCEPT_RANGES);
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    // The compressed body is a different representation, so a strong ETag no longer holds
+    if let Some(etag) = parts.headers.get(ETAG).cloned() {
+        if !etag.as_bytes().starts_with(b"W/") {
+            let mut weak = b"W/".to_vec();
+            weak.extend_from_slice(etag.as_bytes());
+            if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                parts.headers.insert(ETAG, weak);
+            }
+        }
+    }
+
+    let reader = StreamReader::new(TryStreamExt::map_err(body, std::io::Error::other));
+    let body = match encoding {
+        Encoding::Brotli => Body::wrap_stream(ReaderStream::new(BrotliEncoder::with_quality(
+            reader,
+            Level::Precise(BROTLI_LEVEL),
+        ))),
+        Encoding::Gzip => Body::wrap_stream(ReaderStream::new(GzipEncoder::new(reader))),
+    };
+    Response::from_parts(parts, body)
+}
@@ -0,0 +1,428 @@
+use hyper::header::HeaderValue;
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing_utils::tracing::{error, info, warn};
+
+use crate::{
+    access_log::AccessLogConfig,
+    cache::CacheConfig,
+    compression::CompressionConfig,
+    error::{ReverseProxyError, StdError},
+    forwarded::TrustedProxies,
+    headers::HeaderRuleSet,
+    identity::EdgeAuthConfig,
+    limits::LimitsConfig,
+    redirect::{PathRules, RedirectRule, RewriteRule},
+    route::{RouteConfig, RouteTable},
+    static_files::StaticFilesConfig,
+    tls::TlsConfig,
+};
+
+/// Environment variable holding the path of the routing configuration file
+pub const CONFIG_PATH_ENV: &str = "REVERSE_PROXY_CONFIG";
+
+pub struct ReverseProxyConfig {
+    pub listen_addr: String,
+    /// Listener of the metrics, upstream health and cache purge endpoints, they aren't served when
+    /// not set
+    pub admin_listen_addr: Option<String>,
+    pub base_url: String,
+    pub routes: RouteTable,
+    pub trusted_proxies: TrustedProxies,
+    /// Header rules applied to every route
+    pub headers: HeaderRuleSet,
+    /// HTTPS listener, disabled when not set
+    pub tls: Option<TlsConfig>,
+    /// Limits of the response cache and token of its purge endpoint
+    pub cache: CacheConfig,
+    pub compression: CompressionConfig,
+    /// Verification of the access tokens of routes which require auth
+    pub auth: EdgeAuthConfig,
+    /// Redirects answered by the proxy and rewrites applied before routing
+    pub path_rules: PathRules,
+    /// Format and destination of the line logged per request
+    pub access_log: AccessLogConfig,
+    /// Request body sizes and slow client timeouts
+    pub limits: LimitsConfig,
+}
+
+/// The configuration file of the proxy, in TOML or YAML
+///
+/// `listen_addr`, `admin_listen_addr` and `base_url` fall back to the `SERVER_LISTEN_ADDR`,
+/// `ADMIN_LISTEN_ADDR` and `REVERSE_PROXY_URL` environment variables when they are left out of
+/// the file
+#[derive(Debug, Deserialize)]
+struct ConfigFile {
+    listen_addr: Option<String>,
+    admin_listen_addr: Option<String>,
+    base_url: Option<String>,
+    #[serde(default)]
+    routes: Vec<RouteConfig>,
+    /// Addresses or CIDR ranges of the proxies in front of this one
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+    /// Header rules applied to every route, replacing the default removal of AWS headers
+    #[serde(default = "HeaderRuleSet::global_defaults")]
+    headers: HeaderRuleSet,
+    tls: Option<TlsConfig>,
+    #[serde(default)]
+    cache: CacheConfig,
+    #[serde(default)]
+    compression: CompressionConfig,
+    #[serde(default)]
+    auth: EdgeAuthConfig,
+    #[serde(default)]
+    redirects: Vec<RedirectRule>,
+    #[serde(default)]
+    rewrites: Vec<RewriteRule>,
+    #[serde(default)]
+    access_log: AccessLogConfig,
+    #[serde(default)]
+    limits: LimitsConfig,
+}
+
+/// Format of a configuration file, decided by its extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Result<Self, ReverseProxyError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            _ => Err(ReverseProxyError::InvalidConfiguration(format!(
+                "Unsupported configuration file `{}`, expected a .toml, .yaml or .yml file",
+                path.display()
+            ))),
+        }
+    }
+}
+
+/// The admin listen address from the environment, disabled when empty
+fn admin_listen_addr_from_env() -> Option<String> {
+    std::env::var("ADMIN_LISTEN_ADDR")
+        .ok()
+        .filter(|addr| !addr.is_empty())
+}
+
+fn validate_admin_listen_addr(addr: Option<String>) -> Result<Option<String>, ReverseProxyError> {
+    if let Some(addr) = &addr {
+        addr.parse::<SocketAddr>().map_err(|_| {
+            ReverseProxyError::InvalidConfiguration(format!(
+                "Invalid admin listen address `{}`",
+                addr
+            ))
+        })?;
+    }
+    Ok(addr)
+}
+
+/// The base url is sent back in `Location` headers, so it must be a valid header value
+fn validate_base_url(base_url: String) -> Result<String, ReverseProxyError> {
+    match HeaderValue::from_str(&base_url) {
+        Ok(_) => Ok(base_url),
+        Err(_) => Err(ReverseProxyError::InvalidConfiguration(format!(
+            "Invalid base url `{}`",
+            base_url.escape_debug()
+        ))),
+    }
+}
+
+fn get_url_from_env(key: &str) -> Result<String, ReverseProxyError> {
+    std::env::var(key).map_err(|_| ReverseProxyError::MissingConfiguration(key.to_string()))
+}
+
+/// A route of the environment configuration, its variable can list several comma separated
+/// instances of the upstream
+fn route_from_env(name: &str, prefix: &str, key: &str) -> Result<RouteConfig, ReverseProxyError> {
+    let mut route = RouteConfig::new(name, prefix, "");
+    route.upstreams = get_url_from_env(key)?
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok(route)
+}
+
+/// The route of the static assets, served from `STATIC_ASSETS_DIR` when it is set instead of
+/// being proxied to `STATIC_ASSETS_URL`
+fn static_route_from_env() -> Result<RouteConfig, ReverseProxyError> {
+    match std::env::var("STATIC_ASSETS_DIR") {
+        Ok(dir) if !dir.is_empty() => Ok(RouteConfig::static_files(
+            "static_assets",
+            "/static",
+            StaticFilesConfig::new(dir),
+        )),
+        _ => route_from_env("static_assets", "/static", "STATIC_ASSETS_URL"),
+    }
+}
+
+/// Path of the configuration file, if `REVERSE_PROXY_CONFIG` is set
+pub fn config_path() -> Option<PathBuf> {
+    match std::env::var(CONFIG_PATH_ENV) {
+        Ok(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => None,
+    }
+}
+
+impl ReverseProxyConfig {
+    /// Loads the configuration from the file at `REVERSE_PROXY_CONFIG` if it is set, and from the
+    /// individual environment variables of each route otherwise
+    pub fn load() -> Result<Self, ReverseProxyError> {
+        Self::load_from(config_path().as_deref())
+    }
+
+    /// Loads the configuration from the file at `path`, or from the environment without one
+    fn load_from(path: Option<&Path>) -> Result<Self, ReverseProxyError> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None => Self::from_env(),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ReverseProxyError> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            ReverseProxyError::InvalidConfiguration(format!(
+                "Failed to read `{}`: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        Self::parse(&contents, ConfigFormat::from_path(path)?)
+    }
+
+    pub fn parse(contents: &str, format: ConfigFormat) -> Result<Self, ReverseProxyError> {
+        let file: ConfigFile = match format {
+            ConfigFormat::Toml => toml::from_str(contents)
+                .map_err(|err| ReverseProxyError::InvalidConfiguration(err.to_string()))?,
+            ConfigFormat::Yaml => serde_yaml::from_str(contents)
+                .map_err(|err| ReverseProxyError::InvalidConfiguration(err.to_string()))?,
+        };
+
+        let listen_addr = match file.listen_addr {
+            Some(listen_addr) => listen_addr,
+            None => get_url_from_env("SERVER_LISTEN_ADDR")?,
+        };
+        let admin_listen_addr = file.admin_listen_addr.or_else(admin_listen_addr_from_env);
+        let base_url = match file.base_url {
+            Some(base_url) => base_url,
+            None => get_url_from_env("REVERSE_PROXY_URL")?,
+        };
+
+        if let Some(tls) = &file.tls {
+            tls.validate()?;
+        }
+        let auth = file.auth.with_env();
+        auth.validate(&file.routes)?;
+        file.access_log.validate()?;
+        file.limits.validate()?;
+
+        Ok(Self {
+            listen_addr,
+            admin_listen_addr: validate_admin_listen_addr(admin_listen_addr)?,
+            base_url: validate_base_url(base_url)?,
+            routes: RouteTable::new(file.routes)?,
+            trusted_proxies: TrustedProxies::new(&file.trusted_proxies)?,
+            headers: file.headers,
+            tls: file.tls,
+            cache: file.cache.with_env_purge_token(),
+            compression: file.compression,
+            auth,
+            path_rules: PathRules::new(file.redirects, file.rewrites)?,
+            access_log: file.access_log,
+            limits: file.limits,
+        })
+    }
+
+    /// The original configuration of the proxy, with a fixed set of routes each read from its own
+    /// environment variable
+    pub fn from_env() -> Result<Self, ReverseProxyError> {
+        let listen_addr = get_url_from_env("SERVER_LISTEN_ADDR")?;
+        let base_url = get_url_from_env("REVERSE_PROXY_URL")?;
+
+        let routes = vec![
+            route_from_env("auth", "/auth", "AUTH_SERVICE_URL")?,
+            route_from_env("blog", "/blog", "BLOG_URL")?,
+            static_route_from_env()?,
+            route_from_env("publisher", "/publisher", "PUBLISHER_URL")?,
+            route_from_env("librarian", "/librarian", "LIBRARIAN_URL")?,
+            route_from_env("blog_htmx", "/blog-htmx", "BLOG_HTMX_URL")?,
+        ];
+
+        // Optional, comma separated
+        let trusted_proxies: Vec<String> = std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        Ok(Self {
+            listen_addr,
+            admin_listen_addr: validate_admin_listen_addr(admin_listen_addr_from_env())?,
+            base_url: validate_base_url(base_url)?,
+            routes: RouteTable::new(routes)?,
+            trusted_proxies: TrustedProxies::new(&trusted_proxies)?,
+            headers: HeaderRuleSet::global_defaults(),
+            tls: TlsConfig::from_env()?,
+            cache: CacheConfig::default().with_env_purge_token(),
+            compression: CompressionConfig::default(),
+            auth: EdgeAuthConfig::default().with_env(),
+            path_rules: PathRules::default(),
+            access_log: AccessLogConfig::from_env()?,
+            limits: LimitsConfig::default(),
+        })
+    }
+}
+
+/* SHARED_CONFIG */
+
+/// How often the configuration file is checked for changes
+pub const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The configuration of the proxy, loaded once and shared by every request
+///
+/// Reloading swaps the whole configuration atomically, requests already in flight keep the
+/// configuration they started with. An invalid configuration is never swapped in.
+#[derive(Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<ReverseProxyConfig>>>,
+    path: Option<PathBuf>,
+}
+
+impl SharedConfig {
+    /// Loads and validates the configuration, see [`ReverseProxyConfig::load`]
+    pub fn load() -> Result<Self, ReverseProxyError> {
+        let path = config_path();
+        let config = ReverseProxyConfig::load_from(path.as_deref())?;
+        Ok(Self::new(config, path))
+    }
+
+    /// Shares a configuration, reloads read it again from `path` or from the environment
+    pub fn new(config: ReverseProxyConfig, path: Option<PathBuf>) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+            path,
+        }
+    }
+
+    /// The current configuration
+    pub fn get(&self) -> Arc<ReverseProxyConfig> {
+        self.current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Loads the configuration again and swaps it in, the current configuration is kept if the
+    /// new one is invalid
+    pub fn reload(&self) -> Result<(), ReverseProxyError> {
+        let config = ReverseProxyConfig::load_from(self.path.as_deref())?;
+
+        let current = self.get();
+        if config.listen_addr != current.listen_addr {
+            warn!(
+                listen_addr = %config.listen_addr,
+                "Changing the listen address requires a restart, ignoring it"
+            );
+        }
+        if config.admin_listen_addr != current.admin_listen_addr {
+            warn!("Changing the admin listen address requires a restart, ignoring it");
+        }
+        let tls_listener = |config: &ReverseProxyConfig| {
+            config.tls.as_ref().map(|tls| {
+                (
+                    tls.listen_addr.clone(),
+                    tls.cert_path.clone(),
+                    tls.key_path.clone(),
+                )
+            })
+        };
+        if tls_listener(&config) != tls_listener(&current) {
+            warn!("Changing the TLS listener requires a restart, ignoring it");
+        }
+        if config.limits.header_read_timeout_ms != current.limits.header_read_timeout_ms {
+            warn!("Changing the header read timeout requires a restart, ignoring it");
+        }
+
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(config);
+        Ok(())
+    }
+
+    fn reload_and_log(&self, reason: &str) {
+        match self.reload() {
+            Ok(()) => info!(
+                reason,
+                routes = self.get().routes.routes().len(),
+                "Reloaded configuration"
+            ),
+            Err(err) => error!(
+                reason,
+                error = %err,
+                "Failed to reload configuration, keeping the current one"
+            ),
+        }
+    }
+
+    /// Spawns the tasks reloading the configuration on `SIGHUP`, and when the configuration file
+    /// changes
+    pub fn watch(&self) -> Result<(), StdError> {
+        let shared = self.clone();
+        let paths = self.path.iter().cloned().collect();
+        watch_files(paths, move |reason| shared.reload_and_log(reason))
+    }
+}
+
+/// Calls `on_change` on `SIGHUP`, and whenever one of the files is modified
+///
+/// Files are polled every [CONFIG_POLL_INTERVAL]
+pub fn watch_files<F>(paths: Vec<PathBuf>, on_change: F) -> Result<(), StdError>
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    let on_change = Arc::new(on_change);
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    let on_hangup = on_change.clone();
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            on_hangup("SIGHUP");
+        }
+    });
+
+    if !paths.is_empty() {
+        tokio::spawn(async move {
+            let mut last_modified: Vec<_> = paths.iter().map(|path| modified_at(path)).collect();
+            let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                let modified: Vec<_> = paths.iter().map(|path| modified_at(path)).collect();
+                if modified != last_modified {
+                    last_modified = modified;
+                    on_change("file changed");
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
@@ -0,0 +1,194 @@
+use hyper::{
+    header::{
+        HeaderValue, InvalidHeaderValue, ACCEPT, CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE,
+    },
+    http::uri::InvalidUri,
+    Body, HeaderMap, Response, StatusCode,
+};
+use shared_models::{HttpErrResponseBody, TypedErr};
+use thiserror::Error;
+
+use crate::ratelimit::RateLimitDecision;
+
+pub type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[derive(Error, Debug)]
+pub enum ReverseProxyError {
+    #[error("Missing environment variable: {0}")]
+    MissingConfiguration(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfiguration(String),
+    #[error("Invalid Uri: {0:?}")]
+    InvalidUri(#[from] InvalidUri),
+    #[error("Invalid header value: {0:?}")]
+    InvalidHeaderValue(#[from] InvalidHeaderValue),
+    #[error("Failed to send proxy request: {0:?}")]
+    ProxyRequestError(#[from] hyper::Error),
+    #[error("Upstream timed out: {0}")]
+    UpstreamTimeout(String),
+    #[error("TLS error: {0}")]
+    TlsError(String),
+    #[error("Unauthorized, invalid or missing credentials")]
+    Unauthorized,
+    #[error("Method not allowed")]
+    MethodNotAllowed,
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Too many requests, retry in {}s", .0.retry_after_secs)]
+    RateLimited(RateLimitDecision),
+    #[error("Upstream {0} is unavailable, retry in {1}s")]
+    CircuitOpen(String, u64),
+    #[error("Request body larger than {0} bytes")]
+    PayloadTooLarge(u64),
+    #[error("Request timed out: {0}")]
+    RequestTimeout(String),
+}
+
+impl TypedErr for ReverseProxyError {
+    fn error_type(&self) -> String {
+        match self {
+            Self::MissingConfiguration(_) => "MissingConfiguration".to_string(),
+            Self::InvalidConfiguration(_) => "InvalidConfiguration".to_string(),
+            Self::InvalidUri(_) => "InvalidUri".to_string(),
+            Self::InvalidHeaderValue(_) => "InvalidHeaderValue".to_string(),
+            Self::ProxyRequestError(_) => "ProxyRequestError".to_string(),
+            Self::UpstreamTimeout(_) => "UpstreamTimeout".to_string(),
+            Self::TlsError(_) => "TlsError".to_string(),
+            Self::Unauthorized => "Unauthorized".to_string(),
+            Self::MethodNotAllowed => "MethodNotAllowed".to_string(),
+            Self::InvalidRequest(_) => "InvalidRequest".to_string(),
+            Self::RateLimited(_) => "RateLimited".to_string(),
+            Self::CircuitOpen(_, _) => "CircuitOpen".to_string(),
+            Self::PayloadTooLarge(_) => "PayloadTooLarge".to_string(),
+            Self::RequestTimeout(_) => "RequestTimeout".to_string(),
+        }
+    }
+}
+
+impl ReverseProxyError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingConfiguration(_)
+            | Self::InvalidConfiguration(_)
+            | Self::TlsError(_)
+            | Self::CircuitOpen(_, _) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::InvalidUri(_) | Self::InvalidHeaderValue(_) | Self::ProxyRequestError(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::RequestTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Builds the response sent to the client for the error
+    ///
+    /// Server errors only get the reason of their status, their details such as upstream
+    /// connection errors or file paths are left to the logs
+    ///
+    /// # Arguments
+    /// * `accepts_html` - Whether the client accepts HTML, an error page is sent instead of an
+    ///   [HttpErrResponseBody] when it does
+    pub fn into_response(self, accepts_html: bool) -> Response<Body> {
+        let status = self.status_code();
+        let mut headers = HeaderMap::new();
+        match &self {
+            Self::Unauthorized => {
+                headers.insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            Self::RateLimited(decision) => decision.apply(&mut headers),
+            Self::CircuitOpen(_, retry_after_secs) => {
+                headers.insert(RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+            }
+            _ => {}
+        }
+        let body = if status.is_server_error() {
+            HttpErrResponseBody {
+                error_type: self.error_type(),
+                message: status.canonical_reason().unwrap_or("Error").to_string(),
+            }
+        } else {
+            HttpErrResponseBody::from(self)
+        };
+
+        let (content_type, body) = if accepts_html {
+            (
+                "text/html; charset=utf-8",
+                Body::from(error_page(status, &body)),
+            )
+        } else {
+            match serde_json::to_vec(&body) {
+                Ok(json) => ("application/json", Body::from(json)),
+                Err(_) => ("text/plain; charset=utf-8", Body::from(body.to_string())),
+            }
+        };
+
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        response.headers_mut().extend(headers);
+        response
+    }
+}
+
+/// Blames the client for an invalid uri or header value built from its request, a 400 rather than
+/// the 502 of the ones built from the configuration or the upstream's response
+pub fn client_error<E: Into<ReverseProxyError>>(err: E) -> ReverseProxyError {
+    match err.into() {
+        ReverseProxyError::InvalidUri(err) => {
+            ReverseProxyError::InvalidRequest(format!("Invalid uri: {}", err))
+        }
+        ReverseProxyError::InvalidHeaderValue(err) => {
+            ReverseProxyError::InvalidRequest(format!("Invalid header value: {}", err))
+        }
+        err => err,
+    }
+}
+
+/// Whether the client accepts an HTML response, according to its `Accept` header
+pub fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| {
+            let media_type = media_type.split(';').next().unwrap_or("").trim();
+            media_type.eq_ignore_ascii_case("text/html")
+        })
+}
+
+fn error_page(status: StatusCode, body: &HttpErrResponseBody) -> String {
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Error")
+    );
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>{title}</title></head>\n<body>\n\
+         <h1>{title}</h1>\n<p>{}</p>\n<pre>{}</pre>\n</body>\n</html>\n",
+        escape_html(&body.error_type),
+        escape_html(&body.message),
+    )
+}
+
+pub fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
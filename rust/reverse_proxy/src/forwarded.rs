@@ -0,0 +1,297 @@
+use hyper::{
+    header::{HeaderValue, CONNECTION, FORWARDED, HOST},
+    HeaderMap,
+};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::error::{client_error, ReverseProxyError};
+
+/* CLIENT_CONNECTION */
+
+/// The connection a request was received on
+#[derive(Debug, Clone, Copy)]
+pub struct ClientConnection {
+    /// Address of the peer, a client or a proxy in front of this one
+    pub remote_addr: SocketAddr,
+    /// Whether the connection is encrypted
+    pub tls: bool,
+}
+
+impl ClientConnection {
+    pub fn new(remote_addr: SocketAddr) -> Self {
+        Self {
+            remote_addr,
+            tls: false,
+        }
+    }
+
+    pub fn proto(&self) -> &'static str {
+        if self.tls {
+            "https"
+        } else {
+            "http"
+        }
+    }
+}
+
+/* TRUSTED_PROXIES */
+
+/// An IP address or a CIDR range, eg. `10.0.0.0/8`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn parse(input: &str) -> Result<Self, ReverseProxyError> {
+        let invalid = || {
+            ReverseProxyError::InvalidConfiguration(format!("Invalid trusted proxy `{}`", input))
+        };
+
+        let (addr, prefix_len) = match input.trim().split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (input.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                masked(u32::from(range) as u128, 32, self.prefix_len)
+                    == masked(u32::from(addr) as u128, 32, self.prefix_len)
+            }
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                masked(u128::from(range), 128, self.prefix_len)
+                    == masked(u128::from(addr), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn masked(addr: u128, bits: u8, prefix_len: u8) -> u128 {
+    if prefix_len == 0 {
+        return 0;
+    }
+    addr >> (bits - prefix_len)
+}
+
+/// IPv4 addresses mapped to IPv6 by dual stack sockets are compared as IPv4
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        addr => addr,
+    }
+}
+
+/// Proxies in front of this one, whose forwarding headers are kept
+///
+/// Forwarding headers sent by anyone else are dropped, so clients can't spoof their address
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustedProxies {
+    ranges: Vec<IpRange>,
+}
+
+impl TrustedProxies {
+    pub fn new(ranges: &[String]) -> Result<Self, ReverseProxyError> {
+        let ranges = ranges
+            .iter()
+            .map(|range| IpRange::parse(range))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { ranges })
+    }
+
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(addr))
+    }
+}
+
+/// Address of the client which made a request
+///
+/// Behind trusted proxies this is the last address of `X-Forwarded-For` which isn't a trusted
+/// proxy, otherwise it is the address of the peer
+pub fn client_ip(
+    headers: &HeaderMap,
+    connection: &ClientConnection,
+    trusted_proxies: &TrustedProxies,
+) -> IpAddr {
+    let mut client_ip = canonical(connection.remote_addr.ip());
+    if !trusted_proxies.is_trusted(client_ip) {
+        return client_ip;
+    }
+
+    let chain: Vec<Option<IpAddr>> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|addr| addr.trim().parse().ok())
+        .collect();
+    for addr in chain.into_iter().rev() {
+        // Nothing before an address which can't be parsed can be trusted
+        let Some(addr) = addr else {
+            break;
+        };
+        client_ip = canonical(addr);
+        if !trusted_proxies.is_trusted(client_ip) {
+            break;
+        }
+    }
+    client_ip
+}
+
+/* HOP_BY_HOP */
+
+/// Headers which only apply to a single connection, RFC 9110 section 7.6.1
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Removes the hop-by-hop headers, including the ones listed in `Connection`
+pub fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    for name in listed {
+        headers.remove(name.as_str());
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+}
+
+/* FORWARDED */
+
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+pub const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+pub const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// Sets the `X-Forwarded-*` and `Forwarded` headers of a request sent upstream
+///
+/// When the request comes from a trusted proxy its forwarding headers are extended, otherwise
+/// they are replaced
+///
+/// # Arguments
+/// * `headers` - Headers of the request, before the `Host` header is rewritten
+/// * `connection` - The connection the request was received on
+/// * `trusted_proxies` - Proxies allowed to set forwarding headers
+pub fn set_forwarded_headers(
+    headers: &mut HeaderMap,
+    connection: &ClientConnection,
+    trusted_proxies: &TrustedProxies,
+) -> Result<(), ReverseProxyError> {
+    let client_ip = canonical(connection.remote_addr.ip());
+    let trusted = trusted_proxies.is_trusted(client_ip);
+
+    let previous = |headers: &HeaderMap, name: &str| {
+        if !trusted {
+            return None;
+        }
+        let values: Vec<&str> = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    };
+
+    let host = headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(|host| host.to_string());
+    let proto = previous(headers, X_FORWARDED_PROTO).unwrap_or(connection.proto().to_string());
+    let forwarded_host = previous(headers, X_FORWARDED_HOST).or(host.clone());
+
+    let forwarded_for = match previous(headers, X_FORWARDED_FOR) {
+        Some(chain) => format!("{}, {}", chain, client_ip),
+        None => client_ip.to_string(),
+    };
+
+    let mut element = format!("for={}", forwarded_node(client_ip));
+    element.push_str(&format!(";proto={}", connection.proto()));
+    if let Some(host) = host.as_deref() {
+        element.push_str(&format!(";host={}", quoted(host)));
+    }
+    let forwarded = match previous(headers, FORWARDED.as_str()) {
+        Some(chain) => format!("{}, {}", chain, element),
+        None => element,
+    };
+
+    headers.insert(
+        X_FORWARDED_FOR,
+        HeaderValue::from_str(&forwarded_for).map_err(client_error)?,
+    );
+    headers.insert(
+        X_FORWARDED_PROTO,
+        HeaderValue::from_str(&proto).map_err(client_error)?,
+    );
+    match forwarded_host {
+        Some(forwarded_host) => {
+            headers.insert(
+                X_FORWARDED_HOST,
+                HeaderValue::from_str(&forwarded_host).map_err(client_error)?,
+            );
+        }
+        None => {
+            headers.remove(X_FORWARDED_HOST);
+        }
+    }
+    headers.insert(
+        FORWARDED,
+        HeaderValue::from_str(&forwarded).map_err(client_error)?,
+    );
+    Ok(())
+}
+
+/// A node of the `Forwarded` header, IPv6 addresses have to be bracketed and quoted
+fn forwarded_node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    }
+}
+
+/// Quotes a `Forwarded` parameter value when it isn't a plain token, eg. a host with a port
+fn quoted(value: &str) -> String {
+    let is_token = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+    if is_token {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
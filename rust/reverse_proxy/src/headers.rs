@@ -0,0 +1,156 @@
+use hyper::{
+    header::{HeaderName, HeaderValue, LOCATION},
+    HeaderMap,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::{error::ReverseProxyError, route::RouteConfig};
+
+/* HEADER_RULES */
+
+/// Headers added by AWS Lambda function urls which shouldn't reach clients
+const AWS_RESPONSE_HEADERS: [&str; 4] = [
+    "x-amzn-remapped-content-length",
+    "x-amzn-remapped-date",
+    "x-amzn-requestid",
+    "x-amzn-trace-id",
+];
+
+/// Changes made to the headers of a request or response, applied in the order remove, set, add
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderRules {
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, HeaderValue)>,
+    add: Vec<(HeaderName, HeaderValue)>,
+}
+
+/// [HeaderRules] as written in the configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HeaderRulesConfig {
+    /// Headers removed
+    remove: Vec<String>,
+    /// Headers replacing any existing values
+    set: BTreeMap<String, String>,
+    /// Headers appended to any existing values
+    add: BTreeMap<String, String>,
+}
+
+impl<'de> Deserialize<'de> for HeaderRules {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let config = HeaderRulesConfig::deserialize(deserializer)?;
+        Self::try_from(config).map_err(serde::de::Error::custom)
+    }
+}
+
+impl TryFrom<HeaderRulesConfig> for HeaderRules {
+    type Error = ReverseProxyError;
+
+    fn try_from(config: HeaderRulesConfig) -> Result<Self, Self::Error> {
+        let entries = |headers: BTreeMap<String, String>| {
+            headers
+                .into_iter()
+                .map(|(name, value)| Ok((header_name(&name)?, HeaderValue::try_from(value)?)))
+                .collect::<Result<Vec<_>, ReverseProxyError>>()
+        };
+
+        Ok(Self {
+            remove: config
+                .remove
+                .iter()
+                .map(|name| header_name(name))
+                .collect::<Result<_, _>>()?,
+            set: entries(config.set)?,
+            add: entries(config.add)?,
+        })
+    }
+}
+
+fn header_name(name: &str) -> Result<HeaderName, ReverseProxyError> {
+    HeaderName::try_from(name).map_err(|_| {
+        ReverseProxyError::InvalidConfiguration(format!("Invalid header name `{}`", name))
+    })
+}
+
+impl HeaderRules {
+    /// Removes the headers added by AWS Lambda function urls
+    pub fn aws_response_defaults() -> Self {
+        Self {
+            remove: AWS_RESPONSE_HEADERS
+                .iter()
+                .map(|name| HeaderName::from_static(name))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in self.remove.iter() {
+            headers.remove(name);
+        }
+        for (name, value) in self.set.iter() {
+            headers.insert(name.clone(), value.clone());
+        }
+        for (name, value) in self.add.iter() {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Header rules for requests sent upstream and responses sent back to clients
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HeaderRuleSet {
+    pub request: HeaderRules,
+    pub response: HeaderRules,
+}
+
+impl HeaderRuleSet {
+    /// The rules applied to every route when the configuration doesn't set its own
+    pub fn global_defaults() -> Self {
+        Self {
+            request: HeaderRules::default(),
+            response: HeaderRules::aws_response_defaults(),
+        }
+    }
+}
+
+/* LOCATION */
+
+/// Rewrites a `Location` header pointing at an instance of the upstream of the route to the public
+/// url of the proxy, so redirects of the upstream don't send clients around the proxy
+///
+/// # Arguments
+/// * `headers` - Headers of the upstream response
+/// * `route` - The route the request was proxied through
+/// * `base_url` - The public url of the proxy
+pub fn rewrite_location(
+    headers: &mut HeaderMap,
+    route: &RouteConfig,
+    base_url: &str,
+) -> Result<(), ReverseProxyError> {
+    let Some(location) = headers.get(LOCATION).and_then(|value| value.to_str().ok()) else {
+        return Ok(());
+    };
+    let Some(rest) = route
+        .upstreams
+        .iter()
+        .filter_map(|upstream| location.strip_prefix(upstream.as_str()))
+        .find(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+    else {
+        return Ok(());
+    };
+
+    let prefix = if route.strip_prefix && route.prefix != "/" {
+        route.prefix.as_str()
+    } else {
+        ""
+    };
+    let rewritten = format!("{}{}{}", base_url.trim_end_matches('/'), prefix, rest);
+    headers.insert(LOCATION, HeaderValue::from_str(&rewritten)?);
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use auth_models::{decode_token, TokenType};
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION},
+    HeaderMap,
+};
+use serde::Deserialize;
+
+use crate::{error::ReverseProxyError, route::RouteConfig};
+
+/* AUTH_CONFIG */
+
+/// Verification of the access tokens of the routes which require auth, see
+/// [RouteOptions::require_auth]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EdgeAuthConfig {
+    /// Secret the auth service signs its tokens with, falls back to `AUTH_SECRET`
+    pub secret: Option<String>,
+    /// Users given the `admin` role, falls back to `ADMIN_EMAIL`
+    pub admin_emails: Vec<String>,
+}
+
+impl EdgeAuthConfig {
+    /// Fills in the secret and admin from `AUTH_SECRET` and `ADMIN_EMAIL` when the configuration
+    /// has none
+    pub fn with_env(mut self) -> Self {
+        let env = |key: &str| std::env::var(key).ok().filter(|value| !value.is_empty());
+        if self.secret.is_none() {
+            self.secret = env("AUTH_SECRET");
+        }
+        if self.admin_emails.is_empty() {
+            self.admin_emails = env("ADMIN_EMAIL").into_iter().collect();
+        }
+        self
+    }
+
+    /// Every route requiring auth needs the secret to verify tokens
+    pub fn validate(&self, routes: &[RouteConfig]) -> Result<(), ReverseProxyError> {
+        let route = routes.iter().find(|route| route.options.require_auth);
+        match (route, &self.secret) {
+            (Some(route), None) => Err(ReverseProxyError::InvalidConfiguration(format!(
+                "Route `{}` requires auth but no auth secret is set, set AUTH_SECRET",
+                route.prefix
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The roles of a user, everyone is a `user` and the admins are also `admin`
+    pub fn roles(&self, email: &str) -> Vec<&'static str> {
+        let mut roles = vec!["user"];
+        if self
+            .admin_emails
+            .iter()
+            .any(|admin| admin.eq_ignore_ascii_case(email))
+        {
+            roles.push("admin");
+        }
+        roles
+    }
+}
+
+/* IDENTITY_HEADERS */
+
+/// Email of the authenticated user, set by the proxy on routes which require auth
+pub const X_USER_EMAIL: &str = "x-user-email";
+
+/// Comma separated roles of the authenticated user, set by the proxy on routes which require auth
+pub const X_USER_ROLES: &str = "x-user-roles";
+
+/// Removes the identity headers sent by the client, upstreams can only trust the proxy's
+pub fn strip_identity_headers(headers: &mut HeaderMap) {
+    headers.remove(X_USER_EMAIL);
+    headers.remove(X_USER_ROLES);
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then_some(token.trim())
+}
+
+/// Verifies the access token of a request and sets the identity headers from its claims
+///
+/// # Arguments
+/// * `headers` - The headers of the client request, with the identity headers already stripped
+/// * `config` - The auth configuration
+pub fn authenticate(
+    headers: &mut HeaderMap,
+    config: &EdgeAuthConfig,
+) -> Result<(), ReverseProxyError> {
+    let secret = config
+        .secret
+        .as_deref()
+        .ok_or(ReverseProxyError::Unauthorized)?;
+    let token = bearer_token(headers).ok_or(ReverseProxyError::Unauthorized)?;
+    let claims = decode_token(token, secret).map_err(|_| ReverseProxyError::Unauthorized)?;
+    if claims.token_type != TokenType::Access {
+        return Err(ReverseProxyError::Unauthorized);
+    }
+
+    let email = HeaderValue::from_str(&claims.sub).map_err(|_| ReverseProxyError::Unauthorized)?;
+    let roles = HeaderValue::from_str(&config.roles(&claims.sub).join(","))?;
+    headers.insert(X_USER_EMAIL, email);
+    headers.insert(X_USER_ROLES, roles);
+    Ok(())
+}
@@ -0,0 +1,42 @@
+mod access_log;
+mod balancer;
+mod breaker;
+mod cache;
+mod client;
+mod compression;
+mod config;
+mod error;
+mod forwarded;
+mod headers;
+mod identity;
+mod limits;
+mod metrics;
+mod path;
+mod proxy;
+mod ratelimit;
+mod redirect;
+mod route;
+mod server;
+mod static_files;
+mod tls;
+mod upgrade;
+
+pub use access_log::AccessLogEntry;
+pub use balancer::InstanceStatus;
+pub use breaker::{CircuitBreakerOptions, CircuitBreakers, CircuitState};
+pub use cache::PurgeCacheResponse;
+pub use compression::Encoding;
+pub use config::{ConfigFormat, ReverseProxyConfig, SharedConfig};
+pub use error::{client_error, ReverseProxyError, StdError};
+pub use forwarded::{
+    client_ip, set_forwarded_headers, strip_hop_by_hop_headers, ClientConnection, IpRange,
+    TrustedProxies,
+};
+pub use headers::rewrite_location;
+pub use limits::LimitsConfig;
+pub use path::normalize_path;
+pub use proxy::{admin_request, reverse_proxy, ProxyState, UpstreamHealth};
+pub use ratelimit::{MemoryRateLimitStore, RateLimitKey, RateLimitOptions};
+pub use route::{ClassifiedRoute, NonProxyRoute, RouteConfig};
+pub use server::{serve, serve_admin, serve_tls};
+pub use tls::ReloadingCertResolver;
@@ -0,0 +1,171 @@
+use hyper::{body::HttpBody, header::CONTENT_LENGTH, Body, Request};
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing_utils::tracing::warn;
+
+use crate::{
+    error::{ReverseProxyError, StdError},
+    route::RouteOptions,
+};
+
+/* LIMITS_CONFIG */
+
+/// Limits protecting the proxy from clients which send too much, or too slowly
+///
+/// The header read timeout is set on the listeners at startup, later reloads don't change it
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Largest request body of the routes which don't set [RouteOptions::max_body_bytes]
+    pub max_body_bytes: u64,
+    /// Time allowed for a client to send the headers of a request, idle keep-alive connections
+    /// are closed after it too
+    pub header_read_timeout_ms: u64,
+    /// Slowest rate a request body can be sent at, in bytes per second
+    pub min_body_bytes_per_sec: u64,
+    /// Time a request body gets before the minimum rate applies, so small bodies and slow starts
+    /// aren't cut short
+    pub min_body_rate_grace_ms: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 10 * 1024 * 1024,
+            header_read_timeout_ms: 10_000,
+            min_body_bytes_per_sec: 500,
+            min_body_rate_grace_ms: 20_000,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn validate(&self) -> Result<(), ReverseProxyError> {
+        if self.max_body_bytes == 0
+            || self.header_read_timeout_ms == 0
+            || self.min_body_bytes_per_sec == 0
+        {
+            return Err(ReverseProxyError::InvalidConfiguration(
+                "limits max_body_bytes, header_read_timeout_ms and min_body_bytes_per_sec must be \
+                 greater than 0"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn header_read_timeout(&self) -> Duration {
+        Duration::from_millis(self.header_read_timeout_ms)
+    }
+
+    /// The limits of the request bodies of a route
+    pub fn body_limits(&self, options: &RouteOptions) -> BodyLimits {
+        BodyLimits {
+            max_bytes: options.max_body_bytes.unwrap_or(self.max_body_bytes),
+            min_bytes_per_sec: self.min_body_bytes_per_sec,
+            grace: Duration::from_millis(self.min_body_rate_grace_ms),
+        }
+    }
+}
+
+/* BODY_LIMITS */
+
+/// Limits of a request body, see [LimitsConfig]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyLimits {
+    pub max_bytes: u64,
+    pub min_bytes_per_sec: u64,
+    pub grace: Duration,
+}
+
+impl BodyLimits {
+    /// Time allowed to receive a body, the grace period extended by the bytes already received
+    fn allowed(&self, received: u64) -> Duration {
+        self.grace + Duration::from_secs_f64(received as f64 / self.min_bytes_per_sec as f64)
+    }
+}
+
+/// Why the body of a request was cut short, kept in the extensions of the request so the proxy
+/// answers with a 413 or a 408 instead of blaming the upstream
+#[derive(Debug, Clone, Default)]
+pub struct BodyLimitViolation(Arc<Mutex<Option<ReverseProxyError>>>);
+
+impl BodyLimitViolation {
+    fn set(&self, err: ReverseProxyError) {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(err);
+    }
+
+    /// The error the body was cut short with, if it was
+    pub fn take(&self) -> Option<ReverseProxyError> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+    }
+}
+
+/// Enforces the limits of a route on the body of a request
+///
+/// Bodies with a `Content-Length` over the maximum are rejected straight away. Other bodies are
+/// cut short once they go over it, or fall below the minimum rate, see [BodyLimitViolation].
+/// Bodies which are already complete, such as the empty body of a GET, can't go over the maximum
+/// or stall, so they aren't wrapped.
+pub fn limit_request_body(
+    req: &mut Request<Body>,
+    limits: BodyLimits,
+) -> Result<(), ReverseProxyError> {
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > limits.max_bytes) {
+        return Err(ReverseProxyError::PayloadTooLarge(limits.max_bytes));
+    }
+    if req.body().is_end_stream() {
+        return Ok(());
+    }
+
+    let violation = BodyLimitViolation::default();
+    req.extensions_mut().insert(violation.clone());
+
+    let started = tokio::time::Instant::now();
+    let body = std::mem::take(req.body_mut());
+    let stream = futures_util::stream::try_unfold((body, 0u64), move |(mut body, received)| {
+        let violation = violation.clone();
+        async move {
+            let deadline = started + limits.allowed(received);
+            let chunk = tokio::select! {
+                chunk = body.data() => chunk,
+                _ = tokio::time::sleep_until(deadline) => {
+                    let err = ReverseProxyError::RequestTimeout(format!(
+                        "request body slower than {} bytes per second",
+                        limits.min_bytes_per_sec
+                    ));
+                    warn!(received, "Request body too slow, cutting it short");
+                    violation.set(err);
+                    return Err(StdError::from("Request body too slow"));
+                }
+            };
+            let Some(chunk) = chunk else {
+                return Ok(None);
+            };
+            let chunk = chunk?;
+            let received = received + chunk.len() as u64;
+            if received > limits.max_bytes {
+                warn!(received, "Request body too large, cutting it short");
+                violation.set(ReverseProxyError::PayloadTooLarge(limits.max_bytes));
+                return Err(StdError::from("Request body too large"));
+            }
+            Ok(Some((chunk, (body, received))))
+        }
+    });
+    *req.body_mut() = Body::wrap_stream(stream);
+    Ok(())
+}
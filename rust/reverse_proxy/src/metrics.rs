@@ -0,0 +1,86 @@
+use metrics_utils::prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGaugeVec,
+};
+use once_cell::sync::Lazy;
+
+/// Latency of requests sent to upstreams, by proxy route
+pub static UPSTREAM_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "reverse_proxy_upstream_duration_seconds",
+        "Latency of proxied requests to upstreams, in seconds",
+        &["upstream"]
+    )
+    .expect("Failed to register reverse_proxy_upstream_duration_seconds")
+});
+
+/// Requests to upstreams which failed without a response, by proxy route
+pub static UPSTREAM_ERRORS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "reverse_proxy_upstream_errors_total",
+        "Total proxied requests which failed to get a response from upstreams",
+        &["upstream"]
+    )
+    .expect("Failed to register reverse_proxy_upstream_errors_total")
+});
+
+/// Lookups of proxied requests in the response cache, by proxy route and result
+pub static CACHE_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "reverse_proxy_cache_requests_total",
+        "Total proxied requests on cached routes, by result",
+        &["upstream", "result"]
+    )
+    .expect("Failed to register reverse_proxy_cache_requests_total")
+});
+
+/// Requests sent again to another instance of their upstream, by proxy route
+pub static UPSTREAM_RETRIES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "reverse_proxy_upstream_retries_total",
+        "Total proxied requests retried on another upstream instance",
+        &["upstream"]
+    )
+    .expect("Failed to register reverse_proxy_upstream_retries_total")
+});
+
+/// Whether each upstream instance receives traffic, 0 when unhealthy or ejected
+pub static UPSTREAM_INSTANCE_AVAILABLE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "reverse_proxy_upstream_instance_available",
+        "Whether the upstream instance is healthy and not ejected",
+        &["instance"]
+    )
+    .expect("Failed to register reverse_proxy_upstream_instance_available")
+});
+
+/// State of the circuit of each upstream instance, 0 closed, 1 open and 2 half open
+pub static UPSTREAM_CIRCUIT_STATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "reverse_proxy_upstream_circuit_state",
+        "State of the circuit breaker of the upstream instance, 0 closed, 1 open and 2 half open",
+        &["instance"]
+    )
+    .expect("Failed to register reverse_proxy_upstream_circuit_state")
+});
+
+/// Requests failed fast because the circuits of every instance of their upstream were open, by
+/// proxy route
+pub static CIRCUIT_OPEN_REJECTIONS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "reverse_proxy_circuit_open_rejections_total",
+        "Total proxied requests rejected while the circuits of every upstream instance were open",
+        &["upstream"]
+    )
+    .expect("Failed to register reverse_proxy_circuit_open_rejections_total")
+});
+
+/// Requests rejected by the rate limit of their route, by proxy route
+pub static RATE_LIMITED_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "reverse_proxy_rate_limited_total",
+        "Total requests rejected by rate limits",
+        &["upstream"]
+    )
+    .expect("Failed to register reverse_proxy_rate_limited_total")
+});
@@ -0,0 +1,141 @@
+use hyper::{Body, Request, Uri};
+
+use crate::error::{client_error, ReverseProxyError};
+
+/* PERCENT_ENCODING */
+
+/// Characters which never need to be percent-encoded, RFC 3986 section 2.3
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// The byte of a percent-encoding, from the two hex digits following the `%`
+fn decode_hex(hex: &[u8]) -> Option<u8> {
+    let digit = |byte: u8| (byte as char).to_digit(16);
+    match hex {
+        [high, low] => Some((digit(*high)? * 16 + digit(*low)?) as u8),
+        _ => None,
+    }
+}
+
+fn invalid_path(reason: &str) -> ReverseProxyError {
+    ReverseProxyError::InvalidRequest(format!("Invalid path, {}", reason))
+}
+
+/// Decodes the percent-encoded unreserved characters of a path and uppercases the other
+/// percent-encodings, RFC 3986 sections 6.2.2.1 and 6.2.2.2
+fn normalize_percent_encoding(path: &str) -> Result<String, ReverseProxyError> {
+    let bytes = path.as_bytes();
+    let mut normalized = String::with_capacity(path.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != b'%' {
+            normalized.push(bytes[index] as char);
+            index += 1;
+            continue;
+        }
+
+        let decoded = bytes
+            .get(index + 1..index + 3)
+            .and_then(decode_hex)
+            .ok_or_else(|| invalid_path("malformed percent-encoding"))?;
+        if decoded == 0 {
+            return Err(invalid_path("encoded NUL"));
+        }
+        if is_unreserved(decoded) {
+            normalized.push(decoded as char);
+        } else {
+            normalized.push_str(&format!("%{:02X}", decoded));
+        }
+        index += 3;
+    }
+    Ok(normalized)
+}
+
+/// Decodes every percent-encoding of a path segment, malformed ones are kept as they are
+pub fn percent_decode(segment: &str) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(segment.len());
+    let bytes = segment.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes.get(index + 1..index + 3).and_then(decode_hex) {
+            Some(byte) if bytes[index] == b'%' => {
+                decoded.push(byte);
+                index += 3;
+            }
+            _ => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Whether a segment turns into a `..` segment once its encoded separators are decoded, which
+/// upstreams decoding `%2F` or accepting `\` would resolve
+fn hides_traversal(segment: &str) -> bool {
+    percent_decode(segment)
+        .split(|byte| *byte == b'/' || *byte == b'\\')
+        .any(|part| part == b"..")
+}
+
+/* PATH_NORMALISATION */
+
+/// Normalises the path of a request before it is routed, RFC 3986 section 6.2.2
+///
+/// Percent-encodings are normalised, empty segments are merged and dot segments are removed.
+/// Paths climbing above the root, or hiding dot segments behind encoded separators, are rejected
+/// rather than resolved, as upstreams may resolve them differently
+pub fn normalize_path(path: &str) -> Result<String, ReverseProxyError> {
+    // Only origin-form paths are routed, eg. not the `*` of `OPTIONS *`
+    if !path.starts_with('/') {
+        return Ok(path.to_string());
+    }
+
+    let path = normalize_percent_encoding(path)?;
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path.split('/').skip(1) {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    return Err(invalid_path("traversal above the root"));
+                }
+            }
+            _ if hides_traversal(segment) => {
+                return Err(invalid_path("encoded traversal"));
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// Replaces the path of a request with its normalised form, the query is kept exactly as sent
+pub fn normalize_request_path(req: &mut Request<Body>) -> Result<(), ReverseProxyError> {
+    let path = normalize_path(req.uri().path())?;
+    if path == req.uri().path() {
+        return Ok(());
+    }
+    set_request_path(req, path)
+}
+
+/// Replaces the path of a request, the query is kept exactly as sent
+pub fn set_request_path(req: &mut Request<Body>, path: String) -> Result<(), ReverseProxyError> {
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().map_err(client_error)?);
+    *req.uri_mut() = Uri::from_parts(parts).map_err(|_| invalid_path("unparseable uri"))?;
+    Ok(())
+}
@@ -0,0 +1,895 @@
+use hyper::{
+    body::{Bytes, HttpBody},
+    header::{HeaderName, HeaderValue, AUTHORIZATION, HOST, LOCATION, REFERER, USER_AGENT},
+    Body, Method, Request, Response, StatusCode, Uri,
+};
+use serde::{Deserialize, Serialize};
+use shared_models::{build_info, DependencyCheck, LivenessResponse, ReadinessResponse};
+use shutdown_utils::Shutdown;
+use std::{
+    convert::Infallible,
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant, SystemTime},
+};
+use tracing_utils::{
+    tracing::{error, info, info_span, warn, Instrument},
+    REQUEST_ID_HEADER,
+};
+
+use crate::{
+    access_log::{
+        count_request_body, log_on_completion, AccessLog, AccessLogEntry, UpstreamTiming,
+    },
+    balancer::{InstanceStatus, SelectedUpstream, Upstreams},
+    breaker::{circuit_open_response, CircuitBreakers},
+    cache::{
+        cache_key, CacheLookup, PurgeCacheRequest, PurgeCacheResponse, ResponseCache,
+        RouteCacheOptions,
+    },
+    client::UpstreamClients,
+    compression::{compress_response, Encoding},
+    config::{ReverseProxyConfig, SharedConfig},
+    error::{accepts_html, client_error, ReverseProxyError},
+    forwarded::{client_ip, set_forwarded_headers, strip_hop_by_hop_headers, ClientConnection},
+    headers::rewrite_location,
+    identity::{authenticate, strip_identity_headers},
+    limits::{limit_request_body, BodyLimitViolation},
+    metrics::{
+        CACHE_REQUESTS_TOTAL, CIRCUIT_OPEN_REJECTIONS_TOTAL, RATE_LIMITED_TOTAL,
+        UPSTREAM_DURATION_SECONDS, UPSTREAM_ERRORS_TOTAL, UPSTREAM_RETRIES_TOTAL,
+    },
+    path::normalize_request_path,
+    ratelimit::{rate_limit_key, MemoryRateLimitStore, RateLimitDecision, RateLimitStore},
+    redirect::{route_request, RedirectTarget},
+    route::{ClassifiedRoute, NonProxyRoute, ProxyRoute},
+    static_files::serve_static,
+    upgrade::{restore_upgrade_headers, splice, upgrade_protocol},
+};
+
+/* PROXY_STATE */
+
+/// State shared by every request handled by the proxy
+#[derive(Clone)]
+pub struct ProxyState {
+    pub config: SharedConfig,
+    pub clients: Arc<UpstreamClients>,
+    pub cache: Arc<ResponseCache>,
+    pub rate_limits: Arc<dyn RateLimitStore>,
+    pub upstreams: Arc<Upstreams>,
+    pub breakers: Arc<CircuitBreakers>,
+    pub access_log: Arc<AccessLog>,
+    /// Stops the servers of the proxy and fails its readiness once shutdown starts
+    pub shutdown: Shutdown,
+}
+
+impl ProxyState {
+    /// The cache and the access log are set up from the configuration at startup, later reloads
+    /// don't change them
+    pub fn new(config: SharedConfig) -> Self {
+        let cache = ResponseCache::new(&config.get().cache);
+        let access_log = AccessLog::new(&config.get().access_log);
+        Self {
+            config,
+            clients: Arc::new(UpstreamClients::new()),
+            cache: Arc::new(cache),
+            rate_limits: Arc::new(MemoryRateLimitStore::new()),
+            upstreams: Arc::new(Upstreams::new()),
+            breakers: Arc::new(CircuitBreakers::new()),
+            access_log: Arc::new(access_log),
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    /// Starts the active health checks of the upstream instances in the background
+    pub fn start_health_checks(&self) {
+        self.upstreams
+            .watch(self.config.clone(), self.clients.clone());
+    }
+
+    /// Shuts the proxy down with another handle, eg. one listening for signals
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Keeps the rate limit buckets in another store, eg. one shared by several proxies
+    pub fn with_rate_limit_store(mut self, store: Arc<dyn RateLimitStore>) -> Self {
+        self.rate_limits = store;
+        self
+    }
+}
+
+/* GET_PROXIED_REQUEST */
+
+fn get_proxied_request(
+    request: Request<Body>,
+    route: &ProxyRoute,
+    upstream: &str,
+    connection: &ClientConnection,
+    config: &ReverseProxyConfig,
+) -> Result<Request<Body>, ReverseProxyError> {
+    let proxied_uri = route.proxied_uri_on(upstream);
+    let (mut parts, body) = request.into_parts();
+
+    // Fragments are never sent to servers, the query is forwarded exactly as received
+    let new_uri = match parts.uri.query() {
+        Some(query) => format!("{}?{}", proxied_uri, query),
+        None => proxied_uri,
+    };
+
+    // The path and query come from the client, the upstream from the configuration
+    parts.uri = new_uri.parse().map_err(client_error)?;
+
+    let upgrade = upgrade_protocol(&parts.headers);
+    strip_hop_by_hop_headers(&mut parts.headers);
+    if let Some(protocol) = upgrade {
+        restore_upgrade_headers(&mut parts.headers, protocol);
+    }
+    set_forwarded_headers(&mut parts.headers, connection, &config.trusted_proxies)?;
+    config.headers.request.apply(&mut parts.headers);
+    route
+        .route
+        .options
+        .headers
+        .request
+        .apply(&mut parts.headers);
+
+    if !route.route.options.preserve_host {
+        let host = parts.uri.host().unwrap_or("");
+        parts.headers.insert("host", HeaderValue::from_str(host)?);
+    }
+    Ok(Request::from_parts(parts, body))
+}
+
+/* HANDLE_NON_PROXY_ROUTE */
+
+fn handle_non_proxy_route(
+    route: NonProxyRoute,
+    config: &ReverseProxyConfig,
+) -> Result<Response<Body>, ReverseProxyError> {
+    match route {
+        NonProxyRoute::NotFound => Ok(not_found_route()),
+        NonProxyRoute::HealthCheck => Ok(healthcheck_route()),
+        NonProxyRoute::Livez => Ok(livez_route()),
+        // Handled in `handle_route` as it needs the proxy state
+        NonProxyRoute::Readyz => Ok(not_found_route()),
+        NonProxyRoute::Root => Ok(root(&config.base_url)),
+        NonProxyRoute::Redirect(target) => Ok(redirect(target)),
+    }
+}
+
+/* HTTPS_REDIRECT */
+
+/// Redirects plain HTTP requests to HTTPS when configured, probes are still answered over HTTP
+fn https_redirect(
+    route: &ClassifiedRoute,
+    req: &Request<Body>,
+    connection: &ClientConnection,
+    config: &ReverseProxyConfig,
+) -> Option<Response<Body>> {
+    let tls = config.tls.as_ref()?;
+    if !tls.redirect_http || connection.tls || route.is_probe() {
+        return None;
+    }
+
+    let base_url: Option<Uri> = config.base_url.parse().ok();
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            base_url
+                .as_ref()?
+                .authority()
+                .map(|authority| authority.as_str())
+        })?;
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+    let location = tls.https_url(host, path_and_query)?;
+
+    Response::builder()
+        .status(StatusCode::PERMANENT_REDIRECT)
+        .header(LOCATION, location)
+        .body(Body::empty())
+        .ok()
+}
+
+/* HANDLE_PROXY_ROUTE_HELPERS */
+
+fn healthcheck_route() -> Response<Body> {
+    Response::builder()
+        .status(200)
+        .header("content-type", "text/html")
+        .body(Body::from("OK"))
+        .expect("Failed to build healthcheck response")
+}
+
+fn json_response<T: serde::Serialize>(status: u16, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::to_vec(body).expect("Failed to serialize response body"),
+        ))
+        .expect("Failed to build json response")
+}
+
+fn livez_route() -> Response<Body> {
+    json_response(200, &LivenessResponse::new(build_info!()))
+}
+
+/// The proxy is ready when its routing table has routes, and until it starts shutting down
+fn readyz_route(config: &ReverseProxyConfig, shutdown: &Shutdown) -> Response<Body> {
+    let started = Instant::now();
+    let result = if config.routes.routes().is_empty() {
+        Err("No routes configured")
+    } else {
+        Ok(())
+    };
+    let mut checks = vec![DependencyCheck::from_result(
+        "configuration",
+        started,
+        result,
+    )];
+    if shutdown.is_draining() {
+        checks.push(DependencyCheck::draining());
+    }
+
+    let response = ReadinessResponse::new(build_info!(), checks);
+
+    let status = if response.is_ready() { 200 } else { 503 };
+    json_response(status, &response)
+}
+
+/// State of an upstream, as shown by the upstream health endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UpstreamHealth {
+    pub name: String,
+    pub instances: Vec<InstanceStatus>,
+}
+
+/// The instances of every upstream with their circuits, always a 200 as an open circuit doesn't
+/// make the proxy itself unready
+fn upstream_health_route(config: &ReverseProxyConfig, state: &ProxyState) -> Response<Body> {
+    let upstreams: Vec<UpstreamHealth> = config
+        .routes
+        .routes()
+        .iter()
+        .filter(|route| route.static_files.is_none())
+        .map(|route| UpstreamHealth {
+            name: route.name().to_string(),
+            instances: state.upstreams.status(route, &state.breakers),
+        })
+        .collect();
+    json_response(200, &upstreams)
+}
+
+fn metrics_route() -> Response<Body> {
+    Response::builder()
+        .status(200)
+        .header("content-type", metrics_utils::METRICS_CONTENT_TYPE)
+        .body(Body::from(metrics_utils::encode_metrics()))
+        .expect("Failed to build metrics response")
+}
+
+fn not_found_route() -> Response<Body> {
+    Response::builder()
+        .status(404)
+        .header("content-type", "text/html")
+        .body(Body::from("Not Found"))
+        .expect("Failed to build not found response")
+}
+
+fn redirect(target: RedirectTarget) -> Response<Body> {
+    Response::builder()
+        .status(target.status)
+        .header(LOCATION, target.location)
+        .body(Body::empty())
+        .expect("Failed to build redirect response")
+}
+
+fn root(base_url: &str) -> Response<Body> {
+    Response::builder()
+        .status(301)
+        .header("Location", &format!("{}/blog", base_url))
+        .body(Body::empty())
+        .expect("Failed to build root response")
+}
+
+/* SEND_REQUEST */
+
+async fn send_request(
+    clients: &UpstreamClients,
+    request: Request<Body>,
+    route: &ProxyRoute,
+    config: &ReverseProxyConfig,
+) -> Result<Response<Body>, ReverseProxyError> {
+    let options = &route.route.options;
+    let mut response = clients.send(request, options).await?;
+    let status = response.status();
+    let headers = response.headers_mut();
+
+    let upgrade = upgrade_protocol(headers).filter(|_| status == StatusCode::SWITCHING_PROTOCOLS);
+    strip_hop_by_hop_headers(headers);
+    if let Some(protocol) = upgrade {
+        restore_upgrade_headers(headers, protocol);
+    }
+    config.headers.response.apply(headers);
+    options.headers.response.apply(headers);
+    if options.rewrite_location {
+        rewrite_location(headers, &route.route, &config.base_url)?;
+    }
+
+    Ok(response)
+}
+
+/* REVERSE_PROXY_FUNCTION */
+
+/// Handles a request within a span carrying its request id
+///
+/// The `X-Request-Id` header of the request is forwarded if valid and generated otherwise, it is
+/// set on both the proxied request and the response so a request can be followed end to end
+///
+/// The path is normalised before the request is routed, see
+/// [normalize_path](crate::path::normalize_path), and the redirect and rewrite rules are applied
+/// to it, see [route_request]
+///
+/// Request bodies which are too large or too slow are cut short, see [limit_request_body]
+///
+/// The request is handled with the configuration current when it arrived, even if it is reloaded
+/// in the meantime. Errors are answered with a 502, 503 or 504 response rather than dropping the
+/// connection.
+///
+/// Every request is written to the access log once its response has been sent, see [AccessLog]
+pub async fn reverse_proxy(
+    state: ProxyState,
+    connection: ClientConnection,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let request_id = tracing_utils::request_id_or_new(
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok()),
+    );
+    // Request ids are validated to be visible ascii, so they are always valid header values
+    let request_id_header = HeaderValue::from_str(&request_id).ok();
+    if let Some(header) = &request_id_header {
+        req.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+    }
+
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+
+    async move {
+        let started = Instant::now();
+        let timestamp = SystemTime::now();
+        let method = req.method().to_string();
+        let config = state.config.get();
+
+        let bytes_in = Arc::new(AtomicU64::new(0));
+        let access_log_entry = state.access_log.enabled().then(|| {
+            let header = |name: HeaderName| {
+                req.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            AccessLogEntry {
+                timestamp,
+                client_ip: client_ip(req.headers(), &connection, &config.trusted_proxies),
+                method: method.clone(),
+                target: req
+                    .uri()
+                    .path_and_query()
+                    .map_or("/", |path_and_query| path_and_query.as_str())
+                    .to_string(),
+                version: format!("{:?}", req.version()),
+                status: 0,
+                bytes_in: 0,
+                bytes_out: 0,
+                referer: header(REFERER),
+                user_agent: header(USER_AGENT),
+                request_id: request_id.clone(),
+                route: String::new(),
+                upstream: None,
+                upstream_latency: None,
+                latency: Duration::ZERO,
+            }
+        });
+        if access_log_entry.is_some() {
+            count_request_body(&mut req, bytes_in.clone());
+        }
+        let route = normalize_request_path(&mut req).and_then(|_| route_request(&mut req, &config));
+        let route_name = route
+            .as_ref()
+            .map_or("invalid_path", |route| route.name())
+            .to_string();
+        let accepts_html = accepts_html(req.headers());
+
+        let handled = match route {
+            Ok(route) => handle_route(route, req, &connection, &config, &state).await,
+            Err(err) => Err(err),
+        };
+        let mut response = match handled {
+            Ok(response) => response,
+            Err(err) => {
+                match err {
+                    ReverseProxyError::UpstreamTimeout(_) => {
+                        warn!(error = %err, "Failed to handle request")
+                    }
+                    _ => error!(error = %err, "Failed to handle request"),
+                }
+                err.into_response(accepts_html)
+            }
+        };
+        if let Some(header) = request_id_header {
+            response.headers_mut().insert(REQUEST_ID_HEADER, header);
+        }
+
+        let status = response.status().as_u16();
+        let elapsed = started.elapsed();
+        metrics_utils::observe_request(&method, &route_name, status, elapsed);
+        info!(
+            status,
+            route = %route_name,
+            latency_ms = elapsed.as_millis() as u64,
+            "Request completed"
+        );
+
+        let Some(mut entry) = access_log_entry else {
+            return Ok(response);
+        };
+        entry.status = status;
+        entry.route = route_name;
+        if let Some(timing) = response.extensions().get::<UpstreamTiming>() {
+            entry.upstream = Some(timing.upstream.clone());
+            entry.upstream_latency = Some(timing.latency);
+        }
+        Ok(log_on_completion(
+            state.access_log.clone(),
+            response,
+            entry,
+            bytes_in,
+            started,
+        ))
+    }
+    .instrument(span)
+    .await
+}
+
+async fn handle_route(
+    route: ClassifiedRoute,
+    req: Request<Body>,
+    connection: &ClientConnection,
+    config: &ReverseProxyConfig,
+    state: &ProxyState,
+) -> Result<Response<Body>, ReverseProxyError> {
+    if let Some(response) = https_redirect(&route, &req, connection, config) {
+        return Ok(response);
+    }
+
+    match route {
+        ClassifiedRoute::Proxy(proxy_route) => {
+            let mut req = req;
+            strip_identity_headers(req.headers_mut());
+            let authenticated = if proxy_route.route.options.require_auth {
+                authenticate(req.headers_mut(), &config.auth)
+            } else {
+                Ok(())
+            };
+            // Failed authentications still take a token, from the bucket of the client's address
+            // as they carry no user, so tokens can't be guessed past the limit
+            let rate_limit =
+                check_rate_limit(&req, &proxy_route, connection, config, state).await?;
+            authenticated?;
+            limit_request_body(
+                &mut req,
+                config.limits.body_limits(&proxy_route.route.options),
+            )?;
+
+            // Served as stored on disk, so ranges and etags refer to the bytes sent
+            if let Some(static_files) = &proxy_route.route.static_files {
+                let mut response = serve_static(&req, &proxy_route, static_files).await?;
+                let headers = response.headers_mut();
+                config.headers.response.apply(headers);
+                proxy_route.route.options.headers.response.apply(headers);
+                if let Some(decision) = rate_limit {
+                    decision.apply(headers);
+                }
+                return Ok(response);
+            }
+
+            let encoding = Encoding::negotiate(req.headers());
+            let method = req.method().clone();
+            let cache_options = proxy_route.route.options.cache.as_ref();
+            let mut response = match (cache_options, cache_key(&req)) {
+                (Some(options), Some(key)) => {
+                    proxy_cached(req, key, options, &proxy_route, connection, config, state).await
+                }
+                (Some(_), None) => {
+                    CACHE_REQUESTS_TOTAL
+                        .with_label_values(&[proxy_route.name(), "bypass"])
+                        .inc();
+                    proxy(req, &proxy_route, connection, config, state).await
+                }
+                (None, _) => proxy(req, &proxy_route, connection, config, state).await,
+            }?;
+            if let Some(decision) = rate_limit {
+                decision.apply(response.headers_mut());
+            }
+
+            // Compressed after the cache, which keeps a single uncompressed copy of each response
+            if !proxy_route.route.options.compress {
+                return Ok(response);
+            }
+            Ok(compress_response(
+                encoding,
+                &method,
+                response,
+                &config.compression,
+            ))
+        }
+        ClassifiedRoute::NonProxy(NonProxyRoute::Readyz) => {
+            Ok(readyz_route(config, &state.shutdown))
+        }
+        ClassifiedRoute::NonProxy(non_proxy_route) => {
+            handle_non_proxy_route(non_proxy_route, config)
+        }
+    }
+}
+
+/// Takes a token from the bucket of a request on a rate limited route, rejecting the request when
+/// the bucket is empty
+async fn check_rate_limit(
+    req: &Request<Body>,
+    proxy_route: &ProxyRoute,
+    connection: &ClientConnection,
+    config: &ReverseProxyConfig,
+    state: &ProxyState,
+) -> Result<Option<RateLimitDecision>, ReverseProxyError> {
+    let Some(limit) = &proxy_route.route.options.rate_limit else {
+        return Ok(None);
+    };
+
+    let key = rate_limit_key(
+        req.headers(),
+        proxy_route,
+        limit,
+        connection,
+        &config.trusted_proxies,
+    );
+    let decision = state.rate_limits.acquire(&key, limit).await;
+    if !decision.allowed {
+        RATE_LIMITED_TOTAL
+            .with_label_values(&[proxy_route.name()])
+            .inc();
+        warn!(key, "Rate limited request");
+        return Err(ReverseProxyError::RateLimited(decision));
+    }
+    Ok(Some(decision))
+}
+
+/// Methods which can be sent again without changing their outcome, RFC 9110 section 9.2.2
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Whether the upstream was unreachable or said it is unavailable, such attempts are retried on
+/// another instance and count towards opening the circuit
+fn upstream_unavailable(result: &Result<Response<Body>, ReverseProxyError>) -> bool {
+    match result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(err) => matches!(
+            err,
+            ReverseProxyError::UpstreamTimeout(_) | ReverseProxyError::ProxyRequestError(_)
+        ),
+    }
+}
+
+/// Sends a request to an instance of the upstream of its route
+///
+/// Idempotent requests without a body are sent again to another instance when an attempt fails,
+/// up to the retries of the route. Instances whose circuit is open are skipped, the request fails
+/// fast when every instance is.
+async fn proxy(
+    mut req: Request<Body>,
+    proxy_route: &ProxyRoute,
+    connection: &ClientConnection,
+    config: &ReverseProxyConfig,
+    state: &ProxyState,
+) -> Result<Response<Body>, ReverseProxyError> {
+    let options = &proxy_route.route.options;
+    let client_upgrade = upgrade_protocol(req.headers())
+        .is_some()
+        .then(|| hyper::upgrade::on(&mut req));
+    let body_violation = req.extensions_mut().remove::<BodyLimitViolation>();
+    let retryable =
+        client_upgrade.is_none() && is_idempotent(req.method()) && req.body().is_end_stream();
+    let attempts = if retryable {
+        1 + options.retries as usize
+    } else {
+        1
+    };
+
+    let (parts, body) = req.into_parts();
+    let mut body = Some(body);
+    let mut tried = Vec::new();
+    let mut sent = 0;
+    let mut circuit_retry_after = None;
+    let mut selected = select_instance(proxy_route, state, &mut tried, &mut circuit_retry_after);
+
+    while let Some(upstream) = selected.take() {
+        sent += 1;
+
+        let mut request = Request::new(body.take().unwrap_or_default());
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = parts.uri.clone();
+        *request.version_mut() = parts.version;
+        *request.headers_mut() = parts.headers.clone();
+        let proxied_request =
+            get_proxied_request(request, proxy_route, upstream.url(), connection, config)?;
+
+        info!(upstream_uri = %proxied_request.uri(), "Proxying request");
+        let upstream_started = Instant::now();
+        let result = send_request(&state.clients, proxied_request, proxy_route, config).await;
+        let upstream_latency = upstream_started.elapsed();
+        UPSTREAM_DURATION_SECONDS
+            .with_label_values(&[proxy_route.name()])
+            .observe(upstream_latency.as_secs_f64());
+
+        // A body cut short by its limits is the fault of the client, not of the upstream
+        if let Some(err) = body_violation.as_ref().and_then(BodyLimitViolation::take) {
+            return Err(err);
+        }
+        let failed = result
+            .as_ref()
+            .map_or(true, |response| response.status().is_server_error());
+        state
+            .upstreams
+            .report(&upstream, failed, &options.outlier_detection);
+        if let Some(breaker) = &options.circuit_breaker {
+            state
+                .breakers
+                .record(upstream.url(), upstream_unavailable(&result), breaker);
+        }
+
+        if sent < attempts && upstream_unavailable(&result) {
+            selected = select_instance(proxy_route, state, &mut tried, &mut circuit_retry_after);
+            if selected.is_some() {
+                UPSTREAM_RETRIES_TOTAL
+                    .with_label_values(&[proxy_route.name()])
+                    .inc();
+                warn!(
+                    instance = upstream.url(),
+                    "Retrying request on another instance"
+                );
+                continue;
+            }
+        }
+
+        let mut response = result.inspect_err(|_| {
+            UPSTREAM_ERRORS_TOTAL
+                .with_label_values(&[proxy_route.name()])
+                .inc();
+        })?;
+
+        response.extensions_mut().insert(UpstreamTiming {
+            upstream: upstream.url().to_string(),
+            latency: upstream_latency,
+        });
+        if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+            if let Some(client_upgrade) = client_upgrade {
+                info!("Upgrading connection");
+                splice(client_upgrade, hyper::upgrade::on(&mut response));
+            }
+        }
+        return Ok(response);
+    }
+
+    // Every instance left was skipped for its open circuit
+    if let Some((breaker, retry_after_secs)) =
+        options.circuit_breaker.as_ref().zip(circuit_retry_after)
+    {
+        CIRCUIT_OPEN_REJECTIONS_TOTAL
+            .with_label_values(&[proxy_route.name()])
+            .inc();
+        return circuit_open_response(proxy_route.name(), retry_after_secs, breaker).await;
+    }
+    Err(ReverseProxyError::InvalidConfiguration(format!(
+        "Route `{}` has no upstream",
+        proxy_route.name()
+    )))
+}
+
+/// Picks the next instance to send a request to, skipping those whose circuit is open
+///
+/// # Arguments
+/// * `tried` - Urls of the instances already tried or skipped, the picked one is added
+/// * `circuit_retry_after` - Set to the seconds until the first skipped circuit lets a trial
+///   request through
+fn select_instance(
+    proxy_route: &ProxyRoute,
+    state: &ProxyState,
+    tried: &mut Vec<String>,
+    circuit_retry_after: &mut Option<u64>,
+) -> Option<SelectedUpstream> {
+    loop {
+        let upstream = state.upstreams.select(&proxy_route.route, tried)?;
+        tried.push(upstream.url().to_string());
+        let Some(breaker) = &proxy_route.route.options.circuit_breaker else {
+            return Some(upstream);
+        };
+        match state.breakers.allow(upstream.url(), breaker) {
+            Ok(()) => return Some(upstream),
+            Err(retry_after_secs) => {
+                *circuit_retry_after = Some(
+                    circuit_retry_after.map_or(retry_after_secs, |secs| secs.min(retry_after_secs)),
+                );
+            }
+        }
+    }
+}
+
+/// Proxies a request on a cached route, answering it from the cache when possible
+async fn proxy_cached(
+    mut req: Request<Body>,
+    key: String,
+    options: &RouteCacheOptions,
+    proxy_route: &ProxyRoute,
+    connection: &ClientConnection,
+    config: &ReverseProxyConfig,
+    state: &ProxyState,
+) -> Result<Response<Body>, ReverseProxyError> {
+    let count = |result: &str| {
+        CACHE_REQUESTS_TOTAL
+            .with_label_values(&[proxy_route.name(), result])
+            .inc();
+    };
+
+    let validators = match state.cache.lookup(&key, req.headers()).await {
+        CacheLookup::Fresh(response) => {
+            count("hit");
+            return Ok(response);
+        }
+        CacheLookup::Stale(validators) => Some(validators),
+        CacheLookup::Miss => None,
+    };
+
+    // The request as sent by the client, before it is made conditional
+    let mut client_req = Request::new(());
+    *client_req.method_mut() = req.method().clone();
+    *client_req.uri_mut() = req.uri().clone();
+    *client_req.headers_mut() = req.headers().clone();
+
+    if let Some(validators) = &validators {
+        validators.apply(req.headers_mut());
+    }
+    let response = proxy(req, proxy_route, connection, config, state).await?;
+
+    if validators.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+        let revalidated = state
+            .cache
+            .revalidated(key.clone(), &client_req, &response, options)
+            .await;
+        if let Some(revalidated) = revalidated {
+            count("revalidated");
+            return Ok(revalidated);
+        }
+
+        // The entry was evicted or can no longer be cached, the 304 answers validators the client
+        // never sent so the request is sent again as the client sent it. Cached requests are GET
+        // and HEAD requests, which have no body.
+        let mut retry = Request::new(Body::empty());
+        *retry.method_mut() = client_req.method().clone();
+        *retry.uri_mut() = client_req.uri().clone();
+        *retry.headers_mut() = client_req.headers().clone();
+        count("miss");
+        let response = proxy(retry, proxy_route, connection, config, state).await?;
+        return state.cache.store(key, &client_req, response, options).await;
+    }
+
+    count("miss");
+    state.cache.store(key, &client_req, response, options).await
+}
+
+/* ADMIN */
+
+/// Handles a request to the admin listener, see [serve_admin](crate::server::serve_admin)
+///
+/// The admin endpoints are the metrics, the upstream health and the cache purge, along with the
+/// health endpoints of the public listener so the admin listener can be probed on its own
+pub async fn admin_request(
+    state: ProxyState,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let config = state.config.get();
+    let handled = match req.uri().path() {
+        "/metrics" => Ok(metrics_route()),
+        "/healthz/upstreams" => Ok(upstream_health_route(&config, &state)),
+        "/cache/purge" => purge_cache_route(req, &config, &state.cache).await,
+        "/livez" => Ok(livez_route()),
+        "/readyz" => Ok(readyz_route(&config, &state.shutdown)),
+        _ => Ok(not_found_route()),
+    };
+    Ok(handled.unwrap_or_else(|err| {
+        warn!(error = %err, "Failed to handle admin request");
+        err.into_response(false)
+    }))
+}
+
+/* PURGE_CACHE */
+
+/// Largest body accepted by the purge endpoint
+const PURGE_REQUEST_MAX_BYTES: usize = 64 * 1024;
+
+/// Purges the response cache, authenticated with the bearer token of the cache configuration
+async fn purge_cache_route(
+    req: Request<Body>,
+    config: &ReverseProxyConfig,
+    cache: &ResponseCache,
+) -> Result<Response<Body>, ReverseProxyError> {
+    if req.method() != Method::POST {
+        return Err(ReverseProxyError::MethodNotAllowed);
+    }
+
+    let token = config
+        .cache
+        .purge_token
+        .as_deref()
+        .ok_or(ReverseProxyError::Unauthorized)?;
+    let provided = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if !constant_time_eq(provided.as_bytes(), token.as_bytes()) {
+        return Err(ReverseProxyError::Unauthorized);
+    }
+
+    let body = read_body_limited(req.into_body(), PURGE_REQUEST_MAX_BYTES).await?;
+    let request: PurgeCacheRequest = if body.is_empty() {
+        PurgeCacheRequest::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|err| ReverseProxyError::InvalidRequest(err.to_string()))?
+    };
+
+    let purged = cache.purge(request.prefix.as_deref()).await;
+    info!(purged, prefix = ?request.prefix, "Purged response cache");
+    Ok(json_response(200, &PurgeCacheResponse { purged }))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Reads a request body, failing when it is larger than the limit
+async fn read_body_limited(mut body: Body, limit: usize) -> Result<Bytes, ReverseProxyError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| ReverseProxyError::InvalidRequest(err.to_string()))?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(ReverseProxyError::InvalidRequest(format!(
+                "Body larger than {} bytes",
+                limit
+            )));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(bytes))
+}
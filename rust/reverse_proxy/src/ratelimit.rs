@@ -0,0 +1,231 @@
+use hyper::{
+    header::{HeaderValue, RETRY_AFTER},
+    HeaderMap,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use crate::{
+    forwarded::{client_ip, ClientConnection, TrustedProxies},
+    identity::X_USER_EMAIL,
+    route::ProxyRoute,
+};
+
+/* RATE_LIMIT_OPTIONS */
+
+/// What the requests counted together by a rate limit have in common
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The address of the client, see [client_ip]
+    #[default]
+    Ip,
+    /// The authenticated user on routes which require auth, the address of the client otherwise
+    User,
+}
+
+/// A token bucket rate limit of a route, allowing bursts of `requests` refilled over `per_secs`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RateLimitOptions {
+    /// Requests allowed in a burst, the size of the bucket
+    pub requests: u32,
+    /// Time for an empty bucket to refill
+    pub per_secs: u64,
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+impl RateLimitOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.requests == 0 || self.per_secs == 0 {
+            return Err("rate limit requests and per_secs must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.requests as f64 / self.per_secs as f64
+    }
+}
+
+/* RATE_LIMIT_DECISION */
+
+pub const RATELIMIT_LIMIT: &str = "ratelimit-limit";
+pub const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+pub const RATELIMIT_RESET: &str = "ratelimit-reset";
+pub const RATELIMIT_POLICY: &str = "ratelimit-policy";
+
+/// Outcome of taking a token from a bucket
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    /// Size of the bucket
+    pub limit: u32,
+    /// Tokens left in the bucket
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until the next request is allowed, 0 when this one was
+    pub retry_after_secs: u64,
+    /// Seconds an empty bucket takes to refill
+    pub window_secs: u64,
+}
+
+impl RateLimitDecision {
+    /// Sets the `RateLimit-*` headers, and `Retry-After` when the request was rejected
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(self.reset_secs));
+        if let Ok(policy) = HeaderValue::from_str(&format!("{};w={}", self.limit, self.window_secs))
+        {
+            headers.insert(RATELIMIT_POLICY, policy);
+        }
+        if !self.allowed {
+            headers.insert(RETRY_AFTER, HeaderValue::from(self.retry_after_secs));
+        }
+    }
+}
+
+/* RATE_LIMIT_STORE */
+
+pub type RateLimitFuture<'a> = Pin<Box<dyn Future<Output = RateLimitDecision> + Send + 'a>>;
+
+/// Where the token buckets are kept
+///
+/// Buckets are kept in the memory of each proxy by default, a shared backend implementing this
+/// trait lets several proxies enforce the same limits
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket of `key`, creating a full bucket for new keys
+    fn acquire<'a>(&'a self, key: &'a str, limit: &'a RateLimitOptions) -> RateLimitFuture<'a>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    fn secs_until(&self, tokens: f64) -> u64 {
+        ((tokens - self.tokens).max(0.0) / self.refill_per_sec).ceil() as u64
+    }
+}
+
+/// Buckets are pruned once they are full again after this many acquisitions
+const PRUNE_EVERY: usize = 1024;
+
+/// Token buckets kept in memory, limits are per proxy instance
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    acquisitions: AtomicUsize,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a token from the bucket of `key` at the time `now`
+    pub fn acquire_at(
+        &self,
+        key: &str,
+        limit: &RateLimitOptions,
+        now: Instant,
+    ) -> RateLimitDecision {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if self.acquisitions.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                bucket.tokens < bucket.capacity
+            });
+        }
+
+        let capacity = limit.requests as f64;
+        let bucket = buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+            tokens: capacity,
+            updated: now,
+            capacity,
+            refill_per_sec: limit.refill_per_sec(),
+        });
+        // The limit may have changed since the bucket was created when the configuration reloads
+        bucket.capacity = capacity;
+        bucket.refill_per_sec = limit.refill_per_sec();
+        bucket.refill(now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        RateLimitDecision {
+            allowed,
+            limit: limit.requests,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: bucket.secs_until(capacity),
+            retry_after_secs: if allowed { 0 } else { bucket.secs_until(1.0) },
+            window_secs: limit.per_secs,
+        }
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn acquire<'a>(&'a self, key: &'a str, limit: &'a RateLimitOptions) -> RateLimitFuture<'a> {
+        Box::pin(std::future::ready(self.acquire_at(
+            key,
+            limit,
+            Instant::now(),
+        )))
+    }
+}
+
+/// The bucket key of a request, scoped to its route
+///
+/// # Arguments
+/// * `headers` - The headers of the client request, after authentication
+/// * `route` - The route of the request
+/// * `limit` - The rate limit of the route
+/// * `connection` - The connection the request was received on
+/// * `trusted_proxies` - Proxies whose `X-Forwarded-For` is used to find the client address
+pub fn rate_limit_key(
+    headers: &HeaderMap,
+    route: &ProxyRoute,
+    limit: &RateLimitOptions,
+    connection: &ClientConnection,
+    trusted_proxies: &TrustedProxies,
+) -> String {
+    let user = match limit.key {
+        RateLimitKey::User if route.route.options.require_auth => headers
+            .get(X_USER_EMAIL)
+            .and_then(|value| value.to_str().ok()),
+        _ => None,
+    };
+    match user {
+        Some(user) => format!("{}:user:{}", route.name(), user),
+        None => format!(
+            "{}:ip:{}",
+            route.name(),
+            client_ip(headers, connection, trusted_proxies)
+        ),
+    }
+}
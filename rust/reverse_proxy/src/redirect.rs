@@ -0,0 +1,267 @@
+use hyper::{Body, Request, StatusCode};
+use regex::Regex;
+use serde::Deserialize;
+use tracing_utils::tracing::info;
+
+use crate::{
+    config::ReverseProxyConfig,
+    error::ReverseProxyError,
+    path::{normalize_path, set_request_path},
+    route::{ClassifiedRoute, NonProxyRoute},
+};
+
+/* RULE_CONFIG */
+
+/// A redirect sent by the proxy itself, matched on the normalised path before routing
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RedirectRule {
+    /// Path prefix matched on segment boundaries, the rest of the path is appended to `to`
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Regex matched against the path, `to` can refer to its captures as `$1` or `${name}`
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// The path or url to redirect to, paths are relative to the base url
+    pub to: String,
+    /// 301, 302, 307 or 308, defaults to 301
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+    /// Append the query of the request to the location, defaults to true
+    #[serde(default = "default_preserve_query")]
+    pub preserve_query: bool,
+}
+
+fn default_redirect_status() -> u16 {
+    301
+}
+
+fn default_preserve_query() -> bool {
+    true
+}
+
+/// A change of the path of a request before it is routed, invisible to the client
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RewriteRule {
+    /// Path prefix matched on segment boundaries, the rest of the path is appended to `to`
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Regex matched against the path, `to` can refer to its captures as `$1` or `${name}`
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// The path the request is routed on instead
+    pub to: String,
+}
+
+/* PATH_MATCHER */
+
+#[derive(Debug, Clone)]
+enum PathMatcher {
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl PathMatcher {
+    fn new(prefix: &Option<String>, regex: &Option<String>) -> Result<Self, String> {
+        match (prefix, regex) {
+            (Some(prefix), None) => {
+                if normalize_path(prefix).ok().as_deref() != Some(prefix.as_str()) {
+                    return Err(format!("prefix `{}` must be a normalised path", prefix));
+                }
+                Ok(Self::Prefix(prefix.trim_end_matches('/').to_string()))
+            }
+            (None, Some(regex)) => Regex::new(regex)
+                .map(Self::Regex)
+                .map_err(|err| format!("invalid regex `{}`: {}", regex, err)),
+            _ => Err("exactly one of prefix and regex must be set".to_string()),
+        }
+    }
+
+    /// The target of a rule for a path, when the path matches
+    fn substitute(&self, path: &str, to: &str) -> Option<String> {
+        match self {
+            Self::Prefix(prefix) => {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                if !rest.is_empty() && !rest.starts_with('/') {
+                    return None;
+                }
+                let to = if rest.is_empty() {
+                    to
+                } else {
+                    to.trim_end_matches('/')
+                };
+                Some(format!("{}{}", to, rest))
+            }
+            Self::Regex(regex) => {
+                let captures = regex.captures(path)?;
+                let mut target = String::new();
+                captures.expand(to, &mut target);
+                Some(target)
+            }
+        }
+    }
+}
+
+/* PATH_RULES */
+
+#[derive(Debug, Clone)]
+struct Redirect {
+    matcher: PathMatcher,
+    to: String,
+    status: StatusCode,
+    preserve_query: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Rewrite {
+    matcher: PathMatcher,
+    to: String,
+}
+
+/// Where a redirect rule sends a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectTarget {
+    pub status: StatusCode,
+    pub location: String,
+}
+
+/// The redirect and rewrite rules of the proxy, each list is tried in order and the first match
+/// applies
+#[derive(Debug, Clone, Default)]
+pub struct PathRules {
+    redirects: Vec<Redirect>,
+    rewrites: Vec<Rewrite>,
+}
+
+impl PathRules {
+    pub fn new(
+        redirects: Vec<RedirectRule>,
+        rewrites: Vec<RewriteRule>,
+    ) -> Result<Self, ReverseProxyError> {
+        let invalid = |kind: &str, index: usize, reason: String| {
+            ReverseProxyError::InvalidConfiguration(format!("{} {}: {}", kind, index + 1, reason))
+        };
+
+        let redirects = redirects
+            .into_iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                let matcher = PathMatcher::new(&rule.prefix, &rule.regex)
+                    .map_err(|reason| invalid("Redirect", index, reason))?;
+                let status = match rule.status {
+                    301 | 302 | 307 | 308 => StatusCode::from_u16(rule.status)
+                        .map_err(|err| invalid("Redirect", index, err.to_string()))?,
+                    _ => {
+                        return Err(invalid(
+                            "Redirect",
+                            index,
+                            "status must be 301, 302, 307 or 308".to_string(),
+                        ))
+                    }
+                };
+                Ok(Redirect {
+                    matcher,
+                    to: rule.to,
+                    status,
+                    preserve_query: rule.preserve_query,
+                })
+            })
+            .collect::<Result<Vec<_>, ReverseProxyError>>()?;
+
+        let rewrites = rewrites
+            .into_iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                let matcher = PathMatcher::new(&rule.prefix, &rule.regex)
+                    .map_err(|reason| invalid("Rewrite", index, reason))?;
+                if !rule.to.starts_with('/') {
+                    return Err(invalid(
+                        "Rewrite",
+                        index,
+                        "to must be a path starting with `/`".to_string(),
+                    ));
+                }
+                Ok(Rewrite {
+                    matcher,
+                    to: rule.to,
+                })
+            })
+            .collect::<Result<Vec<_>, ReverseProxyError>>()?;
+
+        Ok(Self {
+            redirects,
+            rewrites,
+        })
+    }
+
+    /// Where the first matching redirect rule sends a request, if any
+    ///
+    /// # Arguments
+    /// * `path` - The normalised path of the request
+    /// * `query` - The query of the request
+    /// * `base_url` - The public url of the proxy, which paths are relative to
+    pub fn redirect(
+        &self,
+        path: &str,
+        query: Option<&str>,
+        base_url: &str,
+    ) -> Option<RedirectTarget> {
+        self.redirects.iter().find_map(|redirect| {
+            let target = redirect.matcher.substitute(path, &redirect.to)?;
+            let mut location = if target.starts_with('/') {
+                format!("{}{}", base_url, target)
+            } else {
+                target
+            };
+            if let Some(query) = query.filter(|query| redirect.preserve_query && !query.is_empty())
+            {
+                location.push(if location.contains('?') { '&' } else { '?' });
+                location.push_str(query);
+            }
+            Some(RedirectTarget {
+                status: redirect.status,
+                location,
+            })
+        })
+    }
+
+    /// The path the first matching rewrite rule routes a request on instead, if any
+    pub fn rewrite(&self, path: &str) -> Option<String> {
+        self.rewrites
+            .iter()
+            .find_map(|rewrite| rewrite.matcher.substitute(path, &rewrite.to))
+    }
+}
+
+/* ROUTE_REQUEST */
+
+/// Routes a request on its normalised path, after the redirect and rewrite rules
+///
+/// The routes of the proxy itself, other than the root, are never redirected or rewritten. A
+/// rewritten request keeps its query.
+pub fn route_request(
+    req: &mut Request<Body>,
+    config: &ReverseProxyConfig,
+) -> Result<ClassifiedRoute, ReverseProxyError> {
+    let route = config.routes.classify(req.uri().path());
+    if let ClassifiedRoute::NonProxy(builtin) = &route {
+        if !matches!(builtin, NonProxyRoute::NotFound | NonProxyRoute::Root) {
+            return Ok(route);
+        }
+    }
+
+    let path = req.uri().path();
+    if let Some(target) = config
+        .path_rules
+        .redirect(path, req.uri().query(), &config.base_url)
+    {
+        return Ok(ClassifiedRoute::NonProxy(NonProxyRoute::Redirect(target)));
+    }
+
+    let Some(rewritten) = config.path_rules.rewrite(path) else {
+        return Ok(route);
+    };
+    let rewritten = normalize_path(&rewritten)?;
+    info!(path, rewritten, "Rewrote request path");
+    set_request_path(req, rewritten)?;
+    Ok(config.routes.classify(req.uri().path()))
+}
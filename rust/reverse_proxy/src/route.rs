@@ -0,0 +1,460 @@
+use hyper::Uri;
+use serde::Deserialize;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use crate::{
+    balancer::{HealthCheckOptions, LoadBalancing, OutlierDetectionOptions},
+    breaker::CircuitBreakerOptions,
+    cache::RouteCacheOptions,
+    error::ReverseProxyError,
+    headers::HeaderRuleSet,
+    path::normalize_path,
+    ratelimit::RateLimitOptions,
+    redirect::RedirectTarget,
+    static_files::StaticFilesConfig,
+};
+
+/* ROUTE_CONFIG */
+
+/// An entry of the routing table, proxying every path under `prefix` to `upstream`, or to one of
+/// the instances listed in `upstreams`, or serving them from `static_files`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RouteConfig {
+    /// Name of the route used in logs and metrics, defaults to the prefix
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Path prefix the route matches, eg. `/blog`
+    pub prefix: String,
+    /// Base url of the upstream the route proxies to, the first of `upstreams` when it is left out
+    #[serde(default)]
+    pub upstream: String,
+    /// Base urls of the instances of the upstream, requests are balanced between them
+    #[serde(default)]
+    pub upstreams: Vec<String>,
+    /// Whether the prefix is removed from the path before it is sent upstream
+    #[serde(default = "default_strip_prefix")]
+    pub strip_prefix: bool,
+    #[serde(default)]
+    pub options: RouteOptions,
+    /// Serve the route from a local directory instead of proxying it
+    #[serde(default)]
+    pub static_files: Option<StaticFilesConfig>,
+}
+
+/// Per route behaviour of the proxy
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RouteOptions {
+    /// Forward the `Host` header of the client instead of replacing it with the upstream host
+    pub preserve_host: bool,
+    /// Time allowed to connect to the upstream, defaults to 5 seconds
+    pub connect_timeout_ms: Option<u64>,
+    /// Time allowed for the upstream to respond, defaults to 30 seconds
+    pub request_timeout_ms: Option<u64>,
+    /// Time pooled connections to the upstream are kept open while unused, defaults to 90 seconds
+    pub idle_timeout_ms: Option<u64>,
+    /// Header rules applied after the global ones
+    pub headers: HeaderRuleSet,
+    /// Rewrite `Location` headers pointing at the upstream to the public url, defaults to true
+    pub rewrite_location: bool,
+    /// Cache the responses of the route, disabled when not set
+    pub cache: Option<RouteCacheOptions>,
+    /// Compress the responses of the route following the global compression configuration,
+    /// defaults to true
+    pub compress: bool,
+    /// Only let requests with a valid access token through, and tell the upstream who made them
+    /// with the `X-User-Email` and `X-User-Roles` headers
+    pub require_auth: bool,
+    /// Token bucket rate limit of the route, unlimited when not set
+    pub rate_limit: Option<RateLimitOptions>,
+    /// How the instance of the upstream a request is sent to is picked
+    pub load_balancing: LoadBalancing,
+    /// Active health checks of the instances of the upstream, disabled when not set
+    pub health_check: Option<HealthCheckOptions>,
+    /// Ejection of the instances which keep failing requests
+    pub outlier_detection: OutlierDetectionOptions,
+    /// Times an idempotent request without a body is sent to another instance after a connection
+    /// failure or a 502, 503 or 504, defaults to 1
+    pub retries: u32,
+    /// Fail fast with a 503 while the upstream is down, disabled when not set
+    pub circuit_breaker: Option<CircuitBreakerOptions>,
+    /// Largest request body, larger ones are rejected with a 413, defaults to
+    /// [LimitsConfig::max_body_bytes]
+    pub max_body_bytes: Option<u64>,
+}
+
+impl Default for RouteOptions {
+    fn default() -> Self {
+        Self {
+            preserve_host: false,
+            connect_timeout_ms: None,
+            request_timeout_ms: None,
+            idle_timeout_ms: None,
+            headers: HeaderRuleSet::default(),
+            rewrite_location: true,
+            cache: None,
+            compress: true,
+            require_auth: false,
+            rate_limit: None,
+            load_balancing: LoadBalancing::default(),
+            health_check: None,
+            outlier_detection: OutlierDetectionOptions::default(),
+            retries: 1,
+            circuit_breaker: None,
+            max_body_bytes: None,
+        }
+    }
+}
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+impl RouteOptions {
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_IDLE_TIMEOUT)
+    }
+}
+
+fn default_strip_prefix() -> bool {
+    true
+}
+
+impl RouteConfig {
+    pub fn new(name: &str, prefix: &str, upstream: &str) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            prefix: prefix.to_string(),
+            upstream: upstream.to_string(),
+            upstreams: Vec::new(),
+            strip_prefix: true,
+            options: RouteOptions::default(),
+            static_files: None,
+        }
+    }
+
+    /// A route serving the files of a local directory
+    pub fn static_files(name: &str, prefix: &str, static_files: StaticFilesConfig) -> Self {
+        Self {
+            static_files: Some(static_files),
+            ..Self::new(name, prefix, "")
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.prefix)
+    }
+
+    fn validate(&self) -> Result<(), ReverseProxyError> {
+        let invalid = |reason: &str| {
+            Err(ReverseProxyError::InvalidConfiguration(format!(
+                "Route `{}`: {}",
+                self.name(),
+                reason
+            )))
+        };
+
+        if !self.prefix.starts_with('/') {
+            return invalid("prefix must start with `/`");
+        }
+        if self.prefix.len() > 1 && self.prefix.ends_with('/') {
+            return invalid("prefix must not end with `/`");
+        }
+        // Requests are routed on their normalised path, which an unnormalised prefix never matches
+        if normalize_path(&self.prefix).ok().as_deref() != Some(self.prefix.as_str()) {
+            return invalid("prefix must be a normalised path");
+        }
+        // The probes are matched before the routing table and would shadow the route
+        if PROBE_PATHS
+            .iter()
+            .any(|probe| matches_path(&self.prefix, probe))
+        {
+            return invalid("prefix is reserved by the health endpoints of the proxy");
+        }
+
+        match (
+            self.upstream.is_empty(),
+            self.upstreams.is_empty(),
+            &self.static_files,
+        ) {
+            (true, true, Some(static_files)) => {
+                static_files.validate().or_else(|reason| invalid(&reason))?
+            }
+            (_, _, Some(_)) => return invalid("static_files routes must not have upstreams"),
+            (true, true, None) => return invalid("upstream or upstreams must be set"),
+            (false, false, None) => {
+                return invalid("only one of upstream and upstreams can be set")
+            }
+            _ => {}
+        }
+        for upstream in std::iter::once(&self.upstream)
+            .filter(|upstream| !upstream.is_empty())
+            .chain(&self.upstreams)
+        {
+            let uri: Uri = match upstream.parse() {
+                Ok(uri) => uri,
+                Err(_) => return invalid("upstream is not a valid url"),
+            };
+            if !matches!(uri.scheme_str(), Some("http") | Some("https")) {
+                return invalid("upstream must be an http or https url");
+            }
+            if uri.authority().is_none() {
+                return invalid("upstream must have a host");
+            }
+            if uri.query().is_some() {
+                return invalid("upstream must not have a query");
+            }
+        }
+        let timeouts = [
+            self.options.connect_timeout_ms,
+            self.options.request_timeout_ms,
+            self.options.idle_timeout_ms,
+        ];
+        if timeouts.contains(&Some(0)) {
+            return invalid("timeouts must be greater than 0");
+        }
+        if self.options.max_body_bytes == Some(0) {
+            return invalid("max_body_bytes must be greater than 0");
+        }
+        if let Some(rate_limit) = &self.options.rate_limit {
+            rate_limit.validate().or_else(|reason| invalid(&reason))?;
+        }
+        if let Some(health_check) = &self.options.health_check {
+            health_check.validate().or_else(|reason| invalid(&reason))?;
+        }
+        self.options
+            .outlier_detection
+            .validate()
+            .or_else(|reason| invalid(&reason))?;
+        if let Some(circuit_breaker) = &self.options.circuit_breaker {
+            circuit_breaker
+                .validate()
+                .or_else(|reason| invalid(&reason))?;
+        }
+        Ok(())
+    }
+}
+
+/* ROUTE_TABLE */
+
+/// Routes of the proxy, matched by longest prefix
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    routes: Vec<Arc<RouteConfig>>,
+}
+
+impl RouteTable {
+    /// Validates the routes and builds the table
+    ///
+    /// Trailing slashes are removed from upstreams, so the stripped path can be appended as is.
+    /// Every proxied route lists its instances in `upstreams`, `upstream` being the first of them.
+    pub fn new(routes: Vec<RouteConfig>) -> Result<Self, ReverseProxyError> {
+        let mut routes = routes
+            .into_iter()
+            .map(|mut route| {
+                route.validate()?;
+                if route.static_files.is_some() {
+                    return Ok(Arc::new(route));
+                }
+                if route.upstreams.is_empty() {
+                    route.upstreams.push(route.upstream.clone());
+                }
+                for upstream in route.upstreams.iter_mut() {
+                    *upstream = upstream.trim_end_matches('/').to_string();
+                }
+                route.upstream = route.upstreams[0].clone();
+                Ok(Arc::new(route))
+            })
+            .collect::<Result<Vec<_>, ReverseProxyError>>()?;
+
+        let mut prefixes = HashSet::new();
+        for route in routes.iter() {
+            if !prefixes.insert(route.prefix.as_str()) {
+                return Err(ReverseProxyError::InvalidConfiguration(format!(
+                    "Duplicate route prefix `{}`",
+                    route.prefix
+                )));
+            }
+        }
+
+        routes.sort_by_key(|route| std::cmp::Reverse(route.prefix.len()));
+
+        Ok(Self { routes })
+    }
+
+    /// The routes of the table, longest prefix first
+    pub fn routes(&self) -> &[Arc<RouteConfig>] {
+        &self.routes
+    }
+
+    /// Finds the route with the longest prefix matching the path
+    pub fn find(&self, path: &str) -> Option<ProxyRoute> {
+        self.routes
+            .iter()
+            .find(|route| matches_path(path, &route.prefix))
+            .map(|route| {
+                let path = if route.strip_prefix {
+                    strip_prefix(path, &route.prefix)
+                } else {
+                    path.to_string()
+                };
+                ProxyRoute {
+                    route: route.clone(),
+                    path,
+                }
+            })
+    }
+
+    /// Classifies a path, built in routes take precedence over the routing table
+    pub fn classify(&self, path: &str) -> ClassifiedRoute {
+        if let Some(route) = NonProxyRoute::builtin(path) {
+            return ClassifiedRoute::NonProxy(route);
+        }
+
+        match self.find(path) {
+            Some(route) => ClassifiedRoute::Proxy(route),
+            None => ClassifiedRoute::NonProxy(NonProxyRoute::NotFound),
+        }
+    }
+}
+
+/* ROUTE_HELPERS */
+
+/// Paths of the health endpoints the proxy answers itself on every listener
+const PROBE_PATHS: [&str; 3] = ["/livez", "/readyz", "/healthcheck"];
+
+fn matches_path(path: &str, route_path: &str) -> bool {
+    if route_path == "/" {
+        return path.starts_with('/');
+    }
+
+    let route_path_len = route_path.len();
+
+    if path.len() < route_path_len {
+        return false;
+    }
+
+    if path.starts_with(route_path) {
+        if let Some(next_char) = path.chars().nth(route_path_len) {
+            return next_char == '/';
+        }
+        // Perfect match
+        return true;
+    }
+    false
+}
+
+fn strip_prefix(input: &str, prefix: &str) -> String {
+    if prefix == "/" {
+        return input.to_string();
+    }
+    input.strip_prefix(prefix).unwrap_or(input).to_string()
+}
+
+/* PROXY_ROUTE */
+
+/// A request matched to a route of the routing table
+#[derive(Debug, Clone)]
+pub struct ProxyRoute {
+    pub route: Arc<RouteConfig>,
+    /// The path to request from the upstream
+    pub path: String,
+}
+
+impl ProxyRoute {
+    /// The uri to request from the first instance of the upstream
+    pub fn proxied_uri(&self) -> String {
+        self.proxied_uri_on(&self.route.upstream)
+    }
+
+    /// The uri to request from an instance of the upstream
+    pub fn proxied_uri_on(&self, upstream: &str) -> String {
+        format!("{}{}", upstream, self.path)
+    }
+
+    /// Name of the route, used to label metrics
+    pub fn name(&self) -> &str {
+        self.route.name()
+    }
+}
+
+/* NON_PROXY_ROUTE */
+
+#[derive(Debug)]
+pub enum NonProxyRoute {
+    NotFound,
+    HealthCheck,
+    Livez,
+    Readyz,
+    Root,
+    /// A match of a redirect rule
+    Redirect(RedirectTarget),
+}
+
+impl NonProxyRoute {
+    /// The routes handled by the proxy itself, the admin endpoints are only served by
+    /// [serve_admin]
+    fn builtin(path: &str) -> Option<Self> {
+        match path {
+            "" | "/" => Some(NonProxyRoute::Root),
+            "/livez" => Some(NonProxyRoute::Livez),
+            "/readyz" => Some(NonProxyRoute::Readyz),
+            _ if matches_path(path, "/healthcheck") => Some(NonProxyRoute::HealthCheck),
+            _ => None,
+        }
+    }
+
+    /// Name of the route, used to label metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            NonProxyRoute::NotFound => "not_found",
+            NonProxyRoute::HealthCheck => "healthcheck",
+            NonProxyRoute::Livez => "livez",
+            NonProxyRoute::Readyz => "readyz",
+            NonProxyRoute::Root => "root",
+            NonProxyRoute::Redirect(_) => "redirect",
+        }
+    }
+}
+
+/* CLASSIFIED_ROUTE */
+
+#[derive(Debug)]
+pub enum ClassifiedRoute {
+    Proxy(ProxyRoute),
+    NonProxy(NonProxyRoute),
+}
+
+impl ClassifiedRoute {
+    /// Whether the route is a health endpoint of the proxy itself
+    pub fn is_probe(&self) -> bool {
+        matches!(
+            self,
+            ClassifiedRoute::NonProxy(
+                NonProxyRoute::HealthCheck | NonProxyRoute::Livez | NonProxyRoute::Readyz
+            )
+        )
+    }
+
+    /// Name of the route, used to label metrics
+    pub fn name(&self) -> &str {
+        match self {
+            ClassifiedRoute::Proxy(route) => route.name(),
+            ClassifiedRoute::NonProxy(route) => route.name(),
+        }
+    }
+}
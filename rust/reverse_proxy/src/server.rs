@@ -0,0 +1,141 @@
+use hyper::{server::conn::AddrStream, Server};
+use std::{convert::Infallible, future::Future, time::Duration};
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing_utils::tracing::warn;
+
+use crate::{
+    error::StdError,
+    forwarded::ClientConnection,
+    proxy::{admin_request, reverse_proxy, ProxyState},
+};
+
+/// Serves the proxy on a listener bound by the caller
+///
+/// Once the shutdown of the state starts, no new connections are accepted and the future
+/// resolves when the requests in flight are done
+///
+/// Clients get the header read timeout of the configuration at startup to send their headers
+pub fn serve(
+    listener: std::net::TcpListener,
+    state: ProxyState,
+) -> Result<impl Future<Output = hyper::Result<()>>, StdError> {
+    listener.set_nonblocking(true)?;
+    let shutdown = state.shutdown.started();
+    let header_read_timeout = state.config.get().limits.header_read_timeout();
+
+    let service_fn = hyper::service::make_service_fn(move |stream: &AddrStream| {
+        let state = state.clone();
+        let connection = ClientConnection::new(stream.remote_addr());
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |req| {
+                reverse_proxy(state.clone(), connection, req)
+            }))
+        }
+    });
+
+    Ok(Server::from_tcp(listener)?
+        .http1_header_read_timeout(header_read_timeout)
+        .serve(service_fn)
+        .with_graceful_shutdown(shutdown))
+}
+
+/// Serves the admin endpoints on a listener bound by the caller, see [admin_request]
+///
+/// The listener should only be reachable from the operators' network, the endpoints are not
+/// served by [serve] or [serve_tls]. Shutdown behaves as with [serve].
+pub fn serve_admin(
+    listener: std::net::TcpListener,
+    state: ProxyState,
+) -> Result<impl Future<Output = hyper::Result<()>>, StdError> {
+    listener.set_nonblocking(true)?;
+    let shutdown = state.shutdown.started();
+    let header_read_timeout = state.config.get().limits.header_read_timeout();
+
+    let service_fn = hyper::service::make_service_fn(move |_: &AddrStream| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |req| {
+                admin_request(state.clone(), req)
+            }))
+        }
+    });
+
+    Ok(Server::from_tcp(listener)?
+        .http1_header_read_timeout(header_read_timeout)
+        .serve(service_fn)
+        .with_graceful_shutdown(shutdown))
+}
+
+/// Time allowed for a client to complete the TLS handshake
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the proxy over HTTPS on a listener bound by the caller
+///
+/// Handshakes run in their own tasks so a slow client doesn't hold up the others. Shutdown and
+/// the header read timeout behave as with [serve].
+pub fn serve_tls(
+    listener: std::net::TcpListener,
+    acceptor: TlsAcceptor,
+    state: ProxyState,
+) -> Result<impl Future<Output = hyper::Result<()>>, StdError> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let (sender, mut receiver) = mpsc::channel::<TlsStream<TcpStream>>(64);
+    let shutdown = state.shutdown.started();
+    let header_read_timeout = state.config.get().limits.header_read_timeout();
+
+    let accepting = state.shutdown.started();
+    tokio::spawn(async move {
+        tokio::pin!(accepting);
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut accepting => break,
+            };
+            let (stream, remote_addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(error = %err, "Failed to accept connection");
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(stream).await;
+                    }
+                    Ok(Err(err)) => warn!(%remote_addr, error = %err, "TLS handshake failed"),
+                    Err(_) => warn!(%remote_addr, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+
+    let incoming = hyper::server::accept::poll_fn(move |cx| {
+        receiver
+            .poll_recv(cx)
+            .map(|stream| stream.map(Ok::<_, std::io::Error>))
+    });
+
+    let service_fn = hyper::service::make_service_fn(move |stream: &TlsStream<TcpStream>| {
+        let state = state.clone();
+        let remote_addr = stream.get_ref().0.peer_addr();
+        async move {
+            let connection = ClientConnection {
+                remote_addr: remote_addr?,
+                tls: true,
+            };
+            Ok::<_, std::io::Error>(hyper::service::service_fn(move |req| {
+                reverse_proxy(state.clone(), connection, req)
+            }))
+        }
+    });
+
+    Ok(Server::builder(incoming)
+        .http1_header_read_timeout(header_read_timeout)
+        .serve(service_fn)
+        .with_graceful_shutdown(shutdown))
+}
@@ -0,0 +1,561 @@
+use hyper::{
+    header::{
+        HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+        CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, LOCATION,
+        RANGE,
+    },
+    Body, HeaderMap, Method, Request, Response, StatusCode,
+};
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    cache::etag_matches,
+    compression::{vary_on_accept_encoding, Encoding},
+    error::{escape_html, ReverseProxyError},
+    path::percent_decode,
+    route::ProxyRoute,
+};
+
+/* STATIC_FILES_CONFIG */
+
+/// A local directory a route serves directly instead of proxying to an upstream
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StaticFilesConfig {
+    /// Directory the files are served from, relative to the working directory of the proxy
+    pub root: PathBuf,
+    /// File served for requests to a directory, defaults to `index.html`
+    #[serde(default = "default_index_file")]
+    pub index_file: String,
+    /// List the entries of directories without an index file, disabled by default
+    #[serde(default)]
+    pub directory_index: bool,
+    /// `Cache-Control` header of the files, none when not set
+    #[serde(default)]
+    pub cache_control: Option<String>,
+}
+
+fn default_index_file() -> String {
+    "index.html".to_string()
+}
+
+impl StaticFilesConfig {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index_file: default_index_file(),
+            directory_index: false,
+            cache_control: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.root.is_dir() {
+            return Err(format!(
+                "static files root `{}` is not a directory",
+                self.root.display()
+            ));
+        }
+        if self.index_file.is_empty() || self.index_file.contains(['/', '\\']) {
+            return Err("static files index_file must be a file name".to_string());
+        }
+        if let Some(cache_control) = &self.cache_control {
+            if HeaderValue::from_str(cache_control).is_err() {
+                return Err("static files cache_control must be a valid header value".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/* CONTENT_TYPES */
+
+/// The content type of a file, from its extension
+pub fn content_type_of(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "application/javascript; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("rss") => "application/rss+xml",
+        Some("atom") => "application/atom+xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+/* RANGES */
+
+/// What a `Range` header asks for, RFC 9110 section 14.2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    /// No range, or one the server ignores such as several ranges at once
+    Full,
+    /// The inclusive range of bytes from the first to the last
+    Partial(u64, u64),
+    /// A range starting after the end of the file
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses the `Range` header of a request for a file of `len` bytes
+    pub fn parse(value: &str, len: u64) -> Self {
+        let Some(spec) = value.trim().strip_prefix("bytes=") else {
+            return Self::Full;
+        };
+        if spec.contains(',') {
+            return Self::Full;
+        }
+        let Some((start, end)) = spec.trim().split_once('-') else {
+            return Self::Full;
+        };
+        let (start, end) = (start.trim(), end.trim());
+
+        if start.is_empty() {
+            // The last `end` bytes
+            return match end.parse::<u64>() {
+                Ok(0) => Self::Unsatisfiable,
+                Ok(_) if len == 0 => Self::Unsatisfiable,
+                Ok(suffix) => Self::Partial(len.saturating_sub(suffix), len - 1),
+                Err(_) => Self::Full,
+            };
+        }
+
+        let Ok(start) = start.parse::<u64>() else {
+            return Self::Full;
+        };
+        let end = match end {
+            "" => None,
+            end => match end.parse::<u64>() {
+                Ok(end) if end >= start => Some(end),
+                _ => return Self::Full,
+            },
+        };
+        if start >= len {
+            return Self::Unsatisfiable;
+        }
+        Self::Partial(start, end.unwrap_or(len - 1).min(len - 1))
+    }
+}
+
+/* FILE_LOOKUP */
+
+/// Whether a file or directory is hidden, eg. `.env` or `.git`, these are never served or listed
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// The file a path under the route's prefix refers to, `None` when a segment could escape the
+/// root once decoded or is hidden
+fn file_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let mut file = root.to_path_buf();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let segment = String::from_utf8(percent_decode(segment)).ok()?;
+        if is_hidden(&segment) || segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        file.push(segment);
+    }
+    Some(file)
+}
+
+/// Whether a file resolves to a path under the root, following symlinks
+///
+/// # Arguments
+/// * `root` - The canonical root of the route
+/// * `path` - The file, which must exist
+async fn within_root(root: &Path, path: &Path) -> bool {
+    match tokio::fs::canonicalize(path).await {
+        Ok(path) => path.starts_with(root),
+        Err(_) => false,
+    }
+}
+
+/// A file to serve and how it is encoded
+struct ServedFile {
+    path: PathBuf,
+    metadata: std::fs::Metadata,
+    encoding: Option<Encoding>,
+}
+
+/// The precompressed sibling of a file the client accepts, the file itself otherwise
+///
+/// # Arguments
+/// * `root` - The canonical root of the route, siblings outside of it are ignored
+/// * `path` - The file requested
+/// * `metadata` - The metadata of the file requested
+/// * `headers` - The headers of the client request
+///
+/// # Returns
+/// The file to serve and whether the file has precompressed siblings
+async fn negotiate_file(
+    root: &Path,
+    path: &Path,
+    metadata: std::fs::Metadata,
+    headers: &HeaderMap,
+) -> (ServedFile, bool) {
+    let mut siblings = Vec::new();
+    for encoding in [Encoding::Brotli, Encoding::Gzip] {
+        let extension = match encoding {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        };
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(extension);
+        let sibling = PathBuf::from(sibling);
+        let Ok(sibling_metadata) = tokio::fs::metadata(&sibling).await else {
+            continue;
+        };
+        if !sibling_metadata.is_file() || !within_root(root, &sibling).await {
+            continue;
+        }
+        siblings.push(ServedFile {
+            path: sibling,
+            metadata: sibling_metadata,
+            encoding: Some(encoding),
+        });
+    }
+
+    let has_siblings = !siblings.is_empty();
+    let served = Encoding::accepted(headers)
+        .into_iter()
+        .find_map(|encoding| {
+            let index = siblings
+                .iter()
+                .position(|sibling| sibling.encoding == Some(encoding))?;
+            Some(siblings.swap_remove(index))
+        })
+        .unwrap_or(ServedFile {
+            path: path.to_path_buf(),
+            metadata,
+            encoding: None,
+        });
+    (served, has_siblings)
+}
+
+/* RESPONSES */
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/html")
+        .body(Body::from(status.canonical_reason().unwrap_or("Error")))
+        .expect("Failed to build static files response")
+}
+
+fn io_error(err: std::io::Error) -> ReverseProxyError {
+    ReverseProxyError::InvalidConfiguration(format!("Failed to read static file: {}", err))
+}
+
+/// Answers a 404 for files which are gone and a 403 for files the proxy can't read, other errors
+/// are faults of the server
+fn io_error_response(err: std::io::Error) -> Result<Response<Body>, ReverseProxyError> {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => Ok(status_response(StatusCode::NOT_FOUND)),
+        std::io::ErrorKind::PermissionDenied => Ok(status_response(StatusCode::FORBIDDEN)),
+        _ => Err(io_error(err)),
+    }
+}
+
+/// Validator of a file, changing whenever its size or modification time does
+fn etag_of(file: &ServedFile) -> String {
+    let modified = file
+        .metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let suffix = file.encoding.as_ref().map(Encoding::as_str).unwrap_or("");
+    format!(
+        "\"{:x}-{:x}{}{}\"",
+        file.metadata.len(),
+        modified.as_nanos(),
+        if suffix.is_empty() { "" } else { "-" },
+        suffix
+    )
+}
+
+/// Whether the client's copy of the file is still current
+fn not_modified(headers: &HeaderMap, etag: &HeaderValue, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        return if_none_match
+            .to_str()
+            .is_ok_and(|if_none_match| etag_matches(if_none_match, etag));
+    }
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    // Http dates have a one second resolution
+    let secs = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    };
+    match (since, modified) {
+        (Some(since), Some(modified)) => secs(modified) <= secs(since),
+        _ => false,
+    }
+}
+
+/// Sends a file, or the part of it the `Range` header asks for
+async fn file_response(
+    method: &Method,
+    headers: &HeaderMap,
+    content_type: &str,
+    file: ServedFile,
+    has_siblings: bool,
+    config: &StaticFilesConfig,
+) -> Result<Response<Body>, ReverseProxyError> {
+    let len = file.metadata.len();
+    let modified = file.metadata.modified().ok();
+    let etag = HeaderValue::from_str(&etag_of(&file))?;
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(ETAG, etag.clone());
+    response_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(modified) = modified {
+        response_headers.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_str(&httpdate::fmt_http_date(modified))?,
+        );
+    }
+    if let Some(cache_control) = &config.cache_control {
+        response_headers.insert(CACHE_CONTROL, HeaderValue::from_str(cache_control)?);
+    }
+    if has_siblings {
+        vary_on_accept_encoding(&mut response_headers);
+    }
+
+    if not_modified(headers, &etag, modified) {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        *response.headers_mut() = response_headers;
+        return Ok(response);
+    }
+
+    // A range of an outdated copy of the file would be mixed with the current one
+    let if_range_matches = headers
+        .get(IF_RANGE)
+        .is_none_or(|if_range| *if_range == etag);
+    let range = match headers.get(RANGE).and_then(|range| range.to_str().ok()) {
+        Some(range) if if_range_matches => ByteRange::parse(range, len),
+        _ => ByteRange::Full,
+    };
+
+    response_headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type)?);
+    if let Some(encoding) = file.encoding {
+        response_headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+    }
+    let (status, start, end) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, len),
+        ByteRange::Partial(first, last) => {
+            response_headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", first, last, len))?,
+            );
+            (StatusCode::PARTIAL_CONTENT, first, last + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            response_headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len))?,
+            );
+            let mut response = status_response(StatusCode::RANGE_NOT_SATISFIABLE);
+            response.headers_mut().extend(response_headers);
+            return Ok(response);
+        }
+    };
+    response_headers.insert(CONTENT_LENGTH, HeaderValue::from(end - start));
+
+    let body = if *method == Method::HEAD {
+        Body::empty()
+    } else {
+        let mut reader = match tokio::fs::File::open(&file.path).await {
+            Ok(reader) => reader,
+            Err(err) => return io_error_response(err),
+        };
+        reader
+            .seek(std::io::SeekFrom::Start(start))
+            .await
+            .map_err(io_error)?;
+        Body::wrap_stream(ReaderStream::new(reader.take(end - start)))
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    *response.headers_mut() = response_headers;
+    Ok(response)
+}
+
+/// An HTML listing of the entries of a directory
+async fn directory_listing(
+    method: &Method,
+    directory: &Path,
+    request_path: &str,
+) -> Result<Response<Body>, ReverseProxyError> {
+    let mut entries = Vec::new();
+    let mut reader = match tokio::fs::read_dir(directory).await {
+        Ok(reader) => reader,
+        Err(err) => return io_error_response(err),
+    };
+    while let Some(entry) = reader.next_entry().await.map_err(io_error)? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if is_hidden(&name) {
+            continue;
+        }
+        let is_dir = entry
+            .file_type()
+            .await
+            .is_ok_and(|file_type| file_type.is_dir());
+        entries.push(if is_dir { format!("{}/", name) } else { name });
+    }
+    entries.sort();
+
+    let title = escape_html(request_path);
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {title}</title></head>\n<body>\n\
+         <h1>Index of {title}</h1>\n<ul>\n"
+    );
+    if request_path != "/" {
+        page.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for entry in entries {
+        let href: String = entry
+            .bytes()
+            .map(|byte| match byte {
+                b'/' => "/".to_string(),
+                byte if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                    (byte as char).to_string()
+                }
+                byte => format!("%{:02X}", byte),
+            })
+            .collect();
+        page.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            href,
+            escape_html(&entry)
+        ));
+    }
+    page.push_str("</ul>\n</body>\n</html>\n");
+
+    let len = page.len();
+    let body = if *method == Method::HEAD {
+        Body::empty()
+    } else {
+        Body::from(page)
+    };
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(CONTENT_LENGTH, len)
+        .body(body)
+        .expect("Failed to build directory listing response"))
+}
+
+/* SERVE_STATIC */
+
+/// Serves a request on a route backed by a local directory
+///
+/// Paths which could escape the root, directly or through a symlink, and hidden files are not
+/// found. Requests to a directory without a trailing slash are redirected to it so relative links
+/// resolve.
+///
+/// # Arguments
+/// * `req` - The client request, with its normalised path
+/// * `route` - The route of the request, its path is looked up under the root
+/// * `config` - The static files configuration of the route
+pub async fn serve_static(
+    req: &Request<Body>,
+    route: &ProxyRoute,
+    config: &StaticFilesConfig,
+) -> Result<Response<Body>, ReverseProxyError> {
+    let method = req.method();
+    if *method != Method::GET && *method != Method::HEAD {
+        return Err(ReverseProxyError::MethodNotAllowed);
+    }
+
+    let Some(path) = file_path(&config.root, &route.path) else {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    };
+    let Ok(root) = tokio::fs::canonicalize(&config.root).await else {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    };
+    if !within_root(&root, &path).await {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    }
+    let metadata = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => metadata,
+        Err(err) => return io_error_response(err),
+    };
+
+    let (path, metadata) = if metadata.is_dir() {
+        let request_path = req.uri().path();
+        if !request_path.ends_with('/') {
+            let location = match req.uri().query() {
+                Some(query) => format!("{}/?{}", request_path, query),
+                None => format!("{}/", request_path),
+            };
+            return Ok(Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(LOCATION, location)
+                .body(Body::empty())
+                .expect("Failed to build directory redirect response"));
+        }
+
+        let index = path.join(&config.index_file);
+        match tokio::fs::metadata(&index).await {
+            Ok(metadata) if metadata.is_file() && within_root(&root, &index).await => {
+                (index, metadata)
+            }
+            _ if config.directory_index => {
+                return directory_listing(method, &path, request_path).await
+            }
+            _ => return Ok(status_response(StatusCode::NOT_FOUND)),
+        }
+    } else {
+        (path, metadata)
+    };
+
+    let content_type = content_type_of(&path);
+    let (file, has_siblings) = negotiate_file(&root, &path, metadata, req.headers()).await;
+    file_response(
+        method,
+        req.headers(),
+        content_type,
+        file,
+        has_siblings,
+        config,
+    )
+    .await
+}
@@ -0,0 +1,257 @@
+use hyper::http::uri::Authority;
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        SignatureScheme,
+    },
+    TlsAcceptor,
+};
+use tracing_utils::tracing::{error, info};
+
+use crate::{
+    config::watch_files,
+    error::{ReverseProxyError, StdError},
+};
+
+/* TLS_CONFIG */
+
+/// HTTPS listener of the proxy, the plain HTTP listener keeps running next to it
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TlsConfig {
+    /// Address the HTTPS listener binds to
+    pub listen_addr: String,
+    /// PEM file holding the certificate chain, leaf certificate first
+    pub cert_path: PathBuf,
+    /// PEM file holding the private key of the certificate
+    pub key_path: PathBuf,
+    /// Answer requests on the plain HTTP listener with a redirect to HTTPS
+    #[serde(default)]
+    pub redirect_http: bool,
+}
+
+impl TlsConfig {
+    /// Reads the optional TLS configuration from `TLS_LISTEN_ADDR`, `TLS_CERT_PATH`,
+    /// `TLS_KEY_PATH` and `TLS_REDIRECT_HTTP`, TLS is disabled when `TLS_LISTEN_ADDR` is empty
+    pub fn from_env() -> Result<Option<Self>, ReverseProxyError> {
+        let listen_addr = std::env::var("TLS_LISTEN_ADDR").unwrap_or_default();
+        if listen_addr.is_empty() {
+            return Ok(None);
+        }
+
+        let path = |key: &str| {
+            std::env::var(key)
+                .map(PathBuf::from)
+                .map_err(|_| ReverseProxyError::MissingConfiguration(key.to_string()))
+        };
+        let config = Self {
+            listen_addr,
+            cert_path: path("TLS_CERT_PATH")?,
+            key_path: path("TLS_KEY_PATH")?,
+            redirect_http: std::env::var("TLS_REDIRECT_HTTP").is_ok_and(|value| value == "true"),
+        };
+        config.validate()?;
+        Ok(Some(config))
+    }
+
+    pub fn validate(&self) -> Result<(), ReverseProxyError> {
+        self.socket_addr().map(|_| ())
+    }
+
+    pub fn socket_addr(&self) -> Result<SocketAddr, ReverseProxyError> {
+        self.listen_addr.parse().map_err(|_| {
+            ReverseProxyError::InvalidConfiguration(format!(
+                "Invalid TLS listen address `{}`",
+                self.listen_addr
+            ))
+        })
+    }
+
+    /// The url a plain HTTP request is redirected to
+    ///
+    /// # Arguments
+    /// * `host` - The `Host` header of the request, its port is replaced by the HTTPS one
+    /// * `path_and_query` - The path and query of the request
+    pub fn https_url(&self, host: &str, path_and_query: &str) -> Option<String> {
+        let authority: Authority = host.parse().ok()?;
+        let port = self.socket_addr().ok()?.port();
+        let url = match port {
+            443 => format!("https://{}{}", authority.host(), path_and_query),
+            port => format!("https://{}:{}{}", authority.host(), port, path_and_query),
+        };
+        Some(url)
+    }
+}
+
+/* CERTIFICATES */
+
+/// Serves the certificate from the configured PEM files, reloading it when they change
+pub struct ReloadingCertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl std::fmt::Debug for ReloadingCertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadingCertResolver")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone(),
+        )
+    }
+}
+
+impl ReloadingCertResolver {
+    pub fn new(config: &TlsConfig) -> Result<Arc<Self>, ReverseProxyError> {
+        let key = load_certified_key(&config.cert_path, &config.key_path)?;
+        Ok(Arc::new(Self {
+            cert_path: config.cert_path.clone(),
+            key_path: config.key_path.clone(),
+            current: RwLock::new(Arc::new(key)),
+        }))
+    }
+
+    /// Loads the certificate again, the current one is kept if the files are invalid or the key
+    /// doesn't match the certificate
+    pub fn reload(&self) -> Result<(), ReverseProxyError> {
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(key);
+        Ok(())
+    }
+
+    /// Spawns the tasks reloading the certificate on `SIGHUP`, and when its files change
+    pub fn watch(self: &Arc<Self>) -> Result<(), StdError> {
+        let resolver = self.clone();
+        let paths = vec![self.cert_path.clone(), self.key_path.clone()];
+        watch_files(paths, move |reason| match resolver.reload() {
+            Ok(()) => info!(reason, "Reloaded TLS certificate"),
+            Err(err) => error!(
+                reason,
+                error = %err,
+                "Failed to reload TLS certificate, keeping the current one"
+            ),
+        })
+    }
+
+    pub fn acceptor(self: &Arc<Self>) -> TlsAcceptor {
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        // Upgrades such as WebSockets are only proxied over HTTP/1.1
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+fn load_certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<CertifiedKey, ReverseProxyError> {
+    let invalid = |path: &Path, reason: &str| {
+        ReverseProxyError::TlsError(format!("`{}`: {}", path.display(), reason))
+    };
+    let open = |path: &Path| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|err| invalid(path, &err.to_string()))
+    };
+
+    let certs: Vec<rustls::Certificate> = rustls_pemfile::certs(&mut open(cert_path)?)
+        .map_err(|err| invalid(cert_path, &err.to_string()))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(invalid(cert_path, "no certificates found"));
+    }
+
+    let key = rustls_pemfile::read_all(&mut open(key_path)?)
+        .map_err(|err| invalid(key_path, &err.to_string()))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(key_path, "no private key found"))?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| invalid(key_path, "unsupported private key"))?;
+
+    let certified_key = CertifiedKey::new(certs, key);
+    verify_key_matches_cert(&certified_key).map_err(|reason| {
+        invalid(
+            key_path,
+            &format!("does not match the certificate, {}", reason),
+        )
+    })?;
+    Ok(certified_key)
+}
+
+/// Signature schemes of the probe, with the algorithm verifying them against a certificate
+const PROBE_SCHEMES: [(SignatureScheme, &webpki::SignatureAlgorithm); 5] = [
+    (
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        &webpki::ECDSA_P256_SHA256,
+    ),
+    (
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        &webpki::ECDSA_P384_SHA384,
+    ),
+    (SignatureScheme::ED25519, &webpki::ED25519),
+    (
+        SignatureScheme::RSA_PSS_SHA256,
+        &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    ),
+    (
+        SignatureScheme::RSA_PKCS1_SHA256,
+        &webpki::RSA_PKCS1_2048_8192_SHA256,
+    ),
+];
+
+/// Checks the private key belongs to the leaf certificate, by signing a probe with the key and
+/// verifying the signature with the public key of the certificate
+fn verify_key_matches_cert(certified_key: &CertifiedKey) -> Result<(), String> {
+    const PROBE: &[u8] = b"reverse_proxy certificate probe";
+
+    let schemes: Vec<SignatureScheme> = PROBE_SCHEMES.iter().map(|(scheme, _)| *scheme).collect();
+    let signer = certified_key
+        .key
+        .choose_scheme(&schemes)
+        .ok_or("no supported signature scheme")?;
+    let signature = signer.sign(PROBE).map_err(|err| err.to_string())?;
+    let (_, algorithm) = PROBE_SCHEMES
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+        .ok_or("no supported signature scheme")?;
+
+    let leaf = certified_key
+        .end_entity_cert()
+        .map_err(|err| err.to_string())?;
+    webpki::EndEntityCert::try_from(leaf.0.as_slice())
+        .map_err(|err| format!("invalid certificate: {}", err))?
+        .verify_signature(algorithm, PROBE, &signature)
+        .map_err(|_| "the probe signature doesn't verify".to_string())
+}
@@ -0,0 +1,50 @@
+use hyper::{
+    header::{HeaderValue, CONNECTION, UPGRADE},
+    upgrade::OnUpgrade,
+    HeaderMap,
+};
+use tracing_utils::tracing::{info, warn, Instrument};
+
+/// The protocol a message asks to upgrade to, when its `Connection` header lists `upgrade`
+pub fn upgrade_protocol(headers: &HeaderMap) -> Option<HeaderValue> {
+    let upgrade_requested = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+
+    if upgrade_requested {
+        headers.get(UPGRADE).cloned()
+    } else {
+        None
+    }
+}
+
+/// Puts back the upgrade headers removed with the other hop-by-hop headers
+pub fn restore_upgrade_headers(headers: &mut HeaderMap, protocol: HeaderValue) {
+    headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(UPGRADE, protocol);
+}
+
+/// Copies bytes between the client and upstream connections once both are upgraded, until either
+/// side closes
+pub fn splice(client: OnUpgrade, upstream: OnUpgrade) {
+    tokio::spawn(
+        async move {
+            let (mut client, mut upstream) = match tokio::try_join!(client, upstream) {
+                Ok(connections) => connections,
+                Err(err) => {
+                    warn!(error = %err, "Failed to upgrade connection");
+                    return;
+                }
+            };
+
+            match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+                Ok((sent, received)) => info!(sent, received, "Upgraded connection closed"),
+                Err(err) => warn!(error = %err, "Upgraded connection failed"),
+            }
+        }
+        .in_current_span(),
+    );
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Request};
+use reverse_proxy::*;
+use std::{
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+fn proxy_state(upstream: SocketAddr, access_log: &str) -> ProxyState {
+    state_of(&format!(
+        r#"
+        [access_log]
+        {}
+
+        [[routes]]
+        name = "echo"
+        prefix = "/echo"
+        upstream = "http://{}"
+        "#,
+        access_log, upstream
+    ))
+}
+
+async fn body_through(state: &ProxyState, request: Request<Body>) -> String {
+    body_of(send(state, request).await).await
+}
+
+fn lines_of(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+fn entry() -> AccessLogEntry {
+    AccessLogEntry {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(784_111_777_250),
+        client_ip: "203.0.113.7".parse().unwrap(),
+        method: "GET".to_string(),
+        target: "/blog/posts?page=2".to_string(),
+        version: "HTTP/1.1".to_string(),
+        status: 200,
+        bytes_in: 0,
+        bytes_out: 512,
+        referer: None,
+        user_agent: Some("curl/8.0 \"quoted\"".to_string()),
+        request_id: "abc".to_string(),
+        route: "blog".to_string(),
+        upstream: Some("http://blog.internal".to_string()),
+        upstream_latency: Some(Duration::from_micros(12_500)),
+        latency: Duration::from_micros(15_250),
+    }
+}
+
+#[test]
+fn formats_entries() {
+    assert_eq!(
+        entry().combined(),
+        "203.0.113.7 - - [06/Nov/1994:08:49:37 +0000] \"GET /blog/posts?page=2 HTTP/1.1\" 200 512 \
+         \"-\" \"curl/8.0 \\\"quoted\\\"\" 0 abc blog http://blog.internal 12.5 15.25\n"
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&entry().json()).unwrap();
+    assert_eq!(json["timestamp"], "1994-11-06T08:49:37.250Z");
+    assert_eq!(json["client_ip"], "203.0.113.7");
+    assert_eq!(json["path"], "/blog/posts?page=2");
+    assert_eq!(json["status"], 200);
+    assert_eq!(json["bytes_out"], 512);
+    assert_eq!(json["referer"], serde_json::Value::Null);
+    assert_eq!(json["upstream"], "http://blog.internal");
+    assert_eq!(json["upstream_latency_ms"], 12.5);
+    assert_eq!(json["latency_ms"], 15.25);
+}
+
+#[tokio::test]
+async fn logs_proxied_requests() {
+    let dir = temp_dir("access_log_json");
+    let path = dir.join("access.log");
+    let upstream = body_size_upstream().await;
+    let state = proxy_state(
+        upstream,
+        &format!("format = \"json\"\npath = \"{}\"", path.display()),
+    );
+
+    let request = Request::post("http://localhost:8080/echo/./upload?x=1")
+        .header("x-request-id", "upload-1")
+        .header("referer", "http://localhost:8080/form")
+        .body(Body::from("0123456789"))
+        .unwrap();
+    assert_eq!(body_through(&state, request).await, "echo 10");
+    let request = Request::get("http://localhost:8080/missing")
+        .body(Body::empty())
+        .unwrap();
+    body_through(&state, request).await;
+
+    state.access_log.flush();
+    let lines = lines_of(&path);
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    let json: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!(json["client_ip"], "203.0.113.7");
+    assert_eq!(json["method"], "POST");
+    assert_eq!(json["path"], "/echo/./upload?x=1");
+    assert_eq!(json["status"], 200);
+    assert_eq!(json["bytes_in"], 10);
+    assert_eq!(json["bytes_out"], 7);
+    assert_eq!(json["referer"], "http://localhost:8080/form");
+    assert_eq!(json["request_id"], "upload-1");
+    assert_eq!(json["route"], "echo");
+    assert_eq!(json["upstream"], format!("http://{}", upstream));
+    assert!(json["upstream_latency_ms"].as_f64().unwrap() <= json["latency_ms"].as_f64().unwrap());
+
+    let json: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+    assert_eq!(json["status"], 404);
+    assert_eq!(json["route"], "not_found");
+    assert_eq!(json["upstream"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn rotates_the_log_file() {
+    let dir = temp_dir("access_log_rotation");
+    let path = dir.join("access.log");
+    let upstream = body_size_upstream().await;
+    let state = proxy_state(
+        upstream,
+        &format!(
+            "path = \"{}\"\nmax_bytes = 300\nmax_files = 2",
+            path.display()
+        ),
+    );
+
+    for index in 0..8 {
+        let request = Request::get(format!("http://localhost:8080/echo/{}", index))
+            .body(Body::empty())
+            .unwrap();
+        body_through(&state, request).await;
+    }
+
+    state.access_log.flush();
+    let current = lines_of(&path);
+    let rotated = lines_of(&dir.join("access.log.1"));
+    let oldest = lines_of(&dir.join("access.log.2"));
+    assert!(!dir.join("access.log.3").exists());
+    for lines in [&current, &rotated, &oldest] {
+        assert!(!lines.is_empty());
+        assert!(lines.iter().map(|line| line.len() + 1).sum::<usize>() <= 300);
+    }
+    // The newest requests are in the current file, older ones shift up
+    assert!(current
+        .last()
+        .unwrap()
+        .contains("\"GET /echo/7 HTTP/1.1\" 200 6"));
+    assert!(rotated.last().unwrap().contains("/echo/"));
+    assert!(!oldest.iter().any(|line| line.contains("/echo/7 ")));
+}
+
+#[test]
+fn rejects_invalid_configuration() {
+    let invalid = ["format = \"clf\"", "max_bytes = 0", "path = 1"];
+    for access_log in invalid {
+        let contents = format!(
+            "listen_addr = \"127.0.0.1:8080\"\nbase_url = \"http://localhost:8080\"\n[access_log]\n{}",
+            access_log
+        );
+        assert!(
+            matches!(
+                ReverseProxyConfig::parse(&contents, ConfigFormat::Toml),
+                Err(ReverseProxyError::InvalidConfiguration(_))
+            ),
+            "Expected access log to be rejected: {}",
+            access_log
+        );
+    }
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Request, Response, StatusCode};
+use reverse_proxy::*;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+fn proxy_state(upstreams: &[SocketAddr], options: &str) -> ProxyState {
+    let upstreams = upstreams
+        .iter()
+        .map(|addr| format!("\"http://{}\"", addr))
+        .collect::<Vec<_>>()
+        .join(", ");
+    state_of(&format!(
+        r#"
+        [[routes]]
+        prefix = "/upstream"
+        upstreams = [{upstreams}]
+
+        [routes.options]
+        {options}
+        "#,
+    ))
+}
+
+/// Starts an upstream answering with its name and `status`, counting the requests it received
+///
+/// Requests to `/slow` are answered after a delay
+async fn upstream(name: &'static str, status: StatusCode) -> (SocketAddr, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let addr = start_upstream(move |req| {
+        let counter = counter.clone();
+        async move {
+            if req.uri().path() == "/slow" {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+            if req.uri().path() != "/healthcheck" {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            Response::builder()
+                .status(status)
+                .body(Body::from(name))
+                .unwrap()
+        }
+    });
+    (addr, requests)
+}
+
+fn route_status(state: &ProxyState) -> Vec<InstanceStatus> {
+    let config = state.config.get();
+    state
+        .upstreams
+        .status(&config.routes.routes()[0], &state.breakers)
+}
+
+#[tokio::test]
+async fn spreads_requests_across_instances() {
+    let (a, _) = upstream("a", StatusCode::OK).await;
+    let (b, _) = upstream("b", StatusCode::OK).await;
+    let state = proxy_state(&[a, b], "");
+
+    let mut counts = HashMap::new();
+    for _ in 0..4 {
+        let response = reverse_proxy(state.clone(), client(), get("/upstream/"))
+            .await
+            .unwrap();
+        *counts.entry(body_of(response).await).or_insert(0) += 1;
+    }
+    assert_eq!(counts["a"], 2);
+    assert_eq!(counts["b"], 2);
+}
+
+#[tokio::test]
+async fn sends_requests_to_the_least_busy_instance() {
+    let (a, _) = upstream("a", StatusCode::OK).await;
+    let (b, _) = upstream("b", StatusCode::OK).await;
+    let state = proxy_state(&[a, b], r#"load_balancing = "least_connections""#);
+
+    let slow = tokio::spawn(reverse_proxy(
+        state.clone(),
+        client(),
+        get("/upstream/slow"),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut quick = Vec::new();
+    for _ in 0..3 {
+        let response = reverse_proxy(state.clone(), client(), get("/upstream/"))
+            .await
+            .unwrap();
+        quick.push(body_of(response).await);
+    }
+    let slow = body_of(slow.await.unwrap().unwrap()).await;
+    assert!(quick.iter().all(|name| *name != slow), "{slow} {quick:?}");
+}
+
+#[tokio::test]
+async fn retries_idempotent_requests_on_another_instance() {
+    let (ok, _) = upstream("ok", StatusCode::OK).await;
+    let (unavailable, unavailable_requests) =
+        upstream("down", StatusCode::SERVICE_UNAVAILABLE).await;
+
+    for failing in [closed_upstream().await, unavailable] {
+        let state = proxy_state(&[failing, ok], "");
+        for _ in 0..4 {
+            let response = reverse_proxy(state.clone(), client(), get("/upstream/"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(body_of(response).await, "ok");
+        }
+    }
+    assert_eq!(unavailable_requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn does_not_retry_requests_with_a_body() {
+    let (ok, _) = upstream("ok", StatusCode::OK).await;
+    let (unavailable, unavailable_requests) =
+        upstream("down", StatusCode::SERVICE_UNAVAILABLE).await;
+    let state = proxy_state(&[unavailable, ok], "");
+
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let request = Request::post("http://localhost:8080/upstream/")
+            .body(Body::from("payload"))
+            .unwrap();
+        let response = reverse_proxy(state.clone(), client(), request)
+            .await
+            .unwrap();
+        statuses.push(response.status().as_u16());
+    }
+    statuses.sort();
+    assert_eq!(statuses, [200, 503]);
+    assert_eq!(unavailable_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn ejects_instances_which_keep_failing() {
+    let (ok, _) = upstream("ok", StatusCode::OK).await;
+    let (failing, failing_requests) = upstream("failing", StatusCode::INTERNAL_SERVER_ERROR).await;
+    let state = proxy_state(
+        &[failing, ok],
+        "retries = 0\noutlier_detection = { consecutive_failures = 2, ejection_ms = 60000 }",
+    );
+
+    for _ in 0..8 {
+        reverse_proxy(state.clone(), client(), get("/upstream/"))
+            .await
+            .unwrap();
+    }
+    assert_eq!(failing_requests.load(Ordering::SeqCst), 2);
+
+    let status = route_status(&state);
+    assert!(status[0].ejected);
+    assert!(status[0].healthy);
+    assert!(!status[1].ejected);
+}
+
+#[tokio::test]
+async fn stops_sending_requests_to_unhealthy_instances() {
+    let (ok, _) = upstream("ok", StatusCode::OK).await;
+    let (unhealthy, unhealthy_requests) = upstream("down", StatusCode::SERVICE_UNAVAILABLE).await;
+    let state = proxy_state(
+        &[unhealthy, ok],
+        "retries = 0\nhealth_check = { interval_ms = 50, unhealthy_threshold = 1 }",
+    );
+    state.start_health_checks();
+
+    let mut attempts = 0;
+    while route_status(&state)[0].healthy {
+        attempts += 1;
+        assert!(attempts < 100, "instance never marked unhealthy");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(route_status(&state)[1].healthy);
+
+    for _ in 0..4 {
+        let response = reverse_proxy(state.clone(), client(), get("/upstream/"))
+            .await
+            .unwrap();
+        assert_eq!(body_of(response).await, "ok");
+    }
+    assert_eq!(unhealthy_requests.load(Ordering::SeqCst), 0);
+}
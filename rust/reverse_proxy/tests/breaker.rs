@@ -0,0 +1,177 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Response};
+use reverse_proxy::*;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+const INSTANCE: &str = "http://10.0.0.1:8080";
+
+fn options() -> CircuitBreakerOptions {
+    CircuitBreakerOptions {
+        failure_threshold: 2,
+        open_ms: 1_000,
+        success_threshold: 1,
+        fallback_page: None,
+    }
+}
+
+fn proxy_state(upstream: SocketAddr, circuit_breaker: &str) -> ProxyState {
+    state_of(&format!(
+        r#"
+        [[routes]]
+        name = "librarian"
+        prefix = "/librarian"
+        upstream = "http://{upstream}"
+
+        [routes.options]
+        retries = 0
+        circuit_breaker = {{ {circuit_breaker} }}
+        "#,
+    ))
+}
+
+#[test]
+fn opens_after_failures_and_closes_after_a_trial() {
+    let breakers = CircuitBreakers::new();
+    let options = options();
+    let now = Instant::now();
+
+    assert_eq!(breakers.allow_at(INSTANCE, &options, now), Ok(()));
+    breakers.record(INSTANCE, true, &options);
+    breakers.record(INSTANCE, false, &options);
+    breakers.record(INSTANCE, true, &options);
+    assert_eq!(breakers.status(INSTANCE).state, CircuitState::Closed);
+    breakers.record(INSTANCE, true, &options);
+    assert_eq!(breakers.status(INSTANCE).state, CircuitState::Open);
+    let rejected = breakers.allow_at(INSTANCE, &options, Instant::now());
+    assert_eq!(rejected, Err(1));
+
+    // A single trial once the circuit has been open long enough
+    let later = Instant::now() + Duration::from_millis(options.open_ms);
+    assert_eq!(breakers.allow_at(INSTANCE, &options, later), Ok(()));
+    assert_eq!(breakers.status(INSTANCE).state, CircuitState::HalfOpen);
+    assert!(breakers.allow_at(INSTANCE, &options, later).is_err());
+    breakers.record(INSTANCE, false, &options);
+    assert_eq!(breakers.status(INSTANCE).state, CircuitState::Closed);
+    assert_eq!(breakers.allow_at(INSTANCE, &options, later), Ok(()));
+}
+
+#[test]
+fn reopens_when_the_trial_fails() {
+    let breakers = CircuitBreakers::new();
+    let options = options();
+    breakers.record(INSTANCE, true, &options);
+    breakers.record(INSTANCE, true, &options);
+
+    let later = Instant::now() + Duration::from_millis(options.open_ms);
+    assert_eq!(breakers.allow_at(INSTANCE, &options, later), Ok(()));
+    breakers.record(INSTANCE, true, &options);
+    assert_eq!(breakers.status(INSTANCE).state, CircuitState::Open);
+    assert!(breakers.allow_at(INSTANCE, &options, later).is_err());
+    assert_eq!(
+        breakers.status("http://10.0.0.2:8080").state,
+        CircuitState::Closed
+    );
+}
+
+#[tokio::test]
+async fn fails_fast_while_the_upstream_is_down() {
+    let state = proxy_state(
+        closed_upstream().await,
+        "failure_threshold = 2, open_ms = 60000",
+    );
+
+    for _ in 0..2 {
+        let response = reverse_proxy(state.clone(), client(), get("/librarian/books"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 502);
+    }
+
+    let response = reverse_proxy(state.clone(), client(), get("/librarian/books"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+    assert_eq!(response.headers()["retry-after"], "60");
+    let body: serde_json::Value = serde_json::from_str(&body_of(response).await).unwrap();
+    assert_eq!(body["errorType"], "CircuitOpen");
+
+    let response = admin_request(state, get("/healthz/upstreams"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let upstreams: Vec<UpstreamHealth> = serde_json::from_str(&body_of(response).await).unwrap();
+    assert_eq!(upstreams[0].name, "librarian");
+    assert_eq!(upstreams[0].instances.len(), 1);
+    assert_eq!(upstreams[0].instances[0].circuit.state, CircuitState::Open);
+}
+
+#[tokio::test]
+async fn opens_the_circuit_of_the_failing_instance_only() {
+    let down = closed_upstream().await;
+    let up = start_upstream(|_| async { Response::new(Body::from("up")) });
+
+    let state = state_of(&format!(
+        r#"
+        [[routes]]
+        name = "librarian"
+        prefix = "/librarian"
+        upstreams = ["http://{down}", "http://{up}"]
+
+        [routes.options]
+        retries = 0
+        circuit_breaker = {{ failure_threshold = 1, open_ms = 60000 }}
+        outlier_detection = {{ consecutive_failures = 100 }}
+        "#,
+    ));
+
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        let response = reverse_proxy(state.clone(), client(), get("/librarian/books"))
+            .await
+            .unwrap();
+        statuses.push(response.status().as_u16());
+    }
+    // Only the first request reaches the instance which is down
+    assert_eq!(statuses, [502, 200, 200, 200]);
+    assert_eq!(
+        state.breakers.status(&format!("http://{}", down)).state,
+        CircuitState::Open
+    );
+    assert_eq!(
+        state.breakers.status(&format!("http://{}", up)).state,
+        CircuitState::Closed
+    );
+}
+
+#[tokio::test]
+async fn sends_the_fallback_page_while_open() {
+    let page = std::env::temp_dir().join(format!("fallback-{}.html", std::process::id()));
+    std::fs::write(&page, "<h1>Back soon</h1>").unwrap();
+    let state = proxy_state(
+        closed_upstream().await,
+        &format!(
+            "failure_threshold = 1, open_ms = 60000, fallback_page = \"{}\"",
+            page.display()
+        ),
+    );
+
+    reverse_proxy(state.clone(), client(), get("/librarian/books"))
+        .await
+        .unwrap();
+    let response = reverse_proxy(state, client(), get("/librarian/books"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 503);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/html; charset=utf-8"
+    );
+    assert!(response.headers().contains_key("retry-after"));
+    assert_eq!(body_of(response).await, "<h1>Back soon</h1>");
+    std::fs::remove_file(page).unwrap();
+}
@@ -0,0 +1,252 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Request, Response, Server};
+use reverse_proxy::*;
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+const PURGE_TOKEN: &str = "purge-secret";
+
+fn proxy_state(upstream: SocketAddr, cache: &str) -> ProxyState {
+    state_of(&format!(
+        r#"
+        [[routes]]
+        prefix = "/upstream"
+        upstream = "http://{upstream}"
+        options = {{ cache = {{ ttl_secs = 60 }} }}
+
+        [cache]
+        purge_token = "{PURGE_TOKEN}"
+        {cache}
+        "#,
+    ))
+}
+
+fn purge(token: &str, body: &str) -> Request<Body> {
+    Request::post("http://localhost:8080/cache/purge")
+        .header("authorization", format!("Bearer {}", token))
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Starts an upstream counting the requests made to it, each path answers with different
+/// caching headers
+async fn counting_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let counter = counter.clone();
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |req: Request<Body>| {
+                let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    let response = match req.uri().path() {
+                        "/no-store" => Response::builder().header("cache-control", "no-store"),
+                        "/etag"
+                            if req
+                                .headers()
+                                .get("if-none-match")
+                                .is_some_and(|v| v == "\"v1\"") =>
+                        {
+                            return Ok::<_, Infallible>(
+                                Response::builder()
+                                    .status(304)
+                                    .header("etag", "\"v1\"")
+                                    .header("cache-control", "no-cache")
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            );
+                        }
+                        "/etag" => Response::builder()
+                            .header("etag", "\"v1\"")
+                            .header("cache-control", "no-cache"),
+                        // Revalidations are answered with a 304 which can't be cached
+                        "/etag-no-store" if req.headers().contains_key("if-none-match") => {
+                            return Ok::<_, Infallible>(
+                                Response::builder()
+                                    .status(304)
+                                    .header("etag", "\"v1\"")
+                                    .header("cache-control", "no-store")
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            );
+                        }
+                        "/etag-no-store" => Response::builder()
+                            .header("etag", "\"v1\"")
+                            .header("cache-control", "no-cache"),
+                        _ => Response::builder().header("cache-control", "max-age=60"),
+                    };
+                    Ok(response
+                        .body(Body::from(format!("response {}", count)))
+                        .unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, requests)
+}
+
+#[tokio::test]
+async fn serves_fresh_responses_from_the_cache() {
+    let (addr, requests) = counting_upstream().await;
+    let state = proxy_state(addr, "");
+
+    let response = reverse_proxy(state.clone(), client(), get("/upstream/post"))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-cache"], "MISS");
+    assert_eq!(body_of(response).await, "response 1");
+
+    let response = reverse_proxy(state, client(), get("/upstream/post"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-cache"], "HIT");
+    assert!(response.headers().contains_key("age"));
+    assert_eq!(body_of(response).await, "response 1");
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn does_not_cache_no_store_responses() {
+    let (addr, requests) = counting_upstream().await;
+    let state = proxy_state(addr, "");
+
+    for expected in ["response 1", "response 2"] {
+        let response = reverse_proxy(state.clone(), client(), get("/upstream/no-store"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-cache"], "MISS");
+        assert_eq!(body_of(response).await, expected);
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn revalidates_stale_responses_with_the_upstream() {
+    let (addr, requests) = counting_upstream().await;
+    let state = proxy_state(addr, "");
+
+    let response = reverse_proxy(state.clone(), client(), get("/upstream/etag"))
+        .await
+        .unwrap();
+    assert_eq!(body_of(response).await, "response 1");
+
+    let response = reverse_proxy(state.clone(), client(), get("/upstream/etag"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-cache"], "REVALIDATED");
+    assert_eq!(body_of(response).await, "response 1");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    // The client's own validators are answered from the cache
+    let mut req = get("/upstream/etag");
+    req.headers_mut()
+        .insert("if-none-match", "\"v1\"".parse().unwrap());
+    let response = reverse_proxy(state, client(), req).await.unwrap();
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers()["etag"], "\"v1\"");
+    assert_eq!(body_of(response).await, "");
+}
+
+#[tokio::test]
+async fn refetches_when_the_revalidation_fails() {
+    let (addr, requests) = counting_upstream().await;
+    let state = proxy_state(addr, "");
+
+    let response = reverse_proxy(state.clone(), client(), get("/upstream/etag-no-store"))
+        .await
+        .unwrap();
+    assert_eq!(body_of(response).await, "response 1");
+
+    // The client sent no validators, so it never gets the upstream's 304
+    let response = reverse_proxy(state, client(), get("/upstream/etag-no-store"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-cache"], "MISS");
+    assert_eq!(body_of(response).await, "response 3");
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn purges_with_the_purge_token() {
+    let (addr, requests) = counting_upstream().await;
+    let state = proxy_state(addr, "");
+
+    for path in ["/upstream/posts/1", "/upstream/posts/2", "/upstream/about"] {
+        let response = reverse_proxy(state.clone(), client(), get(path))
+            .await
+            .unwrap();
+        body_of(response).await;
+    }
+
+    // Only served by the admin listener
+    let response = reverse_proxy(state.clone(), client(), purge(PURGE_TOKEN, ""))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    let response = admin_request(state.clone(), purge("wrong", ""))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = admin_request(
+        state.clone(),
+        purge(PURGE_TOKEN, r#"{"prefix": "/upstream/posts"}"#),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 200);
+    let body: PurgeCacheResponse = serde_json::from_str(&body_of(response).await).unwrap();
+    assert_eq!(body.purged, 2);
+
+    let response = reverse_proxy(state.clone(), client(), get("/upstream/about"))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-cache"], "HIT");
+    let response = reverse_proxy(state, client(), get("/upstream/posts/1"))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-cache"], "MISS");
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn spills_evicted_responses_to_disk() {
+    let dir = temp_dir("cache_spill");
+    let (addr, requests) = counting_upstream().await;
+    let state = proxy_state(
+        addr,
+        &format!("max_entries = 1\ndisk_dir = \"{}\"", dir.display()),
+    );
+
+    for path in ["/upstream/first", "/upstream/second"] {
+        let response = reverse_proxy(state.clone(), client(), get(path))
+            .await
+            .unwrap();
+        body_of(response).await;
+    }
+
+    // Evicted from memory by the second response, read back from disk
+    let response = reverse_proxy(state, client(), get("/upstream/first"))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-cache"], "HIT");
+    assert_eq!(body_of(response).await, "response 1");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
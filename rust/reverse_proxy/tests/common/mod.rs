@@ -0,0 +1,115 @@
+// Each test file only uses some of the fixtures
+#![allow(dead_code)]
+
+use hyper::{Body, Request, Response, Server};
+use reverse_proxy::*;
+use std::{convert::Infallible, future::Future, net::SocketAddr, path::PathBuf};
+use tokio::net::TcpListener;
+
+/* CONFIG */
+
+/// Parses a routing table, with the listen address and base url every test uses
+pub fn parse_toml(contents: &str) -> Result<ReverseProxyConfig, ReverseProxyError> {
+    let contents = format!(
+        "listen_addr = \"127.0.0.1:8080\"\nbase_url = \"http://localhost:8080\"\n{}",
+        contents
+    );
+    ReverseProxyConfig::parse(&contents, ConfigFormat::Toml)
+}
+
+/// The state of a proxy serving a routing table, see [parse_toml]
+pub fn state_of(contents: &str) -> ProxyState {
+    ProxyState::new(SharedConfig::new(parse_toml(contents).unwrap(), None))
+}
+
+/// A proxy with a single route proxying `/upstream` with the given route options
+pub fn upstream_state(upstream: SocketAddr, options: &str) -> ProxyState {
+    state_of(&format!(
+        r#"
+        [[routes]]
+        prefix = "/upstream"
+        upstream = "http://{upstream}"
+        options = {{ {options} }}
+        "#,
+    ))
+}
+
+/* REQUESTS */
+
+pub fn client() -> ClientConnection {
+    ClientConnection::new("203.0.113.7:50000".parse().unwrap())
+}
+
+pub fn get(path: &str) -> Request<Body> {
+    Request::get(format!("http://localhost:8080{}", path))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Sends a request through the proxy from [client]
+pub async fn send(state: &ProxyState, request: Request<Body>) -> Response<Body> {
+    reverse_proxy(state.clone(), client(), request)
+        .await
+        .unwrap()
+}
+
+pub async fn body_of(response: Response<Body>) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/* UPSTREAMS */
+
+/// Starts an upstream answering every request with `handler`
+pub fn start_upstream<F, R>(handler: F) -> SocketAddr
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |req| {
+                let response = handler(req);
+                async move { Ok::<_, Infallible>(response.await) }
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// Starts an upstream answering with the path and query it received
+pub async fn echo_upstream() -> SocketAddr {
+    start_upstream(|req| async move {
+        Response::new(Body::from(req.uri().path_and_query().unwrap().to_string()))
+    })
+}
+
+/// Starts an upstream answering with the size of the body it received, eg. `echo 10`
+pub async fn body_size_upstream() -> SocketAddr {
+    start_upstream(|req| async move {
+        let body = hyper::body::to_bytes(req.into_body())
+            .await
+            .unwrap_or_default();
+        Response::new(Body::from(format!("echo {}", body.len())))
+    })
+}
+
+/// An address nothing listens on
+pub async fn closed_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+/* FILES */
+
+/// An empty directory of its own for a test
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("reverse_proxy_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder};
+use common::*;
+use hyper::{Body, HeaderMap, Request, Response};
+use reverse_proxy::*;
+use std::net::SocketAddr;
+use tokio::io::AsyncReadExt;
+
+fn get_encoded(path: &str, accept_encoding: &str) -> Request<Body> {
+    Request::get(format!("http://localhost:8080{}", path))
+        .header("accept-encoding", accept_encoding)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn page() -> String {
+    "<p>A paragraph of the blog</p>".repeat(200)
+}
+
+/// Starts an upstream answering with a large page, or with the variations its path asks for
+async fn upstream() -> SocketAddr {
+    start_upstream(|req| async move {
+        let response = match req.uri().path() {
+            "/small" => Response::builder()
+                .header("content-type", "text/html")
+                .body(Body::from("<p>Small</p>")),
+            "/image" => Response::builder()
+                .header("content-type", "image/png")
+                .body(Body::from(page())),
+            "/encoded" => Response::builder()
+                .header("content-type", "text/html")
+                .header("content-encoding", "gzip")
+                .body(Body::from(page())),
+            _ => Response::builder()
+                .header("content-type", "text/html; charset=utf-8")
+                .header("etag", "\"page\"")
+                .body(Body::from(page())),
+        };
+        response.unwrap()
+    })
+}
+
+async fn decoded_body(response: Response<Body>) -> String {
+    let encoding = response
+        .headers()
+        .get("content-encoding")
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let mut decoded = String::new();
+    match encoding.as_deref() {
+        Some("br") => BrotliDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .await
+            .unwrap(),
+        Some("gzip") => GzipDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .await
+            .unwrap(),
+        _ => return String::from_utf8(body.to_vec()).unwrap(),
+    };
+    decoded
+}
+
+#[test]
+fn negotiates_the_preferred_encoding() {
+    let cases = [
+        ("gzip, deflate, br", Some(Encoding::Brotli)),
+        ("gzip", Some(Encoding::Gzip)),
+        ("br;q=0.5, gzip;q=0.8", Some(Encoding::Gzip)),
+        ("br;q=0, gzip;q=0", None),
+        ("*", Some(Encoding::Brotli)),
+        ("*, br;q=0", Some(Encoding::Gzip)),
+        ("identity", None),
+        ("", None),
+    ];
+    for (accept_encoding, expected) in cases {
+        let mut headers = HeaderMap::new();
+        headers.insert("accept-encoding", accept_encoding.parse().unwrap());
+        assert_eq!(
+            Encoding::negotiate(&headers),
+            expected,
+            "{}",
+            accept_encoding
+        );
+    }
+}
+
+#[tokio::test]
+async fn compresses_text_responses() {
+    let state = upstream_state(upstream().await, "");
+
+    for (accept_encoding, encoding) in [("gzip, br", "br"), ("gzip", "gzip")] {
+        let response = reverse_proxy(
+            state.clone(),
+            client(),
+            get_encoded("/upstream/", accept_encoding),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-encoding"], encoding);
+        assert_eq!(response.headers()["vary"], "Accept-Encoding");
+        assert_eq!(response.headers()["etag"], "W/\"page\"");
+        assert!(!response.headers().contains_key("content-length"));
+        assert_eq!(decoded_body(response).await, page());
+    }
+}
+
+#[tokio::test]
+async fn varies_on_accept_encoding_when_not_compressing() {
+    let state = upstream_state(upstream().await, "");
+
+    let response = reverse_proxy(
+        state.clone(),
+        client(),
+        get_encoded("/upstream/", "identity"),
+    )
+    .await
+    .unwrap();
+    assert!(!response.headers().contains_key("content-encoding"));
+    assert_eq!(response.headers()["vary"], "Accept-Encoding");
+    assert_eq!(response.headers()["etag"], "\"page\"");
+    assert_eq!(decoded_body(response).await, page());
+
+    let response = reverse_proxy(state, client(), get_encoded("/upstream/small", "br"))
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key("content-encoding"));
+    assert_eq!(response.headers()["vary"], "Accept-Encoding");
+}
+
+#[tokio::test]
+async fn leaves_other_responses_alone() {
+    let state = upstream_state(upstream().await, "");
+
+    for path in ["/upstream/image", "/upstream/encoded"] {
+        let response = reverse_proxy(state.clone(), client(), get_encoded(path, "br"))
+            .await
+            .unwrap();
+        assert!(response
+            .headers()
+            .get("content-encoding")
+            .is_none_or(|encoding| *encoding != "br"));
+        assert!(!response.headers().contains_key("vary"));
+    }
+
+    let state = upstream_state(upstream().await, "compress = false");
+    let response = reverse_proxy(state, client(), get_encoded("/upstream/", "br"))
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key("content-encoding"));
+    assert_eq!(decoded_body(response).await, page());
+}
@@ -0,0 +1,127 @@
+use hyper::HeaderMap;
+use reverse_proxy::*;
+
+fn connection(addr: &str) -> ClientConnection {
+    ClientConnection::new(addr.parse().unwrap())
+}
+
+fn trusted(ranges: &[&str]) -> TrustedProxies {
+    let ranges: Vec<String> = ranges.iter().map(|range| range.to_string()).collect();
+    TrustedProxies::new(&ranges).unwrap()
+}
+
+fn spoofed_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("host", "pastureen.com".parse().unwrap());
+    headers.insert("x-forwarded-for", "1.2.3.4".parse().unwrap());
+    headers.insert("x-forwarded-proto", "https".parse().unwrap());
+    headers.insert("x-forwarded-host", "evil.com".parse().unwrap());
+    headers.insert("forwarded", "for=1.2.3.4".parse().unwrap());
+    headers
+}
+
+#[test]
+fn replaces_headers_from_untrusted_clients() {
+    let mut headers = spoofed_headers();
+
+    set_forwarded_headers(
+        &mut headers,
+        &connection("203.0.113.7:5000"),
+        &trusted(&["10.0.0.0/8"]),
+    )
+    .unwrap();
+
+    assert_eq!(headers["x-forwarded-for"], "203.0.113.7");
+    assert_eq!(headers["x-forwarded-proto"], "http");
+    assert_eq!(headers["x-forwarded-host"], "pastureen.com");
+    assert_eq!(
+        headers["forwarded"],
+        "for=203.0.113.7;proto=http;host=pastureen.com"
+    );
+}
+
+#[test]
+fn extends_headers_from_trusted_proxies() {
+    let mut headers = spoofed_headers();
+
+    set_forwarded_headers(
+        &mut headers,
+        &connection("10.1.2.3:5000"),
+        &trusted(&["10.0.0.0/8"]),
+    )
+    .unwrap();
+
+    assert_eq!(headers["x-forwarded-for"], "1.2.3.4, 10.1.2.3");
+    assert_eq!(headers["x-forwarded-proto"], "https");
+    assert_eq!(headers["x-forwarded-host"], "evil.com");
+    assert_eq!(
+        headers["forwarded"],
+        "for=1.2.3.4, for=10.1.2.3;proto=http;host=pastureen.com"
+    );
+}
+
+#[test]
+fn quotes_ipv6_and_ports_in_forwarded() {
+    let mut headers = HeaderMap::new();
+    headers.insert("host", "localhost:8080".parse().unwrap());
+
+    set_forwarded_headers(
+        &mut headers,
+        &connection("[2001:db8::1]:5000"),
+        &TrustedProxies::default(),
+    )
+    .unwrap();
+
+    assert_eq!(headers["x-forwarded-for"], "2001:db8::1");
+    assert_eq!(
+        headers["forwarded"],
+        "for=\"[2001:db8::1]\";proto=http;host=\"localhost:8080\""
+    );
+}
+
+#[test]
+fn strips_hop_by_hop_headers() {
+    let mut headers = HeaderMap::new();
+    headers.insert("connection", "keep-alive, x-internal".parse().unwrap());
+    headers.insert("keep-alive", "timeout=5".parse().unwrap());
+    headers.insert("te", "trailers".parse().unwrap());
+    headers.insert("x-internal", "secret".parse().unwrap());
+    headers.insert("content-type", "text/html".parse().unwrap());
+
+    strip_hop_by_hop_headers(&mut headers);
+
+    assert_eq!(headers.len(), 1);
+    assert_eq!(headers["content-type"], "text/html");
+}
+
+#[test]
+fn matches_trusted_ranges() {
+    let proxies = trusted(&["127.0.0.1", "10.0.0.0/8", "fd00::/8"]);
+
+    assert!(proxies.is_trusted("127.0.0.1".parse().unwrap()));
+    assert!(!proxies.is_trusted("127.0.0.2".parse().unwrap()));
+    assert!(proxies.is_trusted("10.255.0.1".parse().unwrap()));
+    assert!(proxies.is_trusted("::ffff:10.0.0.1".parse().unwrap()));
+    assert!(proxies.is_trusted("fd12::1".parse().unwrap()));
+    assert!(!proxies.is_trusted("fe80::1".parse().unwrap()));
+
+    for invalid in ["10.0.0.0/33", "not an ip", "::/129"] {
+        assert!(IpRange::parse(invalid).is_err());
+    }
+}
+
+#[test]
+fn finds_the_client_behind_trusted_proxies() {
+    let proxies = trusted(&["10.0.0.0/8"]);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-forwarded-for",
+        "1.2.3.4, 198.51.100.9, 10.0.0.2".parse().unwrap(),
+    );
+
+    let from_proxy = client_ip(&headers, &connection("10.1.2.3:5000"), &proxies);
+    assert_eq!(from_proxy.to_string(), "198.51.100.9");
+
+    let from_client = client_ip(&headers, &connection("203.0.113.7:5000"), &proxies);
+    assert_eq!(from_client.to_string(), "203.0.113.7");
+}
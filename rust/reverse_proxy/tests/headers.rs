@@ -0,0 +1,112 @@
+use hyper::HeaderMap;
+use reverse_proxy::*;
+
+fn parse_toml(contents: &str) -> Result<ReverseProxyConfig, ReverseProxyError> {
+    let contents = format!(
+        "listen_addr = \"127.0.0.1:8080\"\nbase_url = \"https://pastureen.com\"\n{}",
+        contents
+    );
+    ReverseProxyConfig::parse(&contents, ConfigFormat::Toml)
+}
+
+fn route(config: &ReverseProxyConfig) -> &RouteConfig {
+    &config.routes.routes()[0]
+}
+
+const BLOG_ROUTE: &str = r#"
+[[routes]]
+prefix = "/blog"
+upstream = "http://blog.internal"
+"#;
+
+#[test]
+fn removes_aws_headers_by_default() {
+    let config = parse_toml(BLOG_ROUTE).unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-amzn-requestid", "abc".parse().unwrap());
+    headers.insert("x-amzn-trace-id", "abc".parse().unwrap());
+    headers.insert("content-type", "text/html".parse().unwrap());
+
+    config.headers.response.apply(&mut headers);
+
+    assert_eq!(headers.len(), 1);
+}
+
+#[test]
+fn applies_remove_set_and_add_rules() {
+    let config = parse_toml(&format!(
+        r#"
+        {}
+        [routes.options.headers.response]
+        remove = ["x-powered-by"]
+        set = {{ "x-frame-options" = "DENY" }}
+        add = {{ "vary" = "Cookie" }}
+        "#,
+        BLOG_ROUTE
+    ))
+    .unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-powered-by", "php".parse().unwrap());
+    headers.insert("x-frame-options", "SAMEORIGIN".parse().unwrap());
+    headers.insert("vary", "Accept-Encoding".parse().unwrap());
+
+    route(&config).options.headers.response.apply(&mut headers);
+
+    assert!(headers.get("x-powered-by").is_none());
+    assert_eq!(headers["x-frame-options"], "DENY");
+    let vary: Vec<_> = headers.get_all("vary").iter().collect();
+    assert_eq!(vary, ["Accept-Encoding", "Cookie"]);
+}
+
+#[test]
+fn rejects_invalid_header_rules() {
+    let invalid = [
+        "[headers.request]\nremove = [\"bad header\"]",
+        "[headers.response]\nset = { \"x-ok\" = \"bad\\nvalue\" }",
+    ];
+    for rules in invalid {
+        assert!(
+            parse_toml(rules).is_err(),
+            "Expected rules to be rejected: {}",
+            rules
+        );
+    }
+}
+
+#[test]
+fn configured_global_rules_replace_defaults() {
+    let config = parse_toml("[headers.response]\nremove = [\"server\"]").unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-amzn-requestid", "abc".parse().unwrap());
+    headers.insert("server", "nginx".parse().unwrap());
+
+    config.headers.response.apply(&mut headers);
+
+    assert_eq!(headers["x-amzn-requestid"], "abc");
+    assert!(headers.get("server").is_none());
+}
+
+#[test]
+fn rewrites_location_of_upstream() {
+    let config = parse_toml(BLOG_ROUTE).unwrap();
+    let cases = [
+        (
+            "http://blog.internal/posts/1?a=b",
+            "https://pastureen.com/blog/posts/1?a=b",
+        ),
+        ("http://blog.internal", "https://pastureen.com/blog"),
+        (
+            "http://blog.internal.evil.com/",
+            "http://blog.internal.evil.com/",
+        ),
+        ("https://elsewhere.com/", "https://elsewhere.com/"),
+        ("/relative", "/relative"),
+    ];
+
+    for (location, expected) in cases {
+        let mut headers = HeaderMap::new();
+        headers.insert("location", location.parse().unwrap());
+        rewrite_location(&mut headers, route(&config), &config.base_url).unwrap();
+        assert_eq!(headers["location"], expected);
+    }
+}
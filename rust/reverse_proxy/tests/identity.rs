@@ -0,0 +1,156 @@
+mod common;
+
+use auth_models::{encode_token, Claims, TokenType};
+use common::*;
+use hyper::{Body, Request, Response};
+use reverse_proxy::*;
+use std::{
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECRET: &str = "edge-secret";
+const ADMIN: &str = "admin@example.com";
+
+fn proxy_state(upstream: SocketAddr) -> ProxyState {
+    state_of(&format!(
+        r#"
+        [auth]
+        secret = "{SECRET}"
+        admin_emails = ["{ADMIN}"]
+
+        [[routes]]
+        prefix = "/private"
+        upstream = "http://{upstream}"
+        options = {{ require_auth = true }}
+
+        [[routes]]
+        prefix = "/public"
+        upstream = "http://{upstream}"
+
+        [[routes]]
+        prefix = "/limited"
+        upstream = "http://{upstream}"
+        options = {{ require_auth = true, rate_limit = {{ requests = 2, per_secs = 60, key = "user" }} }}
+        "#,
+    ))
+}
+
+fn token(email: &str, token_type: TokenType, secret: &str, expires_in: i64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let claims = Claims {
+        sub: email.to_string(),
+        exp: now.checked_add_signed(expires_in).unwrap(),
+        iat: now,
+        token_type,
+        id: "token-id".to_string(),
+    };
+    encode_token(&claims, secret)
+}
+
+fn get_with(path: &str, token: Option<&str>) -> Request<Body> {
+    let mut builder = Request::get(format!("http://localhost:8080{}", path))
+        .header("x-user-email", "spoofed@example.com")
+        .header("x-user-roles", "admin");
+    if let Some(token) = token {
+        builder = builder.header("authorization", format!("Bearer {}", token));
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
+/// Starts an upstream answering with the identity headers it received
+async fn identity_upstream() -> SocketAddr {
+    start_upstream(|req| async move {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        };
+        let body = format!("{}|{}", header("x-user-email"), header("x-user-roles"));
+        Response::new(Body::from(body))
+    })
+}
+
+#[tokio::test]
+async fn rejects_missing_and_invalid_tokens() {
+    let state = proxy_state(identity_upstream().await);
+
+    let tokens = [
+        None,
+        Some("not-a-jwt".to_string()),
+        Some(token("user@example.com", TokenType::Access, "other", 600)),
+        Some(token("user@example.com", TokenType::Access, SECRET, -600)),
+        Some(token("user@example.com", TokenType::Refresh, SECRET, 600)),
+    ];
+    for token in tokens {
+        let response = reverse_proxy(
+            state.clone(),
+            client(),
+            get_with("/private/posts", token.as_deref()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 401, "{:?}", token);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+    }
+}
+
+#[tokio::test]
+async fn rate_limits_failed_authentications() {
+    let state = proxy_state(identity_upstream().await);
+
+    for status in [401, 401, 429] {
+        let response = reverse_proxy(
+            state.clone(),
+            client(),
+            get_with("/limited/posts", Some("not-a-jwt")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), status);
+    }
+
+    // Authenticated users have a bucket of their own
+    let user = token("user@example.com", TokenType::Access, SECRET, 600);
+    let response = reverse_proxy(state, client(), get_with("/limited/posts", Some(&user)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn forwards_the_identity_of_valid_tokens() {
+    let state = proxy_state(identity_upstream().await);
+
+    let user = token("user@example.com", TokenType::Access, SECRET, 600);
+    let response = reverse_proxy(
+        state.clone(),
+        client(),
+        get_with("/private/posts", Some(&user)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(body_of(response).await, "user@example.com|user");
+
+    let admin = token(ADMIN, TokenType::Access, SECRET, 600);
+    let response = reverse_proxy(state, client(), get_with("/private/posts", Some(&admin)))
+        .await
+        .unwrap();
+    assert_eq!(body_of(response).await, "admin@example.com|user,admin");
+}
+
+#[tokio::test]
+async fn strips_client_identity_headers_on_public_routes() {
+    let state = proxy_state(identity_upstream().await);
+
+    let response = reverse_proxy(state, client(), get_with("/public/posts", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(body_of(response).await, "|");
+}
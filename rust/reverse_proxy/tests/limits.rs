@@ -0,0 +1,192 @@
+mod common;
+
+use common::*;
+use hyper::{body::Bytes, Body, Request, StatusCode};
+use reverse_proxy::*;
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn config(upstream: SocketAddr, limits: &str) -> ReverseProxyConfig {
+    let contents = format!(
+        r#"
+        [limits]
+        {}
+
+        [[routes]]
+        name = "echo"
+        prefix = "/echo"
+        upstream = "http://{}"
+
+        [[routes]]
+        name = "small"
+        prefix = "/small"
+        upstream = "http://{}"
+
+        [routes.options]
+        max_body_bytes = 16
+        "#,
+        limits, upstream, upstream
+    );
+    parse_toml(&contents).unwrap()
+}
+
+fn proxy_state(upstream: SocketAddr, limits: &str) -> ProxyState {
+    ProxyState::new(SharedConfig::new(config(upstream, limits), None))
+}
+
+async fn status_and_body(state: &ProxyState, request: Request<Body>) -> (StatusCode, String) {
+    let response = send(state, request).await;
+    (response.status(), body_of(response).await)
+}
+
+/// A body sent in chunks without a `Content-Length`
+fn chunked(chunks: &[&'static str]) -> Body {
+    let chunks: Vec<Result<Bytes, Infallible>> = chunks
+        .iter()
+        .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+        .collect();
+    Body::wrap_stream(futures_util::stream::iter(chunks))
+}
+
+#[tokio::test]
+async fn rejects_bodies_over_the_route_limit() {
+    let state = proxy_state(body_size_upstream().await, "max_body_bytes = 32");
+
+    let request = Request::post("http://localhost:8080/small/upload")
+        .header("content-length", "17")
+        .body(Body::from("0123456789abcdefg"))
+        .unwrap();
+    let (status, body) = status_and_body(&state, request).await;
+    assert_eq!(status, 413);
+    assert!(body.contains("PayloadTooLarge"), "{}", body);
+
+    let request = Request::post("http://localhost:8080/small/upload")
+        .header("content-length", "16")
+        .body(Body::from("0123456789abcdef"))
+        .unwrap();
+    assert_eq!(
+        status_and_body(&state, request).await,
+        (StatusCode::OK, "echo 16".to_string())
+    );
+
+    // Routes without a limit of their own get the global one
+    let request = Request::post("http://localhost:8080/echo/upload")
+        .body(Body::from("x".repeat(33)))
+        .unwrap();
+    assert_eq!(status_and_body(&state, request).await.0, 413);
+    let request = Request::post("http://localhost:8080/echo/upload")
+        .body(Body::from("x".repeat(32)))
+        .unwrap();
+    assert_eq!(status_and_body(&state, request).await.0, 200);
+}
+
+#[tokio::test]
+async fn cuts_short_streamed_bodies_over_the_limit() {
+    let state = proxy_state(body_size_upstream().await, "");
+
+    let request = Request::post("http://localhost:8080/small/upload")
+        .body(chunked(&["0123456789", "abcdef"]))
+        .unwrap();
+    assert_eq!(
+        status_and_body(&state, request).await,
+        (StatusCode::OK, "echo 16".to_string())
+    );
+
+    let request = Request::post("http://localhost:8080/small/upload")
+        .body(chunked(&["0123456789", "abcdef", "g"]))
+        .unwrap();
+    let (status, body) = status_and_body(&state, request).await;
+    assert_eq!(status, 413);
+    assert!(body.contains("PayloadTooLarge(16)"), "{}", body);
+}
+
+#[tokio::test]
+async fn cuts_short_slow_bodies() {
+    let state = proxy_state(
+        body_size_upstream().await,
+        "min_body_bytes_per_sec = 100\nmin_body_rate_grace_ms = 200",
+    );
+
+    // 10 bytes buy another 100ms on top of the grace period, then the client stalls
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        sender.send_data(Bytes::from("0123456789")).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        drop(sender);
+    });
+    let request = Request::post("http://localhost:8080/echo/upload")
+        .body(body)
+        .unwrap();
+    let started = std::time::Instant::now();
+    let (status, body) = status_and_body(&state, request).await;
+    assert_eq!(status, 408);
+    assert!(body.contains("RequestTimeout"), "{}", body);
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // A body keeping up with the minimum rate goes through
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        for _ in 0..4 {
+            sender.send_data(Bytes::from("0123456789")).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    });
+    let request = Request::post("http://localhost:8080/echo/upload")
+        .body(body)
+        .unwrap();
+    assert_eq!(
+        status_and_body(&state, request).await,
+        (StatusCode::OK, "echo 40".to_string())
+    );
+}
+
+#[tokio::test]
+async fn closes_connections_without_headers() {
+    let state = proxy_state(body_size_upstream().await, "header_read_timeout_ms = 200");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, state).unwrap());
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\n")
+        .await
+        .unwrap();
+    let mut buf = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(2), stream.read_to_end(&mut buf)).await;
+    assert!(read.is_ok(), "Connection should be closed by the server");
+    assert!(!String::from_utf8_lossy(&buf).contains("echo"));
+}
+
+#[test]
+fn rejects_invalid_limits() {
+    let upstream = "127.0.0.1:9".parse().unwrap();
+    let invalid = [
+        "max_body_bytes = 0",
+        "header_read_timeout_ms = 0",
+        "min_body_bytes_per_sec = 0",
+    ];
+    for limits in invalid {
+        let contents = format!(
+            "listen_addr = \"127.0.0.1:8080\"\nbase_url = \"http://localhost:8080\"\n[limits]\n{}",
+            limits
+        );
+        assert!(
+            matches!(
+                ReverseProxyConfig::parse(&contents, ConfigFormat::Toml),
+                Err(ReverseProxyError::InvalidConfiguration(_))
+            ),
+            "Expected limits to be rejected: {}",
+            limits
+        );
+    }
+
+    let route = "[[routes]]\nprefix = \"/a\"\nupstream = \"http://localhost\"\n[routes.options]\nmax_body_bytes = 0";
+    let contents = format!(
+        "listen_addr = \"127.0.0.1:8080\"\nbase_url = \"http://localhost:8080\"\n{}",
+        route
+    );
+    assert!(ReverseProxyConfig::parse(&contents, ConfigFormat::Toml).is_err());
+    assert_eq!(config(upstream, "").limits, LimitsConfig::default());
+}
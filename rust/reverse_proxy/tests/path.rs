@@ -0,0 +1,184 @@
+mod common;
+
+use common::*;
+use proptest::prelude::*;
+use reverse_proxy::*;
+
+fn config() -> ReverseProxyConfig {
+    let contents = r#"
+        [[routes]]
+        prefix = "/auth"
+        upstream = "http://auth.internal"
+
+        [[routes]]
+        prefix = "/publisher"
+        upstream = "http://publisher.internal"
+
+        [[routes]]
+        prefix = "/blog/admin"
+        upstream = "http://admin.internal"
+    "#;
+    parse_toml(contents).unwrap()
+}
+
+/// The upstream uri of a path once normalised, or `None` when it isn't proxied
+fn route_of(config: &ReverseProxyConfig, path: &str) -> Option<String> {
+    let path = normalize_path(path).ok()?;
+    match config.routes.classify(&path) {
+        ClassifiedRoute::Proxy(route) => Some(route.proxied_uri()),
+        ClassifiedRoute::NonProxy(_) => None,
+    }
+}
+
+#[test]
+fn normalises_paths() {
+    let cases = [
+        ("/", "/"),
+        ("//auth", "/auth"),
+        ("/auth//login/", "/auth/login/"),
+        ("/auth/./login", "/auth/login"),
+        ("/auth/../publisher", "/publisher"),
+        ("/auth/login/..", "/auth/"),
+        ("/%61uth/%7euser", "/auth/~user"),
+        ("/auth/a%2fb", "/auth/a%2Fb"),
+        ("/auth/%c3%a9", "/auth/%C3%A9"),
+        ("/auth/%2E%2E/publisher", "/publisher"),
+        ("*", "*"),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(normalize_path(path).unwrap(), expected, "{}", path);
+    }
+}
+
+#[test]
+fn rejects_traversal() {
+    let paths = [
+        "/..",
+        "/auth/../../etc/passwd",
+        "/%2e%2e/etc",
+        "/auth/..%2f..%2fetc",
+        "/auth/%2e%2e%5cetc",
+        "/auth/%00",
+        "/auth/%zz",
+        "/auth/%4",
+    ];
+    for path in paths {
+        let err = normalize_path(path).unwrap_err();
+        assert_eq!(err.status_code(), 400, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn forwards_the_normalised_path_and_the_exact_query() {
+    let state = state_of(&format!(
+        r#"
+        [[routes]]
+        prefix = "/auth"
+        upstream = "http://{}"
+        "#,
+        echo_upstream().await
+    ));
+
+    let cases = [
+        (
+            "//auth/./users/%7ejo?b=2&a=%2f&a=1",
+            "/users/~jo?b=2&a=%2f&a=1",
+        ),
+        ("/publisher/../auth/login?", "/login?"),
+        ("/auth/posts?q=a+b&empty=&=x", "/posts?q=a+b&empty=&=x"),
+    ];
+    for (path, expected) in cases {
+        let response = send(&state, get(path)).await;
+        assert_eq!(response.status(), 200, "{}", path);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, expected, "{}", path);
+    }
+
+    let response = send(&state, get("/auth/../../etc/passwd")).await;
+    assert_eq!(response.status(), 400);
+}
+
+/// Path segments without dots or percent signs
+fn segment() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9_~-]{1,8}"
+}
+
+fn segments() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec(segment(), 0..5)
+}
+
+fn prefix() -> impl Strategy<Value = &'static str> {
+    prop::sample::select(vec![
+        "/auth",
+        "/publisher",
+        "/blog/admin",
+        "/blog",
+        "/other",
+    ])
+}
+
+proptest! {
+    #[test]
+    fn normalisation_is_idempotent(path in "/[a-z./%0-9A-F]{0,24}") {
+        if let Ok(normalized) = normalize_path(&path) {
+            prop_assert_eq!(normalize_path(&normalized).unwrap(), normalized.clone());
+            prop_assert!(normalized.starts_with('/'));
+            prop_assert!(!normalized.contains("//"));
+            prop_assert!(normalized.split('/').all(|segment| segment != "." && segment != ".."));
+        }
+    }
+
+    #[test]
+    fn routes_paths_by_their_segments(prefix in prefix(), rest in segments()) {
+        let config = config();
+        let path = format!("{}/{}", prefix, rest.join("/"));
+        let route = route_of(&config, &path);
+
+        // Empty and dot segments don't change the route
+        let padded = format!("/{}//./{}", prefix, rest.join("//"));
+        prop_assert_eq!(&route_of(&config, &padded), &route);
+
+        // Nor do segments which are removed again
+        let detour = format!("/detour/..{}/x/../{}", prefix, rest.join("/"));
+        prop_assert_eq!(&route_of(&config, &detour), &route);
+
+        // Nor does percent-encoding unreserved characters
+        let encoded: String = path
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { format!("%{:02x}", c as u8) } else { c.to_string() })
+            .collect();
+        prop_assert_eq!(&route_of(&config, &encoded), &route);
+    }
+
+    #[test]
+    fn only_matches_whole_prefix_segments(rest in segment()) {
+        let config = config();
+        let route = route_of(&config, &format!("/auth{}", rest));
+        prop_assert_eq!(route, None);
+        let route = route_of(&config, &format!("/auth/{}", rest));
+        prop_assert_eq!(route, Some(format!("http://auth.internal/{}", rest)));
+    }
+
+    #[test]
+    fn rejects_climbing_above_the_root(rest in segments(), extra in 1usize..4) {
+        let climb = "/..".repeat(rest.len() + extra);
+        let path = format!("/{}{}", rest.join("/"), climb);
+        prop_assert!(normalize_path(&path).is_err());
+    }
+}
+
+#[test]
+fn blames_the_client_for_invalid_uris() {
+    let err = "/a b".parse::<hyper::Uri>().unwrap_err();
+    let err = client_error(err);
+    assert!(
+        matches!(err, ReverseProxyError::InvalidRequest(_)),
+        "{:?}",
+        err
+    );
+    assert_eq!(err.status_code(), 400);
+
+    // Built from the configuration or the upstream's response
+    let err = ReverseProxyError::from("/a b".parse::<hyper::Uri>().unwrap_err());
+    assert_eq!(err.status_code(), 502);
+}
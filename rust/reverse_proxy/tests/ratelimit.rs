@@ -0,0 +1,132 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Request, Response};
+use reverse_proxy::*;
+use shared_models::*;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+fn proxy_state(upstream: SocketAddr) -> ProxyState {
+    state_of(&format!(
+        r#"
+        trusted_proxies = ["10.0.0.0/8"]
+
+        [[routes]]
+        prefix = "/auth"
+        upstream = "http://{}"
+        options = {{ rate_limit = {{ requests = 2, per_secs = 60 }} }}
+        "#,
+        upstream
+    ))
+}
+
+fn client_at(addr: &str) -> ClientConnection {
+    ClientConnection::new(addr.parse().unwrap())
+}
+
+fn login(forwarded_for: Option<&str>) -> Request<Body> {
+    let mut builder = Request::post("http://localhost:8080/auth/login");
+    if let Some(forwarded_for) = forwarded_for {
+        builder = builder.header("x-forwarded-for", forwarded_for);
+    }
+    builder.body(Body::empty()).unwrap()
+}
+
+async fn upstream() -> SocketAddr {
+    start_upstream(|_| async { Response::new(Body::from("upstream")) })
+}
+
+#[test]
+fn refills_buckets_over_time() {
+    let store = MemoryRateLimitStore::new();
+    let limit = RateLimitOptions {
+        requests: 3,
+        per_secs: 3,
+        key: RateLimitKey::Ip,
+    };
+    let start = Instant::now();
+
+    for remaining in [2, 1, 0] {
+        let decision = store.acquire_at("client", &limit, start);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, remaining);
+    }
+    let decision = store.acquire_at("client", &limit, start);
+    assert!(!decision.allowed);
+    assert_eq!(decision.retry_after_secs, 1);
+    assert_eq!(decision.reset_secs, 3);
+
+    // Other keys have their own bucket
+    assert!(store.acquire_at("other", &limit, start).allowed);
+
+    let later = start + Duration::from_secs(1);
+    assert!(store.acquire_at("client", &limit, later).allowed);
+    assert!(!store.acquire_at("client", &limit, later).allowed);
+}
+
+#[tokio::test]
+async fn rejects_requests_over_the_limit() {
+    let state = proxy_state(upstream().await);
+
+    for remaining in ["1", "0"] {
+        let response = reverse_proxy(state.clone(), client_at("203.0.113.7:5000"), login(None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["ratelimit-limit"], "2");
+        assert_eq!(response.headers()["ratelimit-remaining"], remaining);
+        assert_eq!(response.headers()["ratelimit-policy"], "2;w=60");
+    }
+
+    let response = reverse_proxy(state.clone(), client_at("203.0.113.7:5000"), login(None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()["retry-after"], "30");
+    assert_eq!(response.headers()["ratelimit-remaining"], "0");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: HttpErrResponseBody = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body.error_type, "RateLimited");
+
+    let response = reverse_proxy(state, client_at("198.51.100.9:5000"), login(None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn limits_clients_behind_trusted_proxies_separately() {
+    let state = proxy_state(upstream().await);
+
+    for _ in 0..2 {
+        let response = reverse_proxy(
+            state.clone(),
+            client_at("10.0.0.2:5000"),
+            login(Some("203.0.113.7")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    let limited = reverse_proxy(
+        state.clone(),
+        client_at("10.0.0.2:5000"),
+        login(Some("203.0.113.7")),
+    )
+    .await
+    .unwrap();
+    assert_eq!(limited.status(), 429);
+
+    let other = reverse_proxy(
+        state,
+        client_at("10.0.0.2:5000"),
+        login(Some("198.51.100.9")),
+    )
+    .await
+    .unwrap();
+    assert_eq!(other.status(), 200);
+}
@@ -0,0 +1,182 @@
+mod common;
+
+use common::*;
+use reverse_proxy::*;
+
+fn parse_rules(rules: &str, upstream: &str) -> Result<ReverseProxyConfig, ReverseProxyError> {
+    parse_toml(&format!(
+        r#"
+        [[routes]]
+        prefix = "/blog"
+        upstream = "{upstream}"
+
+        {rules}
+        "#,
+    ))
+}
+
+fn proxy_state(rules: &str, upstream: &str) -> ProxyState {
+    let config = parse_rules(rules, upstream).unwrap();
+    ProxyState::new(SharedConfig::new(config, None))
+}
+
+/// The status and location of the response to a request
+async fn redirect_of(state: &ProxyState, path: &str) -> (u16, Option<String>) {
+    let response = send(state, get(path)).await;
+    let location = response
+        .headers()
+        .get("location")
+        .map(|location| location.to_str().unwrap().to_string());
+    (response.status().as_u16(), location)
+}
+
+#[tokio::test]
+async fn redirects_by_prefix() {
+    let state = proxy_state(
+        r#"
+        [[redirects]]
+        prefix = "/old-blog"
+        to = "/blog/"
+
+        [[redirects]]
+        prefix = "/docs"
+        to = "https://docs.example.com/v2"
+        status = 308
+        preserve_query = false
+        "#,
+        "http://blog.internal",
+    );
+
+    let cases = [
+        (
+            "/old-blog",
+            301,
+            Some("http://localhost:8080/blog/".to_string()),
+        ),
+        (
+            "/old-blog/posts/1?page=2",
+            301,
+            Some("http://localhost:8080/blog/posts/1?page=2".to_string()),
+        ),
+        (
+            "/docs/setup?x=1",
+            308,
+            Some("https://docs.example.com/v2/setup".to_string()),
+        ),
+        ("/old-blogs", 404, None),
+    ];
+    for (path, status, location) in cases {
+        assert_eq!(
+            redirect_of(&state, path).await,
+            (status, location),
+            "{}",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn redirects_by_regex_with_captures() {
+    let state = proxy_state(
+        r#"
+        [[redirects]]
+        regex = '^/posts/(?P<year>\d{4})/([a-z0-9-]+)$'
+        to = "/blog/${year}/$2?from=posts"
+        status = 302
+
+        [[redirects]]
+        regex = '^/$'
+        to = "/blog/latest"
+        status = 307
+        "#,
+        "http://blog.internal",
+    );
+
+    assert_eq!(
+        redirect_of(&state, "/posts/2023/hello-world?utm=feed").await,
+        (
+            302,
+            Some("http://localhost:8080/blog/2023/hello-world?from=posts&utm=feed".to_string())
+        )
+    );
+    assert_eq!(redirect_of(&state, "/posts/23/hello").await.0, 404);
+    // Overrides the default redirect of the root
+    assert_eq!(
+        redirect_of(&state, "/").await,
+        (307, Some("http://localhost:8080/blog/latest".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn leaves_builtin_routes_alone() {
+    let state = proxy_state(
+        r#"
+        [[redirects]]
+        regex = ".*"
+        to = "/blog"
+
+        [[rewrites]]
+        regex = ".*"
+        to = "/blog"
+        "#,
+        "http://blog.internal",
+    );
+
+    for path in ["/livez", "/healthcheck"] {
+        assert_eq!(redirect_of(&state, path).await, (200, None), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn rewrites_the_upstream_path() {
+    let upstream = format!("http://{}", echo_upstream().await);
+    let state = proxy_state(
+        r#"
+        [[rewrites]]
+        prefix = "/blog/posts/old-slug"
+        to = "/blog/posts/new-slug"
+
+        [[rewrites]]
+        regex = '^/p/(\d+)$'
+        to = "/blog/posts/by-id/$1"
+        "#,
+        &upstream,
+    );
+
+    let cases = [
+        ("/blog/posts/old-slug?ref=rss", "/posts/new-slug?ref=rss"),
+        ("/blog/posts/old-slug/comments", "/posts/new-slug/comments"),
+        ("/blog/posts/old-slugs", "/posts/old-slugs"),
+        ("/p/42", "/posts/by-id/42"),
+    ];
+    for (path, expected) in cases {
+        let response = reverse_proxy(state.clone(), client(), get(path))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200, "{}", path);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, expected, "{}", path);
+    }
+}
+
+#[test]
+fn rejects_invalid_rules() {
+    let invalid = [
+        "[[redirects]]\nregex = \"(\"\nto = \"/blog\"",
+        "[[redirects]]\nprefix = \"/old\"\nto = \"/blog\"\nstatus = 200",
+        "[[redirects]]\nprefix = \"/old\"\nregex = \"^/old\"\nto = \"/blog\"",
+        "[[redirects]]\nto = \"/blog\"",
+        "[[redirects]]\nprefix = \"/old//posts\"\nto = \"/blog\"",
+        "[[rewrites]]\nprefix = \"/old\"\nto = \"http://elsewhere.internal/\"",
+    ];
+    for rules in invalid {
+        assert!(
+            matches!(
+                parse_rules(rules, "http://blog.internal"),
+                Err(ReverseProxyError::InvalidConfiguration(_))
+            ),
+            "Expected rules to be rejected: {}",
+            rules
+        );
+    }
+}
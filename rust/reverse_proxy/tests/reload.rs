@@ -0,0 +1,67 @@
+use reverse_proxy::*;
+use std::path::{Path, PathBuf};
+
+fn routes_file(name: &str, upstream: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "reverse_proxy_{}_{}.toml",
+        name,
+        std::process::id()
+    ));
+    write_routes(&path, upstream);
+    path
+}
+
+fn write_routes(path: &Path, upstream: &str) {
+    let contents = format!(
+        r#"
+        listen_addr = "127.0.0.1:8080"
+        base_url = "http://localhost:8080"
+
+        [[routes]]
+        prefix = "/blog"
+        upstream = "{}"
+        "#,
+        upstream
+    );
+    std::fs::write(path, contents).unwrap();
+}
+
+fn shared_config(path: &Path) -> SharedConfig {
+    let config = ReverseProxyConfig::from_file(path).unwrap();
+    SharedConfig::new(config, Some(path.to_path_buf()))
+}
+
+fn blog_upstream(config: &SharedConfig) -> String {
+    config.get().routes.routes()[0].upstream.clone()
+}
+
+#[test]
+fn reload_swaps_in_new_config() {
+    let path = routes_file("swap", "http://old.internal");
+    let config = shared_config(&path);
+    let in_flight = config.get();
+
+    write_routes(&path, "http://new.internal");
+    config.reload().unwrap();
+
+    assert_eq!(blog_upstream(&config), "http://new.internal");
+    assert_eq!(in_flight.routes.routes()[0].upstream, "http://old.internal");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn reload_keeps_config_when_invalid() {
+    let path = routes_file("invalid", "http://old.internal");
+    let config = shared_config(&path);
+
+    write_routes(&path, "not a url");
+    assert!(matches!(
+        config.reload(),
+        Err(ReverseProxyError::InvalidConfiguration(_))
+    ));
+    assert_eq!(blog_upstream(&config), "http://old.internal");
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(config.reload().is_err());
+    assert_eq!(blog_upstream(&config), "http://old.internal");
+}
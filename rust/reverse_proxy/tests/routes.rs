@@ -0,0 +1,175 @@
+mod common;
+
+use common::*;
+use reverse_proxy::*;
+
+fn proxied_uri(config: &ReverseProxyConfig, path: &str) -> Option<String> {
+    match config.routes.classify(path) {
+        ClassifiedRoute::Proxy(route) => Some(route.proxied_uri()),
+        ClassifiedRoute::NonProxy(_) => None,
+    }
+}
+
+#[test]
+fn matches_longest_prefix() {
+    let config = parse_toml(
+        r#"
+        [[routes]]
+        prefix = "/blog"
+        upstream = "http://blog.internal"
+
+        [[routes]]
+        prefix = "/blog/admin"
+        upstream = "http://admin.internal/"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        proxied_uri(&config, "/blog/admin/posts"),
+        Some("http://admin.internal/posts".to_string())
+    );
+    assert_eq!(
+        proxied_uri(&config, "/blog/administrator"),
+        Some("http://blog.internal/administrator".to_string())
+    );
+    assert_eq!(proxied_uri(&config, "/blogs"), None);
+}
+
+#[test]
+fn keeps_prefix_when_strip_prefix_is_false() {
+    let config = parse_toml(
+        r#"
+        [[routes]]
+        name = "static_assets"
+        prefix = "/static"
+        upstream = "http://assets.internal"
+        strip_prefix = false
+        "#,
+    )
+    .unwrap();
+
+    let route = match config.routes.classify("/static/styles.css") {
+        ClassifiedRoute::Proxy(route) => route,
+        ClassifiedRoute::NonProxy(route) => panic!("Expected a proxy route, got {:?}", route),
+    };
+    assert_eq!(route.name(), "static_assets");
+    assert_eq!(
+        route.proxied_uri(),
+        "http://assets.internal/static/styles.css"
+    );
+}
+
+#[test]
+fn builtin_routes_take_precedence() {
+    let config = parse_toml(
+        r#"
+        [[routes]]
+        prefix = "/"
+        upstream = "http://catch-all.internal"
+        "#,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        config.routes.classify("/livez"),
+        ClassifiedRoute::NonProxy(NonProxyRoute::Livez)
+    ));
+    assert!(matches!(
+        config.routes.classify("/"),
+        ClassifiedRoute::NonProxy(NonProxyRoute::Root)
+    ));
+    assert_eq!(
+        proxied_uri(&config, "/anything/else"),
+        Some("http://catch-all.internal/anything/else".to_string())
+    );
+    // The admin endpoints are only served by the admin listener
+    assert_eq!(
+        proxied_uri(&config, "/metrics"),
+        Some("http://catch-all.internal/metrics".to_string())
+    );
+}
+
+#[test]
+fn parses_yaml() {
+    let config = ReverseProxyConfig::parse(
+        r#"
+listen_addr: 127.0.0.1:8080
+base_url: http://localhost:8080
+routes:
+  - prefix: /auth
+    upstream: https://auth.internal
+    options:
+      preserve_host: true
+"#,
+        ConfigFormat::Yaml,
+    )
+    .unwrap();
+
+    let route = &config.routes.routes()[0];
+    assert_eq!(route.name(), "/auth");
+    assert!(route.strip_prefix);
+    assert!(route.options.preserve_host);
+}
+
+#[test]
+fn rejects_invalid_routes() {
+    let invalid = [
+        "[[routes]]\nprefix = \"auth\"\nupstream = \"http://auth.internal\"",
+        "[[routes]]\nprefix = \"/auth/\"\nupstream = \"http://auth.internal\"",
+        "[[routes]]\nprefix = \"/blog//admin\"\nupstream = \"http://auth.internal\"",
+        "[[routes]]\nprefix = \"/blog/../auth\"\nupstream = \"http://auth.internal\"",
+        "[[routes]]\nprefix = \"/%61uth\"\nupstream = \"http://auth.internal\"",
+        "[[routes]]\nprefix = \"/auth\"\nupstream = \"ftp://auth.internal\"",
+        "[[routes]]\nprefix = \"/auth\"\nupstream = \"/auth\"",
+        "[[routes]]\nprefix = \"/livez\"\nupstream = \"http://auth.internal\"",
+        "[[routes]]\nprefix = \"/healthcheck/deep\"\nupstream = \"http://auth.internal\"",
+        "[[routes]]\nprefix = \"/auth\"\nupstream = \"http://auth.internal\"\n\
+         [[routes]]\nprefix = \"/auth\"\nupstream = \"http://other.internal\"",
+    ];
+
+    for routes in invalid {
+        assert!(
+            matches!(
+                parse_toml(routes),
+                Err(ReverseProxyError::InvalidConfiguration(_))
+            ),
+            "Expected routes to be rejected: {}",
+            routes
+        );
+    }
+}
+
+#[test]
+fn detects_format_from_extension() {
+    use std::path::Path;
+
+    assert_eq!(
+        ConfigFormat::from_path(Path::new("routes.toml")).unwrap(),
+        ConfigFormat::Toml
+    );
+    assert_eq!(
+        ConfigFormat::from_path(Path::new("routes.yml")).unwrap(),
+        ConfigFormat::Yaml
+    );
+    assert!(ConfigFormat::from_path(Path::new("routes.json")).is_err());
+}
+
+#[test]
+fn parses_example_config() {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("routes.example.toml");
+    let config = ReverseProxyConfig::from_file(&path).unwrap();
+
+    assert_eq!(config.routes.routes().len(), 6);
+}
+
+#[test]
+fn parses_the_admin_listen_addr() {
+    let config = parse_toml("admin_listen_addr = \"127.0.0.1:9090\"").unwrap();
+    assert_eq!(config.admin_listen_addr.as_deref(), Some("127.0.0.1:9090"));
+
+    assert!(matches!(
+        parse_toml("admin_listen_addr = \"localhost\""),
+        Err(ReverseProxyError::InvalidConfiguration(_))
+    ));
+}
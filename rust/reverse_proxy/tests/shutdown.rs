@@ -0,0 +1,109 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Response};
+use reverse_proxy::*;
+use shared_models::{HealthStatus, ReadinessResponse};
+use shutdown_utils::Shutdown;
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::Duration,
+};
+
+/// Starts an upstream answering after a delay
+async fn slow_upstream(delay: Duration) -> SocketAddr {
+    start_upstream(move |_| async move {
+        tokio::time::sleep(delay).await;
+        Response::new(Body::from("slow"))
+    })
+}
+
+fn proxy_state(upstream: SocketAddr) -> ProxyState {
+    state_of(&format!(
+        r#"
+        [[routes]]
+        name = "slow"
+        prefix = "/slow"
+        upstream = "http://{upstream}"
+        "#,
+    ))
+    .with_shutdown(Shutdown::new(Duration::from_secs(5)))
+}
+
+#[tokio::test]
+async fn readiness_fails_while_draining() {
+    let state = proxy_state(slow_upstream(Duration::ZERO).await);
+    let readyz = || async {
+        let response = send(&state, get("/readyz")).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (
+            status,
+            serde_json::from_slice::<ReadinessResponse>(&body).unwrap(),
+        )
+    };
+
+    let (status, _) = readyz().await;
+    assert_eq!(status, 200);
+
+    state.shutdown.start();
+    let (status, body) = readyz().await;
+    assert_eq!(status, 503);
+    assert_eq!(body.status, HealthStatus::Down);
+    assert!(
+        body.dependencies
+            .iter()
+            .any(|check| check.name == "shutdown" && check.status == HealthStatus::Down),
+        "{:?}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn finishes_requests_in_flight() {
+    let state = proxy_state(slow_upstream(Duration::from_millis(300)).await);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve(listener, state.clone()).unwrap());
+
+    let in_flight = tokio::spawn(reqwest::get(format!("http://{}/slow/a", addr)));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    state.shutdown.start();
+
+    let response = in_flight.await.unwrap().unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "slow");
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .expect("Server should stop once drained")
+        .unwrap()
+        .unwrap();
+
+    // New connections are no longer accepted
+    assert!(reqwest::get(format!("http://{}/slow/b", addr))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn drain_gives_up_after_the_timeout() {
+    let shutdown = Shutdown::new(Duration::from_millis(50));
+    assert!(!shutdown.is_draining());
+
+    // Nothing is cut short before shutdown starts
+    let finished = shutdown.drain(async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        "done"
+    });
+    assert_eq!(finished.await, Some("done"));
+
+    shutdown.start();
+    assert!(shutdown.is_draining());
+    let stuck = shutdown.drain(std::future::pending::<()>());
+    assert_eq!(
+        tokio::time::timeout(Duration::from_secs(2), stuck)
+            .await
+            .unwrap(),
+        None
+    );
+}
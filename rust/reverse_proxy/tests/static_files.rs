@@ -0,0 +1,334 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Method, Request, Response};
+use reverse_proxy::*;
+use std::path::Path;
+
+fn proxy_state(root: &Path, options: &str) -> ProxyState {
+    state_of(&format!(
+        r#"
+        [[routes]]
+        prefix = "/static"
+
+        [routes.static_files]
+        root = "{}"
+        {}
+        "#,
+        root.display(),
+        options
+    ))
+}
+
+async fn fetch(
+    state: &ProxyState,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Response<Body> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("http://localhost:8080{}", path));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    send(state, request.body(Body::empty()).unwrap()).await
+}
+
+#[tokio::test]
+async fn serves_files_with_validators() {
+    let root = temp_dir("static_validators");
+    std::fs::write(root.join("style.css"), "body {}").unwrap();
+    let state = proxy_state(&root, "cache_control = \"public, max-age=60\"");
+
+    let response = fetch(&state, Method::GET, "/static/style.css", &[]).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/css; charset=utf-8"
+    );
+    assert_eq!(response.headers()["content-length"], "7");
+    assert_eq!(response.headers()["cache-control"], "public, max-age=60");
+    assert_eq!(response.headers()["accept-ranges"], "bytes");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let last_modified = response.headers()["last-modified"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(body_of(response).await, "body {}");
+
+    let response = fetch(
+        &state,
+        Method::GET,
+        "/static/style.css",
+        &[("if-none-match", &etag)],
+    )
+    .await;
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers()["etag"], etag.as_str());
+    let response = fetch(
+        &state,
+        Method::GET,
+        "/static/style.css",
+        &[("if-modified-since", &last_modified)],
+    )
+    .await;
+    assert_eq!(response.status(), 304);
+    let response = fetch(
+        &state,
+        Method::GET,
+        "/static/style.css",
+        &[("if-none-match", "\"other\"")],
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+
+    let response = fetch(&state, Method::HEAD, "/static/style.css", &[]).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-length"], "7");
+    assert_eq!(body_of(response).await, "");
+
+    let response = fetch(&state, Method::POST, "/static/style.css", &[]).await;
+    assert_eq!(response.status(), 405);
+    let response = fetch(&state, Method::GET, "/static/missing.css", &[]).await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn serves_ranges() {
+    let root = temp_dir("static_ranges");
+    std::fs::write(root.join("video.mp4"), "0123456789").unwrap();
+    let state = proxy_state(&root, "");
+
+    let cases = [
+        ("bytes=2-5", 206, Some("bytes 2-5/10"), "2345"),
+        ("bytes=7-", 206, Some("bytes 7-9/10"), "789"),
+        ("bytes=-3", 206, Some("bytes 7-9/10"), "789"),
+        ("bytes=5-100", 206, Some("bytes 5-9/10"), "56789"),
+        ("bytes=0-1,4-5", 200, None, "0123456789"),
+        ("lines=1-2", 200, None, "0123456789"),
+    ];
+    for (range, status, content_range, body) in cases {
+        let response = fetch(
+            &state,
+            Method::GET,
+            "/static/video.mp4",
+            &[("range", range)],
+        )
+        .await;
+        assert_eq!(response.status(), status, "{}", range);
+        assert_eq!(
+            response
+                .headers()
+                .get("content-range")
+                .map(|value| value.to_str().unwrap()),
+            content_range,
+            "{}",
+            range
+        );
+        assert_eq!(body_of(response).await, body, "{}", range);
+    }
+
+    let response = fetch(
+        &state,
+        Method::GET,
+        "/static/video.mp4",
+        &[("range", "bytes=10-")],
+    )
+    .await;
+    assert_eq!(response.status(), 416);
+    assert_eq!(response.headers()["content-range"], "bytes */10");
+
+    // A range of another version of the file is ignored
+    let response = fetch(
+        &state,
+        Method::GET,
+        "/static/video.mp4",
+        &[("range", "bytes=2-5"), ("if-range", "\"old\"")],
+    )
+    .await;
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn serves_precompressed_siblings() {
+    let root = temp_dir("static_precompressed");
+    std::fs::write(root.join("app.js"), "plain").unwrap();
+    std::fs::write(root.join("app.js.br"), "brotli").unwrap();
+    std::fs::write(root.join("app.js.gz"), "gzip").unwrap();
+    std::fs::write(root.join("other.js"), "other").unwrap();
+    let state = proxy_state(&root, "");
+
+    let cases = [
+        ("gzip, br", Some("br"), "brotli"),
+        ("gzip", Some("gzip"), "gzip"),
+        ("br;q=0.5, gzip", Some("gzip"), "gzip"),
+        ("identity", None, "plain"),
+    ];
+    let mut etags = Vec::new();
+    for (accept_encoding, content_encoding, body) in cases {
+        let response = fetch(
+            &state,
+            Method::GET,
+            "/static/app.js",
+            &[("accept-encoding", accept_encoding)],
+        )
+        .await;
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/javascript; charset=utf-8"
+        );
+        assert_eq!(response.headers()["vary"], "Accept-Encoding");
+        assert_eq!(
+            response
+                .headers()
+                .get("content-encoding")
+                .map(|value| value.to_str().unwrap()),
+            content_encoding,
+            "{}",
+            accept_encoding
+        );
+        etags.push(response.headers()["etag"].clone());
+        assert_eq!(body_of(response).await, body, "{}", accept_encoding);
+    }
+    // Each encoding has its own validator
+    assert_ne!(etags[0], etags[1]);
+    assert_ne!(etags[1], etags[3]);
+
+    let response = fetch(
+        &state,
+        Method::GET,
+        "/static/other.js",
+        &[("accept-encoding", "br")],
+    )
+    .await;
+    assert!(response.headers().get("vary").is_none());
+    assert!(response.headers().get("content-encoding").is_none());
+}
+
+#[tokio::test]
+async fn serves_directories() {
+    let root = temp_dir("static_directories");
+    std::fs::create_dir_all(root.join("docs")).unwrap();
+    std::fs::create_dir_all(root.join("images")).unwrap();
+    std::fs::write(root.join("docs/index.html"), "docs").unwrap();
+    std::fs::write(root.join("images/a <b>.png"), "png").unwrap();
+
+    let state = proxy_state(&root, "");
+    let response = fetch(&state, Method::GET, "/static/docs?x=1", &[]).await;
+    assert_eq!(response.status(), 301);
+    assert_eq!(response.headers()["location"], "/static/docs/?x=1");
+    let response = fetch(&state, Method::GET, "/static/docs/", &[]).await;
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/html; charset=utf-8"
+    );
+    assert_eq!(body_of(response).await, "docs");
+    let response = fetch(&state, Method::GET, "/static/images/", &[]).await;
+    assert_eq!(response.status(), 404);
+
+    let state = proxy_state(&root, "directory_index = true");
+    let response = fetch(&state, Method::GET, "/static/images/", &[]).await;
+    assert_eq!(response.status(), 200);
+    let listing = body_of(response).await;
+    assert!(
+        listing.contains("<a href=\"a%20%3Cb%3E.png\">a &lt;b&gt;.png</a>"),
+        "{}",
+        listing
+    );
+    assert!(listing.contains("<a href=\"../\">../</a>"), "{}", listing);
+    let response = fetch(&state, Method::GET, "/static/", &[]).await;
+    let listing = body_of(response).await;
+    assert!(
+        listing.contains("<a href=\"docs/\">docs/</a>"),
+        "{}",
+        listing
+    );
+}
+
+#[tokio::test]
+async fn rejects_traversal() {
+    let parent = temp_dir("static_traversal");
+    let root = parent.join("public");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(parent.join("secret.txt"), "secret").unwrap();
+    std::fs::write(root.join("public.txt"), "public").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(parent.join("secret.txt"), root.join("link.txt")).unwrap();
+    let state = proxy_state(&root, "directory_index = true");
+
+    let response = fetch(&state, Method::GET, "/static/public.txt", &[]).await;
+    assert_eq!(body_of(response).await, "public");
+
+    let cases = [
+        ("/static/../secret.txt", 404),
+        ("/static/%2e%2e/secret.txt", 404),
+        ("/static/..%2fsecret.txt", 400),
+        ("/static/..%5csecret.txt", 400),
+        ("/static/public.txt%00", 400),
+        #[cfg(unix)]
+        ("/static/link.txt", 404),
+    ];
+    for (path, status) in cases {
+        let response = fetch(&state, Method::GET, path, &[]).await;
+        assert_eq!(response.status(), status, "{}", path);
+        assert!(!body_of(response).await.contains("secret"), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn hides_dotfiles() {
+    let root = temp_dir("static_dotfiles");
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    std::fs::write(root.join(".git/config"), "secret").unwrap();
+    std::fs::write(root.join(".env"), "secret").unwrap();
+    std::fs::write(root.join("app.js"), "app").unwrap();
+    let state = proxy_state(&root, "directory_index = true");
+
+    for path in [
+        "/static/.env",
+        "/static/.git/config",
+        "/static/%2egit/",
+        "/static/.missing",
+    ] {
+        let response = fetch(&state, Method::GET, path, &[]).await;
+        assert_eq!(response.status(), 404, "{}", path);
+        assert!(!body_of(response).await.contains("secret"), "{}", path);
+    }
+
+    let response = fetch(&state, Method::GET, "/static/", &[]).await;
+    let listing = body_of(response).await;
+    assert!(listing.contains("app.js"), "{}", listing);
+    assert!(!listing.contains(".env"), "{}", listing);
+    assert!(!listing.contains(".git"), "{}", listing);
+}
+
+#[test]
+fn validates_static_routes() {
+    let root = temp_dir("static_validation");
+    let invalid = [
+        format!(
+            "upstream = \"http://static.internal\"\n[routes.static_files]\nroot = \"{}\"",
+            root.display()
+        ),
+        "[routes.static_files]\nroot = \"/does/not/exist\"".to_string(),
+        format!(
+            "[routes.static_files]\nroot = \"{}\"\nindex_file = \"a/index.html\"",
+            root.display()
+        ),
+    ];
+    for route in invalid {
+        let contents = format!(
+            "listen_addr = \"127.0.0.1:8080\"\nbase_url = \"http://localhost:8080\"\n[[routes]]\nprefix = \"/static\"\n{}",
+            route
+        );
+        assert!(
+            matches!(
+                ReverseProxyConfig::parse(&contents, ConfigFormat::Toml),
+                Err(ReverseProxyError::InvalidConfiguration(_))
+            ),
+            "Expected route to be rejected: {}",
+            route
+        );
+    }
+}
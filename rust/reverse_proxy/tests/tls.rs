@@ -0,0 +1,222 @@
+use hyper::{Body, Response, Server};
+use reverse_proxy::*;
+use std::{
+    convert::Infallible,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+struct Certificate {
+    pem: String,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+/// Writes a new self-signed certificate for `localhost`
+fn write_certificate(name: &str) -> Certificate {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = std::env::temp_dir();
+    let cert_path = dir.join(format!("reverse_proxy_{}_{}.crt", name, std::process::id()));
+    let key_path = dir.join(format!("reverse_proxy_{}_{}.key", name, std::process::id()));
+    let pem = cert.serialize_pem().unwrap();
+    std::fs::write(&cert_path, &pem).unwrap();
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+    Certificate {
+        pem,
+        cert_path,
+        key_path,
+    }
+}
+
+/// Starts an upstream answering with the `X-Forwarded-Proto` it received
+async fn upstream() -> SocketAddr {
+    let make_service = hyper::service::make_service_fn(|_| async {
+        Ok::<_, Infallible>(hyper::service::service_fn(
+            |req: hyper::Request<Body>| async move {
+                let proto = req
+                    .headers()
+                    .get("x-forwarded-proto")
+                    .map(|value| value.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                Ok::<_, Infallible>(Response::new(Body::from(proto)))
+            },
+        ))
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+struct Proxy {
+    http_addr: SocketAddr,
+    https_port: u16,
+    resolver: Arc<ReloadingCertResolver>,
+}
+
+async fn start_proxy(cert: &Certificate, redirect_http: bool) -> Proxy {
+    let http = TcpListener::bind("127.0.0.1:0").unwrap();
+    let https = TcpListener::bind("127.0.0.1:0").unwrap();
+    let http_addr = http.local_addr().unwrap();
+    let https_addr = https.local_addr().unwrap();
+
+    let contents = format!(
+        r#"
+        listen_addr = "{}"
+        base_url = "http://localhost"
+
+        [tls]
+        listen_addr = "{}"
+        cert_path = "{}"
+        key_path = "{}"
+        redirect_http = {}
+
+        [[routes]]
+        prefix = "/upstream"
+        upstream = "http://{}"
+        "#,
+        http_addr,
+        https_addr,
+        cert.cert_path.display(),
+        cert.key_path.display(),
+        redirect_http,
+        upstream().await
+    );
+    let config = ReverseProxyConfig::parse(&contents, ConfigFormat::Toml).unwrap();
+    let resolver = ReloadingCertResolver::new(config.tls.as_ref().unwrap()).unwrap();
+    let state = ProxyState::new(SharedConfig::new(config, None));
+
+    tokio::spawn(serve(http, state.clone()).unwrap());
+    tokio::spawn(serve_tls(https, resolver.acceptor(), state).unwrap());
+
+    Proxy {
+        http_addr,
+        https_port: https_addr.port(),
+        resolver,
+    }
+}
+
+fn client_trusting(pem: &str) -> reqwest::Client {
+    reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(pem.as_bytes()).unwrap())
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+async fn get_https(client: &reqwest::Client, port: u16) -> reqwest::Result<String> {
+    client
+        .get(format!("https://localhost:{}/upstream/post", port))
+        .send()
+        .await?
+        .text()
+        .await
+}
+
+fn remove(cert: &Certificate) {
+    for path in [&cert.cert_path, &cert.key_path] {
+        std::fs::remove_file(Path::new(path)).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn terminates_tls() {
+    let cert = write_certificate("terminate");
+    let proxy = start_proxy(&cert, false).await;
+
+    let proto = get_https(&client_trusting(&cert.pem), proxy.https_port)
+        .await
+        .unwrap();
+    assert_eq!(proto, "https");
+
+    let proto = reqwest::get(format!("http://{}/upstream/post", proxy.http_addr))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(proto, "http");
+    remove(&cert);
+}
+
+#[tokio::test]
+async fn redirects_http_except_probes() {
+    let cert = write_certificate("redirect");
+    let proxy = start_proxy(&cert, true).await;
+    let client = client_trusting(&cert.pem);
+
+    let response = client
+        .get(format!(
+            "http://localhost:{}/upstream/post?a=b",
+            proxy.http_addr.port()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 308);
+    assert_eq!(
+        response.headers()["location"],
+        format!("https://localhost:{}/upstream/post?a=b", proxy.https_port).as_str()
+    );
+
+    let response = client
+        .get(format!("http://{}/livez", proxy.http_addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    remove(&cert);
+}
+
+#[tokio::test]
+async fn reloads_certificates() {
+    let old = write_certificate("reload");
+    let proxy = start_proxy(&old, false).await;
+
+    let new = write_certificate("reload");
+    proxy.resolver.reload().unwrap();
+
+    assert!(get_https(&client_trusting(&old.pem), proxy.https_port)
+        .await
+        .is_err());
+    get_https(&client_trusting(&new.pem), proxy.https_port)
+        .await
+        .unwrap();
+
+    // An invalid certificate is never swapped in
+    std::fs::write(&new.cert_path, "not a certificate").unwrap();
+    assert!(matches!(
+        proxy.resolver.reload(),
+        Err(ReverseProxyError::TlsError(_))
+    ));
+    get_https(&client_trusting(&new.pem), proxy.https_port)
+        .await
+        .unwrap();
+    remove(&new);
+}
+
+#[tokio::test]
+async fn keeps_the_certificate_when_the_key_does_not_match() {
+    let current = write_certificate("mismatch");
+    let proxy = start_proxy(&current, false).await;
+
+    // The certificate is replaced before its key, or with the key of another certificate
+    let other = write_certificate("mismatch_other");
+    std::fs::copy(&other.cert_path, &current.cert_path).unwrap();
+    let err = proxy.resolver.reload().unwrap_err();
+    assert!(
+        err.to_string().contains("does not match the certificate"),
+        "{}",
+        err
+    );
+
+    get_https(&client_trusting(&current.pem), proxy.https_port)
+        .await
+        .unwrap();
+    assert!(get_https(&client_trusting(&other.pem), proxy.https_port)
+        .await
+        .is_err());
+    remove(&current);
+    remove(&other);
+}
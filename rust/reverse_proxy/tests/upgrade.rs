@@ -0,0 +1,84 @@
+use futures_util::{SinkExt, StreamExt};
+use reverse_proxy::*;
+use std::net::{SocketAddr, TcpListener};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Starts a WebSocket upstream echoing every message back
+async fn echo_upstream() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+                    return;
+                };
+                while let Some(Ok(message)) = socket.next().await {
+                    if message.is_close() {
+                        break;
+                    }
+                    socket.send(message).await.unwrap();
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// Starts the proxy with a single `/ws` route to the upstream
+fn start_proxy(upstream: SocketAddr) -> SocketAddr {
+    let contents = format!(
+        r#"
+        listen_addr = "127.0.0.1:0"
+        base_url = "http://localhost"
+
+        [[routes]]
+        prefix = "/ws"
+        upstream = "http://{}"
+        "#,
+        upstream
+    );
+    let config = ReverseProxyConfig::parse(&contents, ConfigFormat::Toml).unwrap();
+    let state = ProxyState::new(SharedConfig::new(config, None));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, state).unwrap());
+    addr
+}
+
+#[tokio::test]
+async fn proxies_websockets() {
+    let proxy = start_proxy(echo_upstream().await);
+
+    let (mut socket, response) =
+        tokio_tungstenite::connect_async(format!("ws://{}/ws/echo", proxy))
+            .await
+            .unwrap();
+    assert_eq!(response.status(), 101);
+
+    for text in ["hello", "world"] {
+        socket.send(Message::Text(text.to_string())).await.unwrap();
+        let echoed = socket.next().await.unwrap().unwrap();
+        assert_eq!(echoed, Message::Text(text.to_string()));
+    }
+
+    socket.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+    let echoed = socket.next().await.unwrap().unwrap();
+    assert_eq!(echoed, Message::Binary(vec![1, 2, 3]));
+
+    socket.close(None).await.unwrap();
+}
+
+#[tokio::test]
+async fn plain_requests_to_websocket_upstream_are_not_upgraded() {
+    let proxy = start_proxy(echo_upstream().await);
+
+    let client = hyper::Client::new();
+    let response = client
+        .get(format!("http://{}/ws/echo", proxy).parse().unwrap())
+        .await
+        .unwrap();
+
+    assert_ne!(response.status(), 101);
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::*;
+use hyper::{Body, Response, Server};
+use reverse_proxy::*;
+use shared_models::*;
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::net::TcpListener;
+
+/// Starts an upstream answering every request, counting the connections made to it
+async fn counting_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    let make_service = hyper::service::make_service_fn(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        async {
+            Ok::<_, Infallible>(hyper::service::service_fn(|_| async {
+                Ok::<_, Infallible>(Response::new(Body::from("upstream")))
+            }))
+        }
+    });
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, connections)
+}
+
+#[tokio::test]
+async fn reuses_upstream_connections() {
+    let (addr, connections) = counting_upstream().await;
+    let state = upstream_state(addr, "");
+
+    for _ in 0..3 {
+        let response = reverse_proxy(state.clone(), client(), get("/upstream/post"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        hyper::body::to_bytes(response.into_body()).await.unwrap();
+    }
+
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn slow_upstream_returns_gateway_timeout() {
+    // Accepts connections but never responds
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+    let state = upstream_state(addr, "request_timeout_ms = 100");
+
+    let response = tokio::time::timeout(
+        Duration::from_secs(5),
+        reverse_proxy(state, client(), get("/upstream/post")),
+    )
+    .await
+    .expect("The proxy should time out before the test does")
+    .unwrap();
+
+    assert_eq!(response.status(), 504);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: HttpErrResponseBody = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body.error_type, "UpstreamTimeout");
+}
+
+#[tokio::test]
+async fn unreachable_upstream_returns_json_bad_gateway() {
+    let state = upstream_state(closed_upstream().await, "");
+
+    let response = reverse_proxy(state, client(), get("/upstream/post"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 502);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: HttpErrResponseBody = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body.error_type, "ProxyRequestError");
+    // The connection error stays in the logs
+    assert_eq!(body.message, "Bad Gateway");
+}
+
+#[tokio::test]
+async fn unreachable_upstream_returns_html_to_browsers() {
+    let state = upstream_state(closed_upstream().await, "");
+    let mut request = get("/upstream/post");
+    request.headers_mut().insert(
+        "accept",
+        "text/html,application/xhtml+xml;q=0.9".parse().unwrap(),
+    );
+
+    let response = reverse_proxy(state, client(), request).await.unwrap();
+
+    assert_eq!(response.status(), 502);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/html; charset=utf-8"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(String::from_utf8(body.to_vec())
+        .unwrap()
+        .contains("502 Bad Gateway"));
+}
@@ -47,3 +47,133 @@ impl std::fmt::Display for ClientHttpResponseError {
 }
 
 impl std::error::Error for ClientHttpResponseError {}
+
+// HEALTH
+
+/// Build information reported by the health endpoints of a service
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo {
+    /// The version of the crate the service was built from
+    pub version: String,
+    /// The git commit the service was built from, provided through the `GIT_SHA` environment
+    /// variable at compile time
+    pub git_sha: String,
+}
+
+/// Creates the [BuildInfo] of the crate this macro is called from
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: option_env!("GIT_SHA").unwrap_or("unknown").to_string(),
+        }
+    };
+}
+
+/// Status of a service or one of its dependencies
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// Response body of the `/livez` endpoint of a service
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LivenessResponse {
+    pub status: HealthStatus,
+    pub build: BuildInfo,
+}
+
+impl LivenessResponse {
+    pub fn new(build: BuildInfo) -> Self {
+        Self {
+            status: HealthStatus::Up,
+            build,
+        }
+    }
+}
+
+/// The result of checking a single dependency of a service
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyCheck {
+    /// Name of the dependency, eg. `database` or `authService`
+    pub name: String,
+    pub status: HealthStatus,
+    /// How long the check took in milliseconds
+    pub latency_ms: u128,
+    /// Why the check failed, if it did
+    pub error: Option<String>,
+}
+
+impl DependencyCheck {
+    /// Creates a check from the result of pinging a dependency
+    ///
+    /// # Arguments
+    /// * `name` - The name of the dependency
+    /// * `started` - When the ping was started, used to calculate the latency
+    /// * `result` - The result of the ping
+    pub fn from_result<E>(name: &str, started: std::time::Instant, result: Result<(), E>) -> Self
+    where
+        E: std::fmt::Display,
+    {
+        let (status, error) = match result {
+            Ok(_) => (HealthStatus::Up, None),
+            Err(err) => (HealthStatus::Down, Some(err.to_string())),
+        };
+        Self {
+            name: name.to_string(),
+            status,
+            latency_ms: started.elapsed().as_millis(),
+            error,
+        }
+    }
+
+    /// A failing check reported while the service drains its requests before stopping, so no
+    /// new traffic is sent to it
+    pub fn draining() -> Self {
+        Self {
+            name: "shutdown".to_string(),
+            status: HealthStatus::Down,
+            latency_ms: 0,
+            error: Some("Shutting down".to_string()),
+        }
+    }
+}
+
+/// Response body of the `/readyz` endpoint of a service
+///
+/// The service is only ready when all of its dependencies are up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessResponse {
+    pub status: HealthStatus,
+    pub build: BuildInfo,
+    pub dependencies: Vec<DependencyCheck>,
+}
+
+impl ReadinessResponse {
+    pub fn new(build: BuildInfo, dependencies: Vec<DependencyCheck>) -> Self {
+        let status = if dependencies
+            .iter()
+            .all(|check| check.status == HealthStatus::Up)
+        {
+            HealthStatus::Up
+        } else {
+            HealthStatus::Down
+        };
+        Self {
+            status,
+            build,
+            dependencies,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.status == HealthStatus::Up
+    }
+}
@@ -0,0 +1,127 @@
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing_utils::tracing::{info, warn};
+
+/// Environment variable holding how long requests in flight get to finish once shutdown starts
+pub const SHUTDOWN_TIMEOUT_ENV: &str = "SHUTDOWN_TIMEOUT_SECS";
+
+/// Time requests in flight get to finish when `SHUTDOWN_TIMEOUT_SECS` isn't set
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether a service is shutting down, shared by its servers and its readiness check
+///
+/// Once shutdown starts the servers stop accepting connections, requests in flight get until the
+/// timeout to finish, and readiness reports the service as unavailable
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    draining: Arc<watch::Sender<bool>>,
+    timeout: Duration,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+}
+
+impl Shutdown {
+    pub fn new(timeout: Duration) -> Self {
+        let (draining, _) = watch::channel(false);
+        Self {
+            draining: Arc::new(draining),
+            timeout,
+        }
+    }
+
+    /// Starts shutting down on SIGTERM or SIGINT, draining for `SHUTDOWN_TIMEOUT_SECS`
+    ///
+    /// Must be called from within a tokio runtime
+    pub fn on_signals() -> Result<Self, std::io::Error> {
+        let shutdown = Self::new(timeout_from_env()?);
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+
+        let trigger = shutdown.clone();
+        tokio::spawn(async move {
+            let received = tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = interrupt.recv() => "SIGINT",
+            };
+            info!(
+                signal = received,
+                timeout_secs = trigger.timeout.as_secs(),
+                "Shutting down, draining requests in flight"
+            );
+            trigger.start();
+        });
+        Ok(shutdown)
+    }
+
+    /// Starts shutting down, servers stop accepting connections and readiness starts failing
+    pub fn start(&self) {
+        self.draining.send_replace(true);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// How long requests in flight get to finish once shutdown starts
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Resolves once shutdown starts, eg. to pass to `with_graceful_shutdown`
+    pub fn started(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut draining = self.draining.subscribe();
+        async move {
+            // The sender lives as long as any handle, so this only fails once none is left
+            let _ = draining.wait_for(|draining| *draining).await;
+        }
+    }
+
+    /// Runs a server until it has drained its connections, or until the timeout once shutdown
+    /// has started
+    ///
+    /// # Returns
+    /// The output of the server, or `None` when the timeout cut requests in flight short
+    pub async fn drain<F>(&self, server: F) -> Option<F::Output>
+    where
+        F: Future,
+    {
+        let deadline = {
+            let started = self.started();
+            let timeout = self.timeout;
+            async move {
+                started.await;
+                tokio::time::sleep(timeout).await;
+            }
+        };
+        tokio::select! {
+            output = server => Some(output),
+            _ = deadline => {
+                warn!(
+                    timeout_secs = self.timeout.as_secs(),
+                    "Shutdown timed out, closing the remaining connections"
+                );
+                None
+            }
+        }
+    }
+}
+
+/// The shutdown timeout from `SHUTDOWN_TIMEOUT_SECS`, the default when it is empty or not set
+pub fn timeout_from_env() -> Result<Duration, std::io::Error> {
+    match std::env::var(SHUTDOWN_TIMEOUT_ENV) {
+        Ok(secs) if !secs.is_empty() => secs.parse().map(Duration::from_secs).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} must be a number of seconds", SHUTDOWN_TIMEOUT_ENV),
+            )
+        }),
+        _ => Ok(DEFAULT_SHUTDOWN_TIMEOUT),
+    }
+}
@@ -0,0 +1,37 @@
+use crate::{RequestContext, REQUEST_ID_HEADER};
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use std::future::Future;
+
+/// Handles every request within a span carrying its request id, see [RequestContext]
+///
+/// Added with `App::wrap_fn(tracing_utils::actix::request_context)`
+pub fn request_context<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let context = RequestContext::new(
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok()),
+        req.method().as_str(),
+        req.path(),
+    );
+    let res = srv.call(req);
+    async move {
+        let mut res = context.run(res).await?;
+        context.completed(res.status().as_u16());
+        res.headers_mut().insert(
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderValue::from_str(context.request_id())
+                .expect("Request ids are valid header values"),
+        );
+        Ok(res)
+    }
+}
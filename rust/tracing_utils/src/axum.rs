@@ -0,0 +1,28 @@
+use crate::{RequestContext, REQUEST_ID_HEADER};
+use ::axum::{
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+
+/// Handles every request within a span carrying its request id, see [RequestContext]
+///
+/// Added with `axum::middleware::from_fn(tracing_utils::axum::request_context)`
+pub async fn request_context<B>(request: Request<B>, next: Next<B>) -> Response {
+    let context = RequestContext::new(
+        request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok()),
+        request.method().as_str(),
+        request.uri().path(),
+    );
+
+    let mut response = context.run(next.run(request)).await;
+    context.completed(response.status().as_u16());
+    response.headers_mut().insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(context.request_id()).expect("Request ids are valid header values"),
+    );
+    response
+}
@@ -0,0 +1,119 @@
+use std::{future::Future, time::Instant};
+use tracing::{info, info_span, Instrument, Span};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+pub use tracing;
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+
+/// Header used to correlate a request across the proxy and the services behind it
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from a client, longer ids are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Initialises JSON logging to stdout for a service
+///
+/// The log level is read from `RUST_LOG` and defaults to `info`
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(true)
+        .with_span_list(false)
+        .init();
+}
+
+/// Returns the request id from an incoming header value, or generates a new one
+///
+/// Header values that are empty, too long or contain anything other than visible ascii are
+/// replaced, as they end up in logs and outgoing headers
+pub fn request_id_or_new(header: Option<&str>) -> String {
+    match header {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.chars().all(|c| c.is_ascii_graphic()) =>
+        {
+            id.to_string()
+        }
+        _ => Uuid::new_v4().to_string(),
+    }
+}
+
+/// Runs the future with the request id set, so outgoing calls made by it can propagate the id
+pub async fn with_request_id<F>(request_id: String, future: F) -> F::Output
+where
+    F: Future,
+{
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// The request id of the request currently being handled, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// The request id and span of a request being handled by a service
+///
+/// Shared by the request context middleware of every web framework, see the `axum` and `actix`
+/// modules
+pub struct RequestContext {
+    request_id: String,
+    span: Span,
+    started: Instant,
+}
+
+impl RequestContext {
+    /// Starts handling a request, the `X-Request-Id` header set by the reverse proxy is reused if
+    /// present so calls made to other services while handling the request carry the same id
+    pub fn new(header: Option<&str>, method: &str, path: &str) -> Self {
+        let request_id = request_id_or_new(header);
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %method,
+            path = %path,
+        );
+        Self {
+            request_id,
+            span,
+            started: Instant::now(),
+        }
+    }
+
+    /// The request id, to send back in the `X-Request-Id` header of the response
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Runs the handler of the request within its span and with its request id set
+    pub async fn run<F>(&self, future: F) -> F::Output
+    where
+        F: Future,
+    {
+        with_request_id(self.request_id.clone(), future)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Logs the status and latency of the handled request
+    pub fn completed(&self, status: u16) {
+        self.span.in_scope(|| {
+            info!(
+                status,
+                latency_ms = self.started.elapsed().as_millis() as u64,
+                "Request completed"
+            )
+        });
+    }
+}